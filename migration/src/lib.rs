@@ -17,6 +17,22 @@ mod m20240826_165428_add_devices_storage;
 mod m20240831_010827_add_devices_locations;
 mod m20240831_053056_device_msg_queues;
 mod m20250706_165202_add_firehose_to_devices;
+mod m20261018_090000_upload_sessions;
+mod m20261018_100000_add_storage_quotas;
+mod m20261018_110000_storage_objects;
+mod m20261018_120000_crashes;
+mod m20261018_130000_engagement_stats;
+mod m20261018_140000_add_user_flags;
+mod m20261018_150000_add_route_places;
+mod m20261018_160000_clips;
+mod m20261018_170000_add_camera_durations;
+mod m20261018_180000_reprocess_jobs;
+mod m20261018_190000_worker_jobs;
+mod m20261018_200000_add_bootlog_details;
+mod m20261018_210000_offline_queue;
+mod m20261018_220000_athena_bus;
+mod m20261018_230000_cloudlogs;
+mod m20261018_233000_cloudlog_alert_rules;
 pub struct Migrator;
 
 #[async_trait::async_trait]
@@ -39,6 +55,22 @@ impl MigratorTrait for Migrator {
             Box::new(m20240831_010827_add_devices_locations::Migration),
             Box::new(m20240831_053056_device_msg_queues::Migration),
             Box::new(m20250706_165202_add_firehose_to_devices::Migration),
+            Box::new(m20261018_090000_upload_sessions::Migration),
+            Box::new(m20261018_100000_add_storage_quotas::Migration),
+            Box::new(m20261018_110000_storage_objects::Migration),
+            Box::new(m20261018_120000_crashes::Migration),
+            Box::new(m20261018_130000_engagement_stats::Migration),
+            Box::new(m20261018_140000_add_user_flags::Migration),
+            Box::new(m20261018_150000_add_route_places::Migration),
+            Box::new(m20261018_160000_clips::Migration),
+            Box::new(m20261018_170000_add_camera_durations::Migration),
+            Box::new(m20261018_180000_reprocess_jobs::Migration),
+            Box::new(m20261018_190000_worker_jobs::Migration),
+            Box::new(m20261018_200000_add_bootlog_details::Migration),
+            Box::new(m20261018_210000_offline_queue::Migration),
+            Box::new(m20261018_220000_athena_bus::Migration),
+            Box::new(m20261018_230000_cloudlogs::Migration),
+            Box::new(m20261018_233000_cloudlog_alert_rules::Migration),
         ]
     }
 }
\ No newline at end of file
@@ -0,0 +1,63 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                table_auto(UploadSessions::Table)
+                    .col(pk_auto(UploadSessions::Id))
+                    .col(uuid_uniq(UploadSessions::SessionId))
+                    .col(string(UploadSessions::DongleId))
+                    .col(string_uniq(UploadSessions::FileKey))
+                    .col(string(UploadSessions::Timestamp))
+                    .col(string(UploadSessions::Segment))
+                    .col(string(UploadSessions::File))
+                    .col(big_integer_null(UploadSessions::TotalSize))
+                    .col(big_integer(UploadSessions::CommittedOffset))
+                    .col(integer(UploadSessions::PartCount))
+                    .col(timestamp(UploadSessions::ExpiresAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-upload_sessions-devices")
+                            .from(UploadSessions::Table, UploadSessions::DongleId)
+                            .to(Devices::Table, Devices::DongleId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UploadSessions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UploadSessions {
+    Table,
+    Id,
+    SessionId,
+    DongleId,
+    FileKey,
+    Timestamp,
+    Segment,
+    File,
+    TotalSize,
+    CommittedOffset,
+    PartCount,
+    ExpiresAt,
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    DongleId,
+}
@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    StorageQuota,
+    LogQuota,
+    CameraQuota,
+    LogStorage,
+    CameraStorage,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    StorageQuota,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Quotas are overrides in bytes, NULL falls back to the server wide default
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Devices::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Devices::StorageQuota).big_integer().null())
+                    .add_column_if_not_exists(ColumnDef::new(Devices::LogQuota).big_integer().null())
+                    .add_column_if_not_exists(ColumnDef::new(Devices::CameraQuota).big_integer().null())
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Devices::LogStorage)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Devices::CameraStorage)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Users::StorageQuota).big_integer().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::StorageQuota)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Devices::Table)
+                    .drop_column(Devices::StorageQuota)
+                    .drop_column(Devices::LogQuota)
+                    .drop_column(Devices::CameraQuota)
+                    .drop_column(Devices::LogStorage)
+                    .drop_column(Devices::CameraStorage)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,59 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                table_auto(StorageObjects::Table)
+                    .col(pk_auto(StorageObjects::Id))
+                    .col(string_uniq(StorageObjects::Key))
+                    .col(string(StorageObjects::DongleId))
+                    .col(big_integer(StorageObjects::Size))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-storage_objects-devices")
+                            .from(StorageObjects::Table, StorageObjects::DongleId)
+                            .to(Devices::Table, Devices::DongleId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-storage_objects-dongle_id")
+                    .table(StorageObjects::Table)
+                    .col(StorageObjects::DongleId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(StorageObjects::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum StorageObjects {
+    Table,
+    Id,
+    Key,
+    DongleId,
+    Size,
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    DongleId,
+}
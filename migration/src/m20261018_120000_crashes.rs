@@ -0,0 +1,119 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                table_auto(CrashGroups::Table)
+                    .col(pk_auto(CrashGroups::Id))
+                    .col(string_uniq(CrashGroups::Signature))
+                    .col(string(CrashGroups::ExceptionType))
+                    .col(string_null(CrashGroups::File))
+                    .col(integer_null(CrashGroups::Line))
+                    .col(string_null(CrashGroups::Function))
+                    .col(timestamp(CrashGroups::FirstSeen))
+                    .col(timestamp(CrashGroups::LastSeen))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                table_auto(Crashes::Table)
+                    .col(pk_auto(Crashes::Id))
+                    .col(string(Crashes::DongleId))
+                    .col(integer(Crashes::CrashGroupId))
+                    .col(string_uniq(Crashes::FileKey))
+                    .col(string(Crashes::LogId))
+                    .col(string(Crashes::Commit))
+                    .col(string_null(Crashes::Branch))
+                    .col(string(Crashes::Name))
+                    .col(string(Crashes::ExceptionType))
+                    .col(text(Crashes::Message))
+                    .col(text(Crashes::StackTrace))
+                    .col(string_null(Crashes::File))
+                    .col(integer_null(Crashes::Line))
+                    .col(string_null(Crashes::Function))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-crashes-devices")
+                            .from(Crashes::Table, Crashes::DongleId)
+                            .to(Devices::Table, Devices::DongleId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-crashes-crash_groups")
+                            .from(Crashes::Table, Crashes::CrashGroupId)
+                            .to(CrashGroups::Table, CrashGroups::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-crashes-crash_group_id-commit")
+                    .table(Crashes::Table)
+                    .col(Crashes::CrashGroupId)
+                    .col(Crashes::Commit)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Crashes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(CrashGroups::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum CrashGroups {
+    Table,
+    Id,
+    Signature,
+    ExceptionType,
+    File,
+    Line,
+    Function,
+    FirstSeen,
+    LastSeen,
+}
+
+#[derive(DeriveIden)]
+enum Crashes {
+    Table,
+    Id,
+    DongleId,
+    CrashGroupId,
+    FileKey,
+    LogId,
+    Commit,
+    Branch,
+    Name,
+    ExceptionType,
+    Message,
+    StackTrace,
+    File,
+    Line,
+    Function,
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    DongleId,
+}
@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Segments {
+    Table,
+}
+
+#[derive(DeriveIden)]
+enum Routes {
+    Table,
+}
+
+#[derive(DeriveIden)]
+enum Engagement {
+    EngagedMillis,
+    EngagedMiles,
+    Disengagements,
+    Overrides,
+}
+
+fn add_engagement_columns(table: impl IntoIden) -> TableAlterStatement {
+    Table::alter()
+        .table(table)
+        .add_column_if_not_exists(
+            ColumnDef::new(Engagement::EngagedMillis)
+                .big_integer()
+                .not_null()
+                .default(0),
+        )
+        .add_column_if_not_exists(
+            ColumnDef::new(Engagement::EngagedMiles)
+                .float()
+                .not_null()
+                .default(0.0),
+        )
+        .add_column_if_not_exists(
+            ColumnDef::new(Engagement::Disengagements)
+                .integer()
+                .not_null()
+                .default(0),
+        )
+        .add_column_if_not_exists(
+            ColumnDef::new(Engagement::Overrides)
+                .integer()
+                .not_null()
+                .default(0),
+        )
+        .to_owned()
+}
+
+fn drop_engagement_columns(table: impl IntoIden) -> TableAlterStatement {
+    Table::alter()
+        .table(table)
+        .drop_column(Engagement::EngagedMillis)
+        .drop_column(Engagement::EngagedMiles)
+        .drop_column(Engagement::Disengagements)
+        .drop_column(Engagement::Overrides)
+        .to_owned()
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(add_engagement_columns(Segments::Table)).await?;
+        manager.alter_table(add_engagement_columns(Routes::Table)).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(drop_engagement_columns(Routes::Table)).await?;
+        manager.alter_table(drop_engagement_columns(Segments::Table)).await
+    }
+}
@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Segments {
+    Table,
+}
+
+#[derive(DeriveIden)]
+enum Routes {
+    Table,
+    HasUserFlag,
+}
+
+fn add_user_flag_column(table: impl IntoIden) -> TableAlterStatement {
+    Table::alter()
+        .table(table)
+        .add_column_if_not_exists(
+            ColumnDef::new(Routes::HasUserFlag)
+                .boolean()
+                .not_null()
+                .default(false),
+        )
+        .to_owned()
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(add_user_flag_column(Segments::Table)).await?;
+        manager.alter_table(add_user_flag_column(Routes::Table)).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Routes::Table).drop_column(Routes::HasUserFlag).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Segments::Table).drop_column(Routes::HasUserFlag).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Routes {
+    Table,
+    StartPlace,
+    EndPlace,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Routes::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Routes::StartPlace).string().null())
+                    .add_column_if_not_exists(ColumnDef::new(Routes::EndPlace).string().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Routes::Table)
+                    .drop_column(Routes::StartPlace)
+                    .drop_column(Routes::EndPlace)
+                    .to_owned(),
+            )
+            .await
+    }
+}
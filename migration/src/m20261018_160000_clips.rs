@@ -0,0 +1,73 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                table_auto(Clips::Table)
+                    .col(pk_auto(Clips::Id))
+                    .col(string(Clips::RouteFullname))
+                    .col(string(Clips::DongleId))
+                    .col(string(Clips::Camera))
+                    .col(big_integer(Clips::StartMillis))
+                    .col(big_integer(Clips::EndMillis))
+                    .col(string(Clips::Status))
+                    .col(float(Clips::Progress).default(0.0))
+                    .col(string_null(Clips::StorageKey))
+                    .col(big_integer_null(Clips::Size))
+                    .col(text_null(Clips::Error))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-clips-routes")
+                            .from(Clips::Table, Clips::RouteFullname)
+                            .to(Routes::Table, Routes::Fullname)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-clips-dongle_id")
+                    .table(Clips::Table)
+                    .col(Clips::DongleId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Clips::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Clips {
+    Table,
+    Id,
+    RouteFullname,
+    DongleId,
+    Camera,
+    StartMillis,
+    EndMillis,
+    Status,
+    Progress,
+    StorageKey,
+    Size,
+    Error,
+}
+
+#[derive(DeriveIden)]
+enum Routes {
+    Table,
+    Fullname,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Segments {
+    Table,
+    FcamDuration,
+    EcamDuration,
+    DcamDuration,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Segments::Table)
+                    .add_column_if_not_exists(float(Segments::FcamDuration).default(0.0))
+                    .add_column_if_not_exists(float(Segments::EcamDuration).default(0.0))
+                    .add_column_if_not_exists(float(Segments::DcamDuration).default(0.0))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Segments::Table)
+                    .drop_column(Segments::FcamDuration)
+                    .drop_column(Segments::EcamDuration)
+                    .drop_column(Segments::DcamDuration)
+                    .to_owned(),
+            )
+            .await
+    }
+}
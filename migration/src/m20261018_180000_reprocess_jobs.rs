@@ -0,0 +1,82 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Segments::Table)
+                    .add_column_if_not_exists(integer(Segments::ParserVersion).default(0))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-segments-parser_version")
+                    .table(Segments::Table)
+                    .col(Segments::ParserVersion)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                table_auto(ReprocessJobs::Table)
+                    .col(pk_auto(ReprocessJobs::Id))
+                    .col(string_null(ReprocessJobs::DongleId))
+                    .col(string_null(ReprocessJobs::RouteFullname))
+                    .col(string_null(ReprocessJobs::SegmentName))
+                    .col(integer_null(ReprocessJobs::BelowVersion))
+                    .col(string(ReprocessJobs::Status))
+                    .col(integer(ReprocessJobs::Total).default(0))
+                    .col(integer(ReprocessJobs::Processed).default(0))
+                    .col(integer(ReprocessJobs::Failed).default(0))
+                    .col(integer(ReprocessJobs::Claimed).default(0))
+                    .col(string_null(ReprocessJobs::Cursor))
+                    .col(text_null(ReprocessJobs::Error))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ReprocessJobs::Table).to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("idx-segments-parser_version").table(Segments::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Segments::Table).drop_column(Segments::ParserVersion).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Segments {
+    Table,
+    ParserVersion,
+}
+
+#[derive(DeriveIden)]
+enum ReprocessJobs {
+    Table,
+    Id,
+    DongleId,
+    RouteFullname,
+    SegmentName,
+    BelowVersion,
+    Status,
+    Total,
+    Processed,
+    Failed,
+    Claimed,
+    Cursor,
+    Error,
+}
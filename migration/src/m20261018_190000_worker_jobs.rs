@@ -0,0 +1,68 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                table_auto(WorkerJobs::Table)
+                    .col(pk_auto(WorkerJobs::Id))
+                    .col(string(WorkerJobs::Worker))
+                    .col(string(WorkerJobs::FileKey))
+                    .col(string(WorkerJobs::DongleId))
+                    .col(text(WorkerJobs::Args))
+                    .col(string(WorkerJobs::Status))
+                    .col(integer(WorkerJobs::Attempts).default(0))
+                    .col(big_integer(WorkerJobs::DurationMillis).default(0))
+                    .col(text_null(WorkerJobs::LastError))
+                    .to_owned(),
+            )
+            .await?;
+
+        // One record per file and worker, a retry updates it
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-worker_jobs-worker-file_key")
+                    .table(WorkerJobs::Table)
+                    .col(WorkerJobs::Worker)
+                    .col(WorkerJobs::FileKey)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-worker_jobs-status")
+                    .table(WorkerJobs::Table)
+                    .col(WorkerJobs::Status)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WorkerJobs::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WorkerJobs {
+    Table,
+    Id,
+    Worker,
+    FileKey,
+    DongleId,
+    Args,
+    Status,
+    Attempts,
+    DurationMillis,
+    LastError,
+}
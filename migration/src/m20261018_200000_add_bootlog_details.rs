@@ -0,0 +1,83 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Bootlogs {
+    Table,
+    DongleId,
+    BootTime,
+    KernelVersion,
+    AgnosVersion,
+    OpenpilotVersion,
+    GitCommit,
+    LaunchLog,
+    ManagerInit,
+    PandaVersions,
+    Errors,
+    ErrorCount,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Bootlogs::Table)
+                    .add_column_if_not_exists(big_integer(Bootlogs::BootTime).default(0))
+                    .add_column_if_not_exists(string(Bootlogs::KernelVersion).default(""))
+                    .add_column_if_not_exists(string(Bootlogs::AgnosVersion).default(""))
+                    .add_column_if_not_exists(string(Bootlogs::OpenpilotVersion).default(""))
+                    .add_column_if_not_exists(string(Bootlogs::GitCommit).default(""))
+                    .add_column_if_not_exists(text(Bootlogs::LaunchLog).default(""))
+                    .add_column_if_not_exists(text(Bootlogs::ManagerInit).default(""))
+                    .add_column_if_not_exists(text(Bootlogs::PandaVersions).default(""))
+                    .add_column_if_not_exists(text(Bootlogs::Errors).default(""))
+                    .add_column_if_not_exists(integer(Bootlogs::ErrorCount).default(0))
+                    .to_owned(),
+            )
+            .await?;
+
+        // The boot history of a device, newest first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-bootlogs-dongle_id-boot_time")
+                    .table(Bootlogs::Table)
+                    .col(Bootlogs::DongleId)
+                    .col(Bootlogs::BootTime)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-bootlogs-dongle_id-boot_time")
+                    .table(Bootlogs::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Bootlogs::Table)
+                    .drop_column(Bootlogs::BootTime)
+                    .drop_column(Bootlogs::KernelVersion)
+                    .drop_column(Bootlogs::AgnosVersion)
+                    .drop_column(Bootlogs::OpenpilotVersion)
+                    .drop_column(Bootlogs::GitCommit)
+                    .drop_column(Bootlogs::LaunchLog)
+                    .drop_column(Bootlogs::ManagerInit)
+                    .drop_column(Bootlogs::PandaVersions)
+                    .drop_column(Bootlogs::Errors)
+                    .drop_column(Bootlogs::ErrorCount)
+                    .to_owned(),
+            )
+            .await
+    }
+}
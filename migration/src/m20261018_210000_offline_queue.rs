@@ -0,0 +1,89 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum DeviceMsgQueues {
+    Table,
+    DongleId,
+    Method,
+    Status,
+    RpcId,
+    Attempts,
+    MaxAttempts,
+    ExpiresAt,
+    SentAt,
+    Result,
+    Error,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Rows already queued become plain queued messages that never expire
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(DeviceMsgQueues::Table)
+                    .add_column_if_not_exists(string(DeviceMsgQueues::Method).default(""))
+                    .add_column_if_not_exists(string(DeviceMsgQueues::Status).default("queued"))
+                    .add_column_if_not_exists(string(DeviceMsgQueues::RpcId).default(""))
+                    .add_column_if_not_exists(integer(DeviceMsgQueues::Attempts).default(0))
+                    .add_column_if_not_exists(integer(DeviceMsgQueues::MaxAttempts).default(3))
+                    .add_column_if_not_exists(big_integer(DeviceMsgQueues::ExpiresAt).default(0))
+                    .add_column_if_not_exists(big_integer(DeviceMsgQueues::SentAt).default(0))
+                    .add_column_if_not_exists(json_null(DeviceMsgQueues::Result))
+                    .add_column_if_not_exists(text_null(DeviceMsgQueues::Error))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-device_msg_queues-dongle_id-status")
+                    .table(DeviceMsgQueues::Table)
+                    .col(DeviceMsgQueues::DongleId)
+                    .col(DeviceMsgQueues::Status)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Responses from the device are matched on the JSON-RPC id
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-device_msg_queues-rpc_id")
+                    .table(DeviceMsgQueues::Table)
+                    .col(DeviceMsgQueues::RpcId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx-device_msg_queues-rpc_id").table(DeviceMsgQueues::Table).to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("idx-device_msg_queues-dongle_id-status").table(DeviceMsgQueues::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(DeviceMsgQueues::Table)
+                    .drop_column(DeviceMsgQueues::Method)
+                    .drop_column(DeviceMsgQueues::Status)
+                    .drop_column(DeviceMsgQueues::RpcId)
+                    .drop_column(DeviceMsgQueues::Attempts)
+                    .drop_column(DeviceMsgQueues::MaxAttempts)
+                    .drop_column(DeviceMsgQueues::ExpiresAt)
+                    .drop_column(DeviceMsgQueues::SentAt)
+                    .drop_column(DeviceMsgQueues::Result)
+                    .drop_column(DeviceMsgQueues::Error)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,59 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Which server instance holds each device's athena websocket
+        manager
+            .create_table(
+                table_auto(AthenaConnections::Table)
+                    .col(pk_auto(AthenaConnections::Id))
+                    .col(string_uniq(AthenaConnections::DongleId))
+                    .col(string(AthenaConnections::NodeId))
+                    .col(string(AthenaConnections::ConnectionId))
+                    .to_owned(),
+            )
+            .await?;
+
+        // JSON-RPC traffic between instances. Only the row id goes through NOTIFY, payloads are
+        // often bigger than it allows.
+        manager
+            .create_table(
+                table_auto(AthenaBusMessages::Table)
+                    .col(pk_auto(AthenaBusMessages::Id))
+                    .col(string(AthenaBusMessages::NodeId))
+                    .col(text(AthenaBusMessages::Payload))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AthenaBusMessages::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(AthenaConnections::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AthenaConnections {
+    Table,
+    Id,
+    DongleId,
+    NodeId,
+    ConnectionId,
+}
+
+#[derive(DeriveIden)]
+enum AthenaBusMessages {
+    Table,
+    Id,
+    NodeId,
+    Payload,
+}
@@ -0,0 +1,81 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEXES: [(&str, Cloudlogs); 5] = [
+    ("idx-cloudlogs-dongle_id-log_time", Cloudlogs::DongleId),
+    ("idx-cloudlogs-branch-log_time", Cloudlogs::Branch),
+    ("idx-cloudlogs-commit-log_time", Cloudlogs::Commit),
+    ("idx-cloudlogs-level-log_time", Cloudlogs::Level),
+    ("idx-cloudlogs-module-log_time", Cloudlogs::Module),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Cloudlog lines from forwardLogs. The fields searched on are copied out of the line,
+        // the line itself is kept as is.
+        manager
+            .create_table(
+                table_auto(Cloudlogs::Table)
+                    .col(pk_auto(Cloudlogs::Id))
+                    .col(string(Cloudlogs::DongleId))
+                    .col(string(Cloudlogs::Branch))
+                    .col(string(Cloudlogs::Commit))
+                    .col(string(Cloudlogs::Module))
+                    .col(string(Cloudlogs::FuncName))
+                    .col(string(Cloudlogs::Level))
+                    .col(integer(Cloudlogs::Levelnum))
+                    .col(big_integer(Cloudlogs::LogTime))
+                    .col(json(Cloudlogs::Log))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Retention deletes by time alone
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-cloudlogs-log_time")
+                    .table(Cloudlogs::Table)
+                    .col(Cloudlogs::LogTime)
+                    .to_owned(),
+            )
+            .await?;
+        for (name, column) in INDEXES {
+            manager
+                .create_index(
+                    Index::create()
+                        .name(name)
+                        .table(Cloudlogs::Table)
+                        .col(column)
+                        .col(Cloudlogs::LogTime)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Cloudlogs::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Cloudlogs {
+    Table,
+    Id,
+    DongleId,
+    Branch,
+    Commit,
+    Module,
+    FuncName,
+    Level,
+    Levelnum,
+    LogTime,
+    Log,
+}
@@ -0,0 +1,60 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Rules over incoming cloudlogs. Empty match fields match anything.
+        manager
+            .create_table(
+                table_auto(CloudlogAlertRules::Table)
+                    .col(pk_auto(CloudlogAlertRules::Id))
+                    .col(string(CloudlogAlertRules::Name))
+                    .col(boolean(CloudlogAlertRules::Enabled).default(true))
+                    .col(string_null(CloudlogAlertRules::Level))
+                    .col(integer_null(CloudlogAlertRules::MinLevelnum))
+                    .col(string_null(CloudlogAlertRules::Module))
+                    .col(string_null(CloudlogAlertRules::FuncName))
+                    .col(string_null(CloudlogAlertRules::MsgRegex))
+                    .col(string_null(CloudlogAlertRules::Branch))
+                    .col(string_null(CloudlogAlertRules::Commit))
+                    .col(integer(CloudlogAlertRules::Threshold))
+                    .col(integer(CloudlogAlertRules::WindowSecs))
+                    .col(integer(CloudlogAlertRules::CooldownSecs).default(3600))
+                    .col(string(CloudlogAlertRules::WebhookUrl))
+                    .col(big_integer(CloudlogAlertRules::LastFiredAt).default(0))
+                    .col(text_null(CloudlogAlertRules::LastError))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CloudlogAlertRules::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum CloudlogAlertRules {
+    Table,
+    Id,
+    Name,
+    Enabled,
+    Level,
+    MinLevelnum,
+    Module,
+    FuncName,
+    MsgRegex,
+    Branch,
+    Commit,
+    Threshold,
+    WindowSecs,
+    CooldownSecs,
+    WebhookUrl,
+    LastFiredAt,
+    LastError,
+}
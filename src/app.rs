@@ -30,8 +30,10 @@ use crate::{
     controllers,
     initializers,
     controllers::ws::ConnectionManager, 
+    common::athena_bus::{self, BusBackend},
     models::_entities::{devices, users},
-    workers::log_helpers::{persist_param_value_counts, persist_device_params}
+    workers::log_helpers::{persist_param_value_counts, persist_device_params},
+    workers::reprocess::{ReprocessWorker, ReprocessWorkerArgs},
 };
 
 pub struct App {}
@@ -75,6 +77,12 @@ impl Hooks for App {
             .add_route(controllers::maps::routes())
             .add_route(controllers::params::routes())
             .add_route(controllers::stats::routes())
+            .add_route(controllers::crashes::routes())
+            .add_route(controllers::clips::routes())
+            .add_route(controllers::reprocess::routes())
+            .add_route(controllers::worker_jobs::routes())
+            .add_route(controllers::offline_queue::routes())
+            .add_route(controllers::cloudlog_alerts::routes())
     }
 
     fn connect_workers<'a>(p: &'a mut Processor, ctx: &'a AppContext) {
@@ -82,8 +90,12 @@ impl Hooks for App {
             return;
         }
         p.register(crate::workers::bootlog_parser::BootlogParserWorker::build(ctx));
-        p.register(crate::workers::jpg_extractor::JpgExtractorWorker::build(ctx));
+        p.register(crate::workers::crash_parser::CrashParserWorker::build(ctx));
+        p.register(crate::workers::clip_renderer::ClipRendererWorker::build(ctx));
+        p.register(crate::workers::camera_remuxer::CameraRemuxWorker::build(ctx));
         p.register(crate::workers::log_parser::LogSegmentWorker::build(ctx));
+        p.register(crate::workers::rlog_parser::RlogParserWorker::build(ctx));
+        p.register(crate::workers::reprocess::ReprocessWorker::build(ctx));
     }
 
     fn register_tasks(tasks: &mut Tasks) {
@@ -91,6 +103,7 @@ impl Hooks for App {
         tasks.register(tasks::huggingface::Huggingface);
         tasks.register(tasks::deleter::Deleter);
         tasks.register(tasks::cleaner::Cleaner);
+        tasks.register(tasks::geocode_routes::GeocodeRoutes);
         tasks.register(tasks::collect_data::CollectData);
         tasks.register(tasks::seed_from_mkv::SeedFromMkv);
         tasks.register(tasks::seed::SeedData);
@@ -125,12 +138,27 @@ impl Hooks for App {
             return Ok(router);
         }
 
-        match devices::Model::reset_online(&ctx.db).await {
-            Ok(_) => tracing::info!("Reset all devices to offline"),
-            Err(e) => tracing::error!("Failed to Reset all devices to offline: {e}"),
-        };
-
-        let connection_manager: Arc<ConnectionManager> = ConnectionManager::new();
+        // Devices connected to other instances are reached over the athena bus
+        let bus = BusBackend::from_env(&ctx.db);
+        let connection_manager: Arc<ConnectionManager> = ConnectionManager::new(bus);
+        if bus == BusBackend::Postgres {
+            // The other instances keep their devices online
+            match athena_bus::reset_online(&ctx.db, &connection_manager.node_id).await {
+                Ok(_) => tracing::info!("Reset devices without a connection to offline"),
+                Err(e) => tracing::error!("Failed to Reset devices without a connection to offline: {e}"),
+            };
+            tokio::spawn(crate::controllers::ws::run_bus(
+                ctx.db.clone(),
+                ctx.config.database.uri.clone(),
+                connection_manager.clone(),
+            ));
+        } else {
+            match devices::Model::reset_online(&ctx.db).await {
+                Ok(_) => tracing::info!("Reset all devices to offline"),
+                Err(e) => tracing::error!("Failed to Reset all devices to offline: {e}"),
+            };
+        }
+        tokio::spawn(crate::controllers::ws::run_cloudlog_feed(ctx.db.clone(), connection_manager.clone()));
         let ping_manager: Arc<ConnectionManager> = connection_manager.clone();
         let db_clone: DatabaseConnection = ctx.db.clone();
         tokio::spawn(async move {
@@ -154,6 +182,38 @@ impl Hooks for App {
             }
         });
 
+        tokio::spawn({
+            let db = ctx.db.clone();
+            async move {
+                let mut interval = time::interval(time::Duration::from_secs(60 * 10)); // Sweep every 10 minutes
+                loop {
+                    interval.tick().await;
+                    crate::controllers::connectincomming::gc_expired_upload_sessions(&db).await;
+                }
+            }
+        });
+
+        tokio::spawn({
+            let db = ctx.db.clone();
+            async move {
+                let mut interval = time::interval(time::Duration::from_secs(60 * 60)); // Prune every hour
+                loop {
+                    interval.tick().await;
+                    crate::controllers::connectdata::prune_cloudlogs(&db).await;
+                }
+            }
+        });
+
+        // Picks up unfinished reprocess jobs and a new parser version
+        tokio::spawn({
+            let ctx = ctx.clone();
+            async move {
+                if let Err(e) = ReprocessWorker::perform_later(&ctx, ReprocessWorkerArgs::default()).await {
+                    tracing::error!("Failed to queue reprocessing: {e}");
+                }
+            }
+        });
+
         //let client = Client::new();
         let client = Client::builder()
             .pool_max_idle_per_host(500)
@@ -0,0 +1,263 @@
+//! Routes athena JSON-RPC between server instances. A device's websocket lives on whichever
+//! instance it connected to, so each instance records its devices in `athena_connections` and
+//! the others forward calls for those devices to it over Postgres LISTEN/NOTIFY.
+//!
+//! Each instance listens on its own channel. A message is written to `athena_bus_messages` and
+//! only its id is sent through NOTIFY, the receiver takes the row out again.
+use std::{env, time::Duration};
+
+use sea_orm::{sqlx::postgres::PgListener, ConnectionTrait, DatabaseConnection, DbBackend, DbErr, Statement};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// A connection whose instance hasn't checked in for this long is gone.
+const CONNECTION_TTL_SECS: i64 = 60;
+/// Messages nobody picked up, e.g. because their instance went away, are dropped after this.
+const MESSAGE_TTL_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusBackend {
+    Postgres,
+    /// Single instance, every device is local
+    Local,
+}
+
+impl BusBackend {
+    /// `ATHENA_BUS` can be `postgres`, `local` or `auto` (the default), which uses postgres
+    /// whenever the database is postgres. The bus needs postgres, without it this is local.
+    pub fn from_env(db: &DatabaseConnection) -> Self {
+        let is_postgres = db.get_database_backend() == DbBackend::Postgres;
+        match env::var("ATHENA_BUS").unwrap_or_default().to_lowercase().as_str() {
+            "postgres" if !is_postgres => {
+                tracing::warn!("ATHENA_BUS=postgres needs a postgres database, running the athena bus locally");
+                BusBackend::Local
+            }
+            "postgres" => BusBackend::Postgres,
+            "local" => BusBackend::Local,
+            _ if is_postgres => BusBackend::Postgres,
+            _ => BusBackend::Local,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BusMessage {
+    /// A JSON-RPC call for a device on the receiving instance. The answer goes to `reply_to`.
+    Request {
+        dongle_id: String,
+        reply_to: String,
+        request: String,
+    },
+    /// A device's answer to a forwarded call
+    Response { response: String },
+}
+
+/// `ATHENA_NODE_ID`, or a random one. It names the instance's NOTIFY channel, so only letters,
+/// digits and underscores are kept.
+pub fn node_id() -> String {
+    let node_id: String = env::var("ATHENA_NODE_ID")
+        .unwrap_or_default()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect();
+    if node_id.is_empty() {
+        uuid::Uuid::new_v4().simple().to_string()
+    } else {
+        node_id
+    }
+}
+
+fn channel(node_id: &str) -> String {
+    format!("athena_{node_id}")
+}
+
+/// Claim a device for this instance. A reconnect to another instance takes it over.
+pub async fn register_device(
+    db: &DatabaseConnection,
+    node_id: &str,
+    dongle_id: &str,
+    connection_id: &str,
+) -> Result<(), DbErr> {
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "INSERT INTO athena_connections (dongle_id, node_id, connection_id) VALUES ($1, $2, $3) \
+         ON CONFLICT (dongle_id) DO UPDATE SET node_id = $2, connection_id = $3, updated_at = now()",
+        [dongle_id.into(), node_id.into(), connection_id.into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Only removes the connection it was registered with, a newer one may have replaced it.
+pub async fn unregister_device(db: &DatabaseConnection, dongle_id: &str, connection_id: &str) -> Result<(), DbErr> {
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "DELETE FROM athena_connections WHERE dongle_id = $1 AND connection_id = $2",
+        [dongle_id.into(), connection_id.into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Keeps this instance's connections alive and drops what's expired.
+pub async fn heartbeat(db: &DatabaseConnection, node_id: &str) -> Result<(), DbErr> {
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "UPDATE athena_connections SET updated_at = now() WHERE node_id = $1",
+        [node_id.into()],
+    ))
+    .await?;
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "DELETE FROM athena_connections WHERE updated_at < now() - make_interval(secs => $1)",
+        [(CONNECTION_TTL_SECS as f64).into()],
+    ))
+    .await?;
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "DELETE FROM athena_bus_messages WHERE created_at < now() - make_interval(secs => $1)",
+        [(MESSAGE_TTL_SECS as f64).into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Marks the devices offline that no other instance holds a websocket for, on start. The
+/// connections left from an earlier run of this node are dropped first.
+pub async fn reset_online(db: &DatabaseConnection, node_id: &str) -> Result<(), DbErr> {
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "DELETE FROM athena_connections WHERE node_id = $1",
+        [node_id.into()],
+    ))
+    .await?;
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "UPDATE devices SET online = false WHERE online AND dongle_id NOT IN \
+         (SELECT dongle_id FROM athena_connections WHERE updated_at > now() - make_interval(secs => $1))",
+        [(CONNECTION_TTL_SECS as f64).into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// The instance holding the device's websocket, if it's connected anywhere.
+pub async fn find_device_node(db: &DatabaseConnection, dongle_id: &str) -> Result<Option<String>, DbErr> {
+    let row = db
+        .query_one(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "SELECT node_id FROM athena_connections \
+             WHERE dongle_id = $1 AND updated_at > now() - make_interval(secs => $2)",
+            [dongle_id.into(), (CONNECTION_TTL_SECS as f64).into()],
+        ))
+        .await?;
+    row.map(|row| row.try_get::<String>("", "node_id")).transpose()
+}
+
+pub async fn send(db: &DatabaseConnection, node_id: &str, message: &BusMessage) -> Result<(), DbErr> {
+    let payload = serde_json::to_string(message).map_err(|e| DbErr::Custom(e.to_string()))?;
+    let row = db
+        .query_one(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "INSERT INTO athena_bus_messages (node_id, payload) VALUES ($1, $2) RETURNING id",
+            [node_id.into(), payload.into()],
+        ))
+        .await?
+        .ok_or_else(|| DbErr::Custom("no id for the bus message".to_string()))?;
+    let id: i32 = row.try_get("", "id")?;
+    db.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "SELECT pg_notify($1, $2)",
+        [channel(node_id).into(), id.to_string().into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Messages sent to this instance. The listener reconnects by itself if its connection drops.
+///
+/// The listener keeps its connection for as long as the instance runs, so it opens its own from
+/// `database_url` instead of taking one out of the pool.
+pub async fn subscribe(
+    db: &DatabaseConnection,
+    database_url: &str,
+    node_id: &str,
+) -> Result<mpsc::Receiver<BusMessage>, DbErr> {
+    let mut listener = PgListener::connect(database_url)
+        .await
+        .map_err(|e| DbErr::Custom(e.to_string()))?;
+    listener
+        .listen(&channel(node_id))
+        .await
+        .map_err(|e| DbErr::Custom(e.to_string()))?;
+    let (tx, rx) = mpsc::channel(256);
+    let db = db.clone();
+    tokio::spawn(async move {
+        loop {
+            let notification = match listener.recv().await {
+                Ok(notification) => notification,
+                Err(e) => {
+                    tracing::error!("Athena bus listener failed: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let Ok(id) = notification.payload().parse::<i32>() else {
+                tracing::warn!("Ignoring athena bus notification {}", notification.payload());
+                continue;
+            };
+            match take_message(&db, id).await {
+                Ok(Some(message)) => {
+                    if tx.send(message).await.is_err() {
+                        return; // nothing is reading anymore
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => tracing::error!("Failed to read athena bus message {id}: {e}"),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+async fn take_message(db: &DatabaseConnection, id: i32) -> Result<Option<BusMessage>, DbErr> {
+    let Some(row) = db
+        .query_one(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "DELETE FROM athena_bus_messages WHERE id = $1 RETURNING payload",
+            [id.into()],
+        ))
+        .await?
+    else {
+        return Ok(None);
+    };
+    let payload: String = row.try_get("", "payload")?;
+    serde_json::from_str(&payload)
+        .map(Some)
+        .map_err(|e| DbErr::Custom(format!("bad athena bus message: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_messages_round_trip() {
+        let message = BusMessage::Request {
+            dongle_id: "0123456789abcdef".to_string(),
+            reply_to: "node_a".to_string(),
+            request: r#"{"method":"getVersion","jsonrpc":"2.0","id":1}"#.to_string(),
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        assert!(json.contains(r#""kind":"request""#));
+        match serde_json::from_str::<BusMessage>(&json).unwrap() {
+            BusMessage::Request { dongle_id, reply_to, .. } => {
+                assert_eq!(dongle_id, "0123456789abcdef");
+                assert_eq!(reply_to, "node_a");
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
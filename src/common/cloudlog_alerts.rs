@@ -0,0 +1,257 @@
+//! Alert rules over incoming cloudlogs. Every instance reads all stored lines, whichever
+//! instance the device is connected to, and a rule fires once there are more than its threshold
+//! within the window. The cooldown lives in the database, so only one instance sends a firing.
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::models::cloudlog_alert_rules::CARM;
+
+/// Rules are read again after this. Changes made through this instance apply right away.
+const RULES_TTL: Duration = Duration::from_secs(60);
+/// Matching lines sent along with a firing
+const SAMPLE_LINES: usize = 5;
+
+static WEBHOOK_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+});
+
+/// The text of a cloudlog line's message, which can also be a JSON object.
+fn message(log: &Value) -> String {
+    match log.get("msg$s").or_else(|| log.get("msg")) {
+        Some(Value::String(msg)) => msg.clone(),
+        Some(msg) => msg.to_string(),
+        None => String::new(),
+    }
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(|v| v.as_str())
+}
+
+struct CompiledRule {
+    rule: CARM,
+    msg_re: Option<Regex>,
+}
+
+impl CompiledRule {
+    /// `None` when the message regex doesn't compile, such a rule can't match anything.
+    fn new(rule: CARM) -> Option<Self> {
+        let msg_re = match rule.msg_regex.as_deref().filter(|re| !re.is_empty()) {
+            Some(re) => match Regex::new(re) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    tracing::warn!("Skipping cloudlog alert rule {}, bad msg_regex: {e}", rule.id);
+                    return None;
+                }
+            },
+            None => None,
+        };
+        Some(Self { rule, msg_re })
+    }
+
+    fn matches(&self, log: &Value) -> bool {
+        let rule = &self.rule;
+        let ctx = log.get("ctx").unwrap_or(&Value::Null);
+        let checks = [
+            (&rule.level, str_field(log, "level")),
+            (&rule.module, str_field(log, "filename")),
+            (&rule.func_name, str_field(log, "funcName")),
+            (&rule.branch, str_field(ctx, "branch")),
+            (&rule.commit, str_field(ctx, "commit")),
+        ];
+        if checks
+            .iter()
+            .any(|(wanted, value)| wanted.as_deref().map_or(false, |wanted| Some(wanted) != *value))
+        {
+            return false;
+        }
+        if let Some(min_levelnum) = rule.min_levelnum {
+            let levelnum = log.get("levelnum").and_then(|v| v.as_i64()).unwrap_or_default();
+            if levelnum < i64::from(min_levelnum) {
+                return false;
+            }
+        }
+        self.msg_re.as_ref().map_or(true, |re| re.is_match(&message(log)))
+    }
+}
+
+/// Matching lines of a rule within its window
+#[derive(Default)]
+struct Window {
+    hits: VecDeque<(Instant, String)>,
+    samples: VecDeque<Value>,
+}
+
+#[derive(Default)]
+struct AlertState {
+    rules: Vec<CompiledRule>,
+    loaded_at: Option<Instant>,
+    windows: HashMap<i32, Window>,
+}
+
+/// What the webhook of a fired rule is sent
+#[derive(Serialize, Debug)]
+pub struct AlertPayload {
+    pub rule_id: i32,
+    pub rule_name: String,
+    /// Matching lines in the window
+    pub count: usize,
+    pub threshold: i32,
+    pub window_secs: i32,
+    /// Unix time
+    pub fired_at: i64,
+    /// Devices the matching lines came from
+    pub dongle_ids: Vec<String>,
+    /// The latest matching lines
+    pub samples: Vec<Value>,
+}
+
+#[derive(Default)]
+pub struct CloudlogAlerts {
+    state: Mutex<AlertState>,
+}
+
+impl CloudlogAlerts {
+    /// Read the rules again on the next batch of lines.
+    pub async fn invalidate(&self) {
+        self.state.lock().await.loaded_at = None;
+    }
+
+    /// Counts the lines `dongle_id` just forwarded against the rules, and sends the webhooks of
+    /// those that fire in the background. Called with every stored line, see `run_cloudlog_feed`.
+    pub async fn check(&self, db: &DatabaseConnection, dongle_id: &str, logs: &[Value]) {
+        let now = Instant::now();
+        let mut fired = Vec::new();
+        {
+            let mut state = self.state.lock().await;
+            if state.loaded_at.map_or(true, |loaded_at| loaded_at.elapsed() >= RULES_TTL) {
+                match CARM::find_enabled_rules(db).await {
+                    Ok(rules) => {
+                        state.rules = rules.into_iter().filter_map(CompiledRule::new).collect();
+                        let ids: Vec<i32> = state.rules.iter().map(|rule| rule.rule.id).collect();
+                        state.windows.retain(|id, _| ids.contains(id));
+                    }
+                    Err(e) => tracing::error!("Failed to load cloudlog alert rules: {e}"),
+                }
+                // also after a failure, the next batch shouldn't try again right away
+                state.loaded_at = Some(now);
+            }
+
+            let AlertState { rules, windows, .. } = &mut *state;
+            for rule in rules.iter() {
+                let window = windows.entry(rule.rule.id).or_default();
+                for log in logs.iter().filter(|log| rule.matches(log)) {
+                    window.hits.push_back((now, dongle_id.to_string()));
+                    let mut sample = log.clone();
+                    if let Some(obj) = sample.as_object_mut() {
+                        obj.insert("_dongle_id".to_string(), Value::String(dongle_id.to_string()));
+                    }
+                    window.samples.push_back(sample);
+                    if window.samples.len() > SAMPLE_LINES {
+                        window.samples.pop_front();
+                    }
+                }
+                let span = Duration::from_secs(rule.rule.window_secs.max(0) as u64);
+                while window.hits.front().map_or(false, |(at, _)| now.duration_since(*at) > span) {
+                    window.hits.pop_front();
+                }
+                if window.hits.is_empty() {
+                    window.samples.clear();
+                }
+                if window.hits.len() > rule.rule.threshold.max(0) as usize {
+                    let count = window.hits.len();
+                    let dongle_ids: BTreeSet<String> = window.hits.drain(..).map(|(_, dongle_id)| dongle_id).collect();
+                    let payload = AlertPayload {
+                        rule_id: rule.rule.id,
+                        rule_name: rule.rule.name.clone(),
+                        count,
+                        threshold: rule.rule.threshold,
+                        window_secs: rule.rule.window_secs,
+                        fired_at: Utc::now().timestamp(),
+                        dongle_ids: dongle_ids.into_iter().collect(),
+                        samples: window.samples.drain(..).collect(),
+                    };
+                    fired.push((rule.rule.webhook_url.clone(), payload));
+                }
+            }
+        }
+
+        for (webhook_url, payload) in fired {
+            tokio::spawn(send_alert(db.clone(), webhook_url, payload));
+        }
+    }
+}
+
+/// Posts the firing unless another instance, or an earlier batch, fired the rule within its
+/// cooldown. The outcome is kept on the rule.
+async fn send_alert(db: DatabaseConnection, webhook_url: String, payload: AlertPayload) {
+    match CARM::claim_firing(&db, payload.rule_id, payload.fired_at).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            tracing::error!("Failed to record cloudlog alert {}: {e}", payload.rule_id);
+            return;
+        }
+    }
+    tracing::info!("Cloudlog alert {} fired with {} lines", payload.rule_name, payload.count);
+    let error = match WEBHOOK_CLIENT
+        .post(&webhook_url)
+        .json(&payload)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+    {
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!("Cloudlog alert {} webhook failed: {e}", payload.rule_id);
+            Some(e.to_string())
+        }
+    };
+    if let Err(e) = CARM::set_last_error(&db, payload.rule_id, error.as_deref()).await {
+        tracing::error!("Failed to record cloudlog alert {} delivery: {e}", payload.rule_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_matches_lines() {
+        let rule = CompiledRule::new(CARM {
+            level: Some("ERROR".to_string()),
+            module: Some("controlsd.py".to_string()),
+            branch: Some("FrogPilot-Testing".to_string()),
+            msg_regex: Some("^controls mismatch".to_string()),
+            min_levelnum: Some(40),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut log = serde_json::json!({
+            "level": "ERROR",
+            "levelnum": 40,
+            "filename": "controlsd.py",
+            "msg": "controls mismatch: lateral",
+            "ctx": {"branch": "FrogPilot-Testing", "commit": "abc123"},
+        });
+        assert!(rule.matches(&log));
+        log["ctx"]["branch"] = Value::String("FrogPilot".to_string());
+        assert!(!rule.matches(&log));
+
+        let bad_regex = CARM { msg_regex: Some("(".to_string()), ..Default::default() };
+        assert!(CompiledRule::new(bad_regex).is_none());
+    }
+}
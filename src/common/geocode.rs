@@ -0,0 +1,167 @@
+//! Offline reverse geocoding against a GeoNames places extract loaded from disk.
+//!
+//! `GEOCODER_DATASET` points at a file in the GeoNames dump format, e.g. `cities1000.txt` or
+//! `cities500.txt` from <https://download.geonames.org/export/dump/>. Without it every lookup
+//! returns None. The places are bucketed by whole degree, so a lookup only looks at the few
+//! hundred places around the point.
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+use once_cell::sync::Lazy;
+
+/// Points further than this from any known place don't get a name.
+const MAX_DISTANCE_METERS: f64 = 50_000.0;
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+static GEOCODER: Lazy<Option<Geocoder>> = Lazy::new(|| {
+    let path = env::var("GEOCODER_DATASET").ok().filter(|path| !path.is_empty())?;
+    match Geocoder::load(&path) {
+        Ok(geocoder) => {
+            tracing::info!("Loaded {} places for reverse geocoding from {path}", geocoder.places.len());
+            Some(geocoder)
+        }
+        Err(e) => {
+            tracing::error!("Failed to load reverse geocoding dataset {path}: {e}");
+            None
+        }
+    }
+});
+
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub name: String,
+    /// State or province code, e.g. "CA"
+    pub admin1: String,
+    pub country_code: String,
+    pub lat: f64,
+    pub lng: f64,
+}
+
+impl Place {
+    /// "San Diego, CA, US"
+    pub fn display_name(&self) -> String {
+        [&self.name, &self.admin1, &self.country_code]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+pub struct Geocoder {
+    places: Vec<Place>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl Geocoder {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Ok(Self::from_places(read_geonames(BufReader::new(File::open(path)?))?))
+    }
+
+    pub fn from_places(places: Vec<Place>) -> Self {
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (index, place) in places.iter().enumerate() {
+            cells.entry(cell(place.lat, place.lng)).or_default().push(index);
+        }
+        Self { places, cells }
+    }
+
+    /// The closest place within `MAX_DISTANCE_METERS`.
+    pub fn nearest(&self, lat: f64, lng: f64) -> Option<&Place> {
+        if !lat.is_finite() || !lng.is_finite() || (lat == 0.0 && lng == 0.0) {
+            return None; // routes without gps have 0,0
+        }
+        let (cell_lat, cell_lng) = cell(lat, lng);
+        let mut nearest: Option<(f64, &Place)> = None;
+        for d_lat in -1..=1 {
+            for d_lng in -1..=1 {
+                let key = (cell_lat + d_lat, wrap_lng_cell(cell_lng + d_lng));
+                for &index in self.cells.get(&key).into_iter().flatten() {
+                    let place = &self.places[index];
+                    let distance = approximate_distance(lat, lng, place.lat, place.lng);
+                    if nearest.map_or(true, |(best, _)| distance < best) {
+                        nearest = Some((distance, place));
+                    }
+                }
+            }
+        }
+        nearest
+            .filter(|(distance, _)| *distance <= MAX_DISTANCE_METERS)
+            .map(|(_, place)| place)
+    }
+}
+
+/// False when `GEOCODER_DATASET` is unset or couldn't be loaded.
+pub fn is_available() -> bool {
+    GEOCODER.is_some()
+}
+
+/// Name of the place closest to the point, None if there is no dataset or nothing nearby.
+pub fn place_name(lat: f64, lng: f64) -> Option<String> {
+    GEOCODER.as_ref()?.nearest(lat, lng).map(Place::display_name)
+}
+
+/// Tab separated GeoNames rows: geonameid, name, asciiname, alternatenames, latitude,
+/// longitude, feature class, feature code, country code, cc2, admin1 code, ...
+fn read_geonames<R: BufRead>(reader: R) -> io::Result<Vec<Place>> {
+    let mut places = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 11 {
+            continue;
+        }
+        let (Ok(lat), Ok(lng)) = (fields[4].parse::<f64>(), fields[5].parse::<f64>()) else {
+            continue;
+        };
+        places.push(Place {
+            name: fields[1].to_string(),
+            admin1: fields[10].to_string(),
+            country_code: fields[8].to_string(),
+            lat,
+            lng,
+        });
+    }
+    Ok(places)
+}
+
+fn cell(lat: f64, lng: f64) -> (i32, i32) {
+    (lat.floor() as i32, wrap_lng_cell(lng.floor() as i32))
+}
+
+/// Keeps the cells on both sides of the antimeridian next to each other.
+fn wrap_lng_cell(cell_lng: i32) -> i32 {
+    (cell_lng + 180).rem_euclid(360) - 180
+}
+
+/// Equirectangular distance in meters, plenty for picking between places a few km apart.
+fn approximate_distance(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let mut d_lng = (lng2 - lng1).abs();
+    if d_lng > 180.0 {
+        d_lng = 360.0 - d_lng;
+    }
+    let x = d_lng * ((lat1 + lat2) / 2.0).to_radians().cos();
+    let y = lat2 - lat1;
+    (x * x + y * y).sqrt() * METERS_PER_DEGREE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_nearest_place() {
+        let rows = "5391811\tSan Diego\tSan Diego\t\t32.71571\t-117.16472\tP\tPPLA2\tUS\t\tCA\n\
+                    5368361\tLos Angeles\tLos Angeles\t\t34.05223\t-118.24368\tP\tPPLA2\tUS\t\tCA\n";
+        let geocoder = Geocoder::from_places(read_geonames(rows.as_bytes()).unwrap());
+        let place = geocoder.nearest(32.75, -117.1).unwrap();
+        assert_eq!(place.display_name(), "San Diego, CA, US");
+        assert!(geocoder.nearest(0.0, 0.0).is_none());
+        assert!(geocoder.nearest(40.0, -100.0).is_none());
+    }
+}
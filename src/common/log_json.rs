@@ -0,0 +1,128 @@
+//! JSON rendering of log events for the `.jsonl` files next to each qlog and bootlog.
+//!
+//! Everything is walked through capnp's schema reflection, so every event type and field
+//! comes out without being listed here. Each line looks like
+//! `{"type":"carState","logMonoTime":123,"valid":true,"data":{...}}`. Data fields are hex,
+//! enums are their enumerant name and unset pointer fields are left out.
+use std::io::{self, Write};
+
+use capnp::{dynamic_struct, dynamic_value, schema::Field};
+use serde_json::{Map, Value};
+
+use crate::cereal::log_capnp::event as LogEvent;
+
+/// None for events whose type this build's schema doesn't know yet.
+pub fn event_to_json(event: LogEvent::Reader<'_>) -> Option<Value> {
+    let dynamic_value::Reader::Struct(reader) = dynamic_value::Reader::from(event) else {
+        return None;
+    };
+    let field = reader.which().ok().flatten()?;
+    let mut line = Map::new();
+    line.insert("type".to_string(), field_name(field)?.into());
+    line.insert("logMonoTime".to_string(), event.get_log_mono_time().into());
+    line.insert("valid".to_string(), event.get_valid().into());
+    line.insert("data".to_string(), reader.get(field).map(value_to_json).unwrap_or(Value::Null));
+    Some(Value::Object(line))
+}
+
+/// Append `event` as one line. Events the schema doesn't know are skipped.
+pub fn write_event<W: Write>(writer: &mut W, event: LogEvent::Reader<'_>) -> io::Result<()> {
+    if let Some(line) = event_to_json(event) {
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn field_name(field: Field) -> Option<String> {
+    field.get_proto().get_name().ok()?.to_str().ok().map(str::to_string)
+}
+
+fn float_to_json(value: f64) -> Value {
+    // NaN and inf have no JSON representation
+    serde_json::Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+fn struct_to_json(reader: dynamic_struct::Reader<'_>) -> Value {
+    let mut fields: Vec<Field> = match reader.get_schema().get_non_union_fields() {
+        Ok(fields) => fields.iter().collect(),
+        Err(_) => vec![],
+    };
+    if let Ok(Some(field)) = reader.which() {
+        fields.push(field);
+    }
+
+    let mut object = Map::new();
+    for field in fields {
+        // has() is only false for pointers that were never set
+        if !reader.has(field).unwrap_or(false) {
+            continue;
+        }
+        if let (Some(name), Ok(value)) = (field_name(field), reader.get(field)) {
+            object.insert(name, value_to_json(value));
+        }
+    }
+    Value::Object(object)
+}
+
+fn value_to_json(value: dynamic_value::Reader<'_>) -> Value {
+    match value {
+        dynamic_value::Reader::Void => Value::Null,
+        dynamic_value::Reader::Bool(value) => value.into(),
+        dynamic_value::Reader::Int8(value) => value.into(),
+        dynamic_value::Reader::Int16(value) => value.into(),
+        dynamic_value::Reader::Int32(value) => value.into(),
+        dynamic_value::Reader::Int64(value) => value.into(),
+        dynamic_value::Reader::UInt8(value) => value.into(),
+        dynamic_value::Reader::UInt16(value) => value.into(),
+        dynamic_value::Reader::UInt32(value) => value.into(),
+        dynamic_value::Reader::UInt64(value) => value.into(),
+        // Going through the shortest f32 string keeps 0.1 from turning into 0.10000000149011612
+        dynamic_value::Reader::Float32(value) => float_to_json(value.to_string().parse().unwrap_or(value as f64)),
+        dynamic_value::Reader::Float64(value) => float_to_json(value),
+        dynamic_value::Reader::Enum(value) => match value.get_enumerant() {
+            Ok(Some(enumerant)) => enumerant
+                .get_proto()
+                .get_name()
+                .ok()
+                .and_then(|name| name.to_str().ok())
+                .map_or_else(|| value.get_value().into(), Value::from),
+            _ => value.get_value().into(),
+        },
+        dynamic_value::Reader::Text(text) => String::from_utf8_lossy(text.as_bytes()).into_owned().into(),
+        dynamic_value::Reader::Data(data) => hex::encode(data).into(),
+        dynamic_value::Reader::Struct(reader) => struct_to_json(reader),
+        dynamic_value::Reader::List(list) => Value::Array(
+            (0..list.len())
+                .filter_map(|index| list.get(index).ok())
+                .map(value_to_json)
+                .collect(),
+        ),
+        _ => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_can_event() {
+        let mut message = capnp::message::Builder::new_default();
+        {
+            let mut event = message.init_root::<LogEvent::Builder>();
+            event.set_log_mono_time(42);
+            event.set_valid(true);
+            let mut can = event.init_can(1).get(0);
+            can.set_address(0x123);
+            can.set_dat(&[0xde, 0xad]);
+        }
+        let event = message.get_root_as_reader::<LogEvent::Reader>().unwrap();
+        let line = event_to_json(event).unwrap();
+        assert_eq!(line["type"], "can");
+        assert_eq!(line["logMonoTime"], 42);
+        assert_eq!(line["valid"], true);
+        assert_eq!(line["data"][0]["address"], 0x123);
+        assert_eq!(line["data"][0]["dat"], "dead");
+    }
+}
@@ -0,0 +1,169 @@
+//! Incremental decoding of openpilot logs (qlogs, rlogs and bootlogs, bz2 or zstd).
+//!
+//! A log is just capnp messages back to back in the standard stream framing, so each
+//! message can be cut out of the decompressed stream using its segment table. Only the
+//! message being looked at is ever held in memory.
+use std::{io, pin::Pin};
+
+use async_compression::tokio::bufread;
+use bytes::Bytes;
+use capnp::{
+    message::{Reader, ReaderOptions},
+    serialize::OwnedSegments,
+};
+use futures::{stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::StreamReader;
+
+use crate::common::storage::ByteStream;
+
+/// A segment table any longer than this is garbage rather than a real message.
+const MAX_SEGMENTS: usize = 512;
+/// Same as capnp's default traversal limit, 64 MiB.
+const MAX_MESSAGE_WORDS: usize = 8 * 1024 * 1024;
+
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const BZ2_MAGIC: &[u8] = b"BZh";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Bz2,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        if file_name.ends_with(".bz2") {
+            Some(Compression::Bz2)
+        } else if file_name.ends_with(".zst") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// From the first bytes of the file.
+    pub fn from_magic(head: &[u8]) -> Option<Self> {
+        if head.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else if head.starts_with(BZ2_MAGIC) {
+            Some(Compression::Bz2)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct LogReader {
+    decoder: Pin<Box<dyn AsyncRead + Send>>,
+    options: ReaderOptions,
+    buf: Vec<u8>,
+}
+
+impl LogReader {
+    /// The decompressor is picked from the file name, which has to end in `.bz2` or `.zst`.
+    pub fn new(stream: ByteStream, file_name: &str) -> io::Result<Self> {
+        let compression = Compression::from_file_name(file_name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Invalid file type. Must end with .bz2 or .zst")
+        })?;
+        Ok(Self::with_compression(stream, compression))
+    }
+
+    /// Like `new`, but the magic bytes at the start of the stream win over the file name, so
+    /// a file uploaded under the wrong extension still decodes.
+    pub async fn sniff(mut stream: ByteStream, file_name: &str) -> io::Result<Self> {
+        let mut head = Vec::new();
+        while head.len() < ZSTD_MAGIC.len() {
+            match stream.next().await {
+                Some(chunk) => head.extend_from_slice(&chunk?),
+                None => break,
+            }
+        }
+        let compression = Compression::from_magic(&head)
+            .or_else(|| Compression::from_file_name(file_name))
+            .ok_or_else(|| invalid_data(format!("{file_name} is neither bz2 nor zstd")))?;
+        let stream: ByteStream = Box::pin(stream::once(async move { Ok(Bytes::from(head)) }).chain(stream));
+        Ok(Self::with_compression(stream, compression))
+    }
+
+    pub fn with_compression(stream: ByteStream, compression: Compression) -> Self {
+        let stream_reader = StreamReader::new(stream);
+        let decoder: Pin<Box<dyn AsyncRead + Send>> = match compression {
+            Compression::Bz2 => Box::pin(bufread::BzDecoder::new(stream_reader)),
+            Compression::Zstd => Box::pin(bufread::ZstdDecoder::new(stream_reader)),
+        };
+        Self {
+            decoder,
+            options: ReaderOptions::default(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// The next message, or None at the end of the log.
+    pub async fn next_message(&mut self) -> io::Result<Option<Reader<OwnedSegments>>> {
+        self.buf.clear();
+        // (segment count - 1) followed by the size of each segment in words, padded to a whole word
+        if !self.read_more(4).await? {
+            return Ok(None);
+        }
+        let segment_count = u32::from_le_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]) as usize + 1;
+        if segment_count > MAX_SEGMENTS {
+            return Err(invalid_data(format!("message claims {segment_count} segments")));
+        }
+        let padding = if segment_count % 2 == 0 { 4 } else { 0 };
+        if !self.read_more(4 * segment_count + padding).await? {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let words: usize = self.buf[4..4 + 4 * segment_count]
+            .chunks_exact(4)
+            .map(|size| u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize)
+            .sum();
+        if words > MAX_MESSAGE_WORDS {
+            return Err(invalid_data(format!("message of {words} words is too big")));
+        }
+        if !self.read_more(words * 8).await? {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        capnp::serialize::read_message(&mut self.buf.as_slice(), self.options)
+            .map(Some)
+            .map_err(|e| invalid_data(e.to_string()))
+    }
+
+    /// Append exactly `len` bytes to the buffer. False if the stream was already at its end.
+    async fn read_more(&mut self, len: usize) -> io::Result<bool> {
+        let start = self.buf.len();
+        self.buf.resize(start + len, 0);
+        let mut filled = 0;
+        while filled < len {
+            let n = self.decoder.read(&mut self.buf[start + filled..]).await?;
+            if n == 0 {
+                if filled == 0 {
+                    self.buf.truncate(start);
+                    return Ok(false);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            filled += n;
+        }
+        Ok(true)
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_compression() {
+        let zstd = [0x28, 0xb5, 0x2f, 0xfd, 0x00];
+        assert_eq!(Compression::from_magic(&zstd), Some(Compression::Zstd));
+        assert_eq!(Compression::from_magic(b"BZh91AY&SY"), Some(Compression::Bz2));
+        assert_eq!(Compression::from_magic(b"\x00\x00"), None);
+        assert_eq!(Compression::from_file_name("2024-01-01--00-00-00.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_file_name("rlog"), None);
+    }
+}
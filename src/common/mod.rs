@@ -1,4 +1,12 @@
-pub mod mkv_helpers;
+pub mod storage;
+pub mod athena_bus;
+pub mod cloudlog_alerts;
 pub mod enforce;
+pub mod geocode;
+pub mod log_json;
+pub mod log_reader;
+pub mod quota;
 pub mod re;
-pub mod types;
\ No newline at end of file
+pub mod track;
+pub mod types;
+pub mod video;
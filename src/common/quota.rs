@@ -0,0 +1,167 @@
+//! Storage quotas for device uploads.
+//!
+//! Limits come from the `storage_quota`/`log_quota`/`camera_quota` columns on the device
+//! (and `storage_quota` on the owner) when set, otherwise from the server wide defaults:
+//! `DEVICE_STORAGE_QUOTA_GB`, `DEVICE_LOG_QUOTA_GB`, `DEVICE_CAMERA_QUOTA_GB` and
+//! `USER_STORAGE_QUOTA_GB`. An unset or zero default means no limit.
+use std::env;
+
+use axum::http::StatusCode;
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+
+use crate::models::{devices::DM, users::UM};
+
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileClass {
+    Logs,
+    Cameras,
+}
+
+impl FileClass {
+    /// Videos count against the camera quota, everything else (qlogs, rlogs, bootlogs,
+    /// crashes) against the log quota.
+    pub fn from_file_name(file: &str) -> FileClass {
+        if file.ends_with(".hevc") || file.ends_with(".ts") || file.ends_with(".mp4") || file.ends_with(".m4s") {
+            FileClass::Cameras
+        } else {
+            FileClass::Logs
+        }
+    }
+}
+
+fn default_quota(var: &str) -> Option<i64> {
+    env::var(var)
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|gb| *gb > 0.0)
+        .map(|gb| (gb * BYTES_PER_GB) as i64)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Default)]
+pub struct Usage {
+    pub used: i64,
+    /// None when unlimited.
+    pub limit: Option<i64>,
+}
+
+impl Usage {
+    fn new(used: i64, limit: Option<i64>) -> Self {
+        Usage { used, limit }
+    }
+
+    fn would_exceed(&self, incoming: i64) -> bool {
+        // Uploads without a Content-Length count as one byte so a full device is still turned away,
+        // the body itself is held to `remaining` while it streams in
+        self.limit.is_some_and(|limit| self.used + incoming.max(1) > limit)
+    }
+
+    /// Bytes left under the limit, None when unlimited.
+    fn remaining(&self) -> Option<i64> {
+        self.limit.map(|limit| (limit - self.used).max(0))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct QuotaUsage {
+    pub device: Usage,
+    pub logs: Usage,
+    pub cameras: Usage,
+    /// Combined usage of every device the owner has. None for unpaired devices.
+    pub user: Option<Usage>,
+}
+
+impl QuotaUsage {
+    pub async fn for_device(db: &DatabaseConnection, device: &DM) -> QuotaUsage {
+        let user = match device.owner_id {
+            Some(owner_id) => match UM::find_by_id(db, owner_id).await {
+                Ok(owner) => {
+                    let used = DM::find_user_devices(db, owner.id)
+                        .await
+                        .iter()
+                        .map(|d| d.server_storage)
+                        .sum();
+                    Some(Usage::new(used, owner.storage_quota.or_else(|| default_quota("USER_STORAGE_QUOTA_GB"))))
+                }
+                Err(e) => {
+                    tracing::error!("Failed to find owner {owner_id} of {}: {e}", device.dongle_id);
+                    None
+                }
+            },
+            None => None,
+        };
+        QuotaUsage {
+            device: Usage::new(
+                device.server_storage,
+                device.storage_quota.or_else(|| default_quota("DEVICE_STORAGE_QUOTA_GB")),
+            ),
+            logs: Usage::new(
+                device.log_storage,
+                device.log_quota.or_else(|| default_quota("DEVICE_LOG_QUOTA_GB")),
+            ),
+            cameras: Usage::new(
+                device.camera_storage,
+                device.camera_quota.or_else(|| default_quota("DEVICE_CAMERA_QUOTA_GB")),
+            ),
+            user,
+        }
+    }
+
+    fn class(&self, class: FileClass) -> &Usage {
+        match class {
+            FileClass::Logs => &self.logs,
+            FileClass::Cameras => &self.cameras,
+        }
+    }
+
+    /// The most bytes of `class` that still fit under every quota, None when none applies.
+    pub fn remaining(&self, class: FileClass) -> Option<i64> {
+        [Some(self.device), Some(*self.class(class)), self.user]
+            .into_iter()
+            .flatten()
+            .filter_map(|usage| usage.remaining())
+            .min()
+    }
+
+    /// Which quota, if any, `incoming` more bytes of `class` would go over.
+    pub fn exceeded_by(&self, class: FileClass, incoming: i64) -> Option<&'static str> {
+        if self.device.would_exceed(incoming) {
+            Some("Device storage quota exceeded")
+        } else if self.class(class).would_exceed(incoming) {
+            match class {
+                FileClass::Logs => Some("Device log storage quota exceeded"),
+                FileClass::Cameras => Some("Device camera storage quota exceeded"),
+            }
+        } else if self.user.is_some_and(|user| user.would_exceed(incoming)) {
+            Some("Owner storage quota exceeded")
+        } else {
+            None
+        }
+    }
+}
+
+/// Reject an upload of `incoming` bytes of `file` if it would put the device over quota.
+/// 507 is used because the openpilot uploader treats it as a failure and backs off,
+/// unlike 401/403/412 which make it give up on the file.
+///
+/// `incoming` is only what the request says it sends. Returns the bytes the body may really
+/// take, None when unlimited.
+pub async fn enforce_upload_quota(
+    db: &DatabaseConnection,
+    device: &DM,
+    file: &str,
+    incoming: i64,
+) -> Result<Option<i64>, (StatusCode, &'static str)> {
+    let usage = QuotaUsage::for_device(db, device).await;
+    let class = FileClass::from_file_name(file);
+    match usage.exceeded_by(class, incoming) {
+        Some(msg) => {
+            tracing::warn!("{msg} for {}. Rejecting upload of {file}", device.dongle_id);
+            Err((StatusCode::INSUFFICIENT_STORAGE, msg))
+        }
+        None => Ok(usage.remaining(class)),
+    }
+}
@@ -11,4 +11,8 @@ pub const NUMBER: &str = r"[0-9]+";
 pub const HEX: &str = r"[0-9a-f]+";
 /// Any file name
 pub const ANY_FILENAME: &str = r".+";
-pub const ALLOWED_FILENAME: &str = r"(rlog\.(?:bz2|zst)|qlog\.(?:bz2|zst)|qcamera\.ts|fcamera\.hevc|dcamera\.hevc|ecamera\.hevc|qlog\.unlog|sprite\.jpg|coords\.json|events\.json)";
+pub const ALLOWED_FILENAME: &str = r"(rlog\.(?:bz2|zst)|qlog\.(?:bz2|zst)|qcamera\.ts|fcamera\.hevc|dcamera\.hevc|ecamera\.hevc|[def]camera_init\.mp4|[def]camera\.m4s|qlog\.unlog|qlog\.jsonl|sprite\.jpg|coords\.json|events\.json|rlog_coords\.json|rlog_events\.json)";
+/// Files a device is allowed to upload. The rest of ALLOWED_FILENAME is derived by the workers.
+pub const UPLOAD_FILENAME: &str = r"(rlog\.(?:bz2|zst)|qlog\.(?:bz2|zst)|qcamera\.ts|fcamera\.hevc|dcamera\.hevc|ecamera\.hevc)";
+/// Crash file names, which end up in the storage key as is
+pub const SAFE_FILENAME: &str = r"[0-9A-Za-z_.-]+";
@@ -0,0 +1,159 @@
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+
+use super::{BlobStore, ByteRange, ByteStream, GetObject, StorageError, StorageResult};
+
+/// Plain directory on disk for small self-hosted setups. Keys are sharded into
+/// subdirectories by their first two characters so a single directory doesn't
+/// end up with millions of entries.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(PathBuf::from(
+            env::var("STORAGE_LOCAL_ROOT").expect("STORAGE_LOCAL_ROOT env variable not set"),
+        ))
+    }
+
+    fn shard(key: &str) -> &str {
+        key.get(..2).unwrap_or("__")
+    }
+
+    fn path(&self, key: &str) -> StorageResult<PathBuf> {
+        super::validate_key(key)?;
+        Ok(self.root.join(Self::shard(key)).join(key))
+    }
+}
+
+fn not_found(e: io::Error, key: &str) -> StorageError {
+    if e.kind() == io::ErrorKind::NotFound {
+        StorageError::NotFound(key.to_string())
+    } else {
+        StorageError::Io(e)
+    }
+}
+
+async fn list_dir(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> io::Result<()> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str() {
+            // skip files that are still being written
+            if name.starts_with(prefix) && !name.starts_with('.') {
+                keys.push(name.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl BlobStore for LocalStore {
+    async fn put(&self, key: &str, mut body: ByteStream, _content_length: Option<u64>) -> StorageResult<()> {
+        let path = self.path(key)?;
+        let dir = path.parent().expect("key path always has a shard directory");
+        fs::create_dir_all(dir).await?;
+        if fs::try_exists(&path).await? {
+            return Err(StorageError::AlreadyExists(key.to_string()));
+        }
+
+        // Write to a hidden temp file first so readers never see a partial object
+        let tmp_path = dir.join(format!(".{}.{}", key, uuid::Uuid::new_v4()));
+        let mut file = fs::File::create(&tmp_path).await?;
+        let write_result: io::Result<()> = async {
+            while let Some(chunk) = body.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.sync_all().await
+        }
+        .await;
+        if let Err(e) = write_result {
+            fs::remove_file(&tmp_path).await.ok();
+            return Err(e.into());
+        }
+
+        // hard_link refuses to replace an existing file, which keeps keys write-once
+        let link_result = fs::hard_link(&tmp_path, &path).await;
+        fs::remove_file(&tmp_path).await.ok();
+        match link_result {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(StorageError::AlreadyExists(key.to_string())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: Option<ByteRange>) -> StorageResult<GetObject> {
+        let path = self.path(key)?;
+        let mut file = fs::File::open(&path).await.map_err(|e| not_found(e, key))?;
+        let total_size = file.metadata().await?.len();
+
+        let (start, end) = match range {
+            Some(range) => range.resolve(total_size).ok_or(StorageError::InvalidRange)?,
+            None => (0, total_size.saturating_sub(1)),
+        };
+        let content_length = if total_size == 0 { 0 } else { end - start + 1 };
+        if start > 0 {
+            file.seek(io::SeekFrom::Start(start)).await?;
+        }
+        let stream: ByteStream = Box::pin(ReaderStream::new(file.take(content_length)));
+        Ok(GetObject {
+            stream,
+            content_length,
+            total_size,
+            range: range.map(|_| (start, end)),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let path = self.path(key)?;
+        fs::remove_file(&path).await.map_err(|e| not_found(e, key))
+    }
+
+    async fn list_prefix(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let mut keys = Vec::new();
+        if prefix.len() >= 2 {
+            list_dir(&self.root.join(Self::shard(prefix)), prefix, &mut keys).await?;
+        } else {
+            let mut shards = match fs::read_dir(&self.root).await {
+                Ok(shards) => shards,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(keys),
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(shard) = shards.next_entry().await? {
+                if shard.file_type().await?.is_dir() {
+                    list_dir(&shard.path(), prefix, &mut keys).await?;
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn stat(&self, key: &str) -> StorageResult<Option<u64>> {
+        let path = self.path(key)?;
+        match fs::metadata(&path).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
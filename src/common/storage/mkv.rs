@@ -0,0 +1,137 @@
+use std::{env, io, time::Duration};
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use reqwest::{header, Client, StatusCode};
+use serde::Deserialize;
+
+use super::{BlobStore, ByteRange, ByteStream, GetObject, StorageError, StorageResult};
+
+/// minikeyvalue. Duplicate PUTs come back as 403 which maps onto `AlreadyExists`.
+pub struct MkvStore {
+    endpoint: String,
+    client: Client,
+}
+
+#[derive(Deserialize)]
+struct ListResponse {
+    keys: Vec<String>,
+}
+
+impl MkvStore {
+    pub fn new(endpoint: String) -> Self {
+        // No total timeout, uploads are streamed through for as long as the device takes
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .pool_idle_timeout(Some(Duration::from_secs(10)))
+            .build()
+            .expect("Failed to build MKV client");
+        Self { endpoint: endpoint.trim_end_matches('/').to_string(), client }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env::var("MKV_ENDPOINT").expect("MKV_ENDPOINT env variable not set"))
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.endpoint, key)
+    }
+}
+
+fn header_u64(headers: &header::HeaderMap, name: header::HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// `Content-Range: bytes 0-99/1234` -> ((0, 99), 1234)
+fn parse_content_range(headers: &header::HeaderMap) -> Option<((u64, u64), u64)> {
+    let value = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    Some(((start.parse().ok()?, end.parse().ok()?), total.parse().ok()?))
+}
+
+#[async_trait]
+impl BlobStore for MkvStore {
+    async fn put(&self, key: &str, body: ByteStream, content_length: Option<u64>) -> StorageResult<()> {
+        super::validate_key(key)?;
+        let mut request = self.client.put(self.url(key));
+        if let Some(content_length) = content_length {
+            request = request.header(header::CONTENT_LENGTH, content_length);
+        }
+        let response = request.body(reqwest::Body::wrap_stream(body)).send().await?;
+        match response.status() {
+            StatusCode::CREATED | StatusCode::OK | StatusCode::NO_CONTENT => Ok(()),
+            StatusCode::FORBIDDEN => Err(StorageError::AlreadyExists(key.to_string())),
+            status => Err(StorageError::Status(status)),
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: Option<ByteRange>) -> StorageResult<GetObject> {
+        super::validate_key(key)?;
+        let mut request = self.client.get(self.url(key));
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range.to_header());
+        }
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::PARTIAL_CONTENT => (),
+            StatusCode::NOT_FOUND => return Err(StorageError::NotFound(key.to_string())),
+            StatusCode::RANGE_NOT_SATISFIABLE => return Err(StorageError::InvalidRange),
+            status => return Err(StorageError::Status(status)),
+        }
+
+        let headers = response.headers();
+        let content_length = header_u64(headers, header::CONTENT_LENGTH).unwrap_or_default();
+        let (range, total_size) = match parse_content_range(headers) {
+            Some((range, total_size)) => (Some(range), total_size),
+            // Volume servers ignore Range on small files and send the whole thing
+            None => (None, content_length),
+        };
+        let stream = response
+            .bytes_stream()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        Ok(GetObject {
+            stream: Box::pin(stream),
+            content_length,
+            total_size,
+            range,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        super::validate_key(key)?;
+        let response = self.client.delete(self.url(key)).send().await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND => Err(StorageError::NotFound(key.to_string())),
+            status => Err(StorageError::Status(status)),
+        }
+    }
+
+    async fn list_prefix(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let response = self.client
+            .get(format!("{}/{}?list", self.endpoint, prefix))
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => (),
+            StatusCode::NOT_FOUND => return Ok(vec![]),
+            status => return Err(StorageError::Status(status)),
+        }
+        let list: ListResponse = response.json().await?;
+        Ok(list.keys
+            .into_iter()
+            .map(|key| key.trim_start_matches('/').to_string())
+            .collect())
+    }
+
+    async fn stat(&self, key: &str) -> StorageResult<Option<u64>> {
+        super::validate_key(key)?;
+        let response = self.client.head(self.url(key)).send().await?;
+        match response.status() {
+            status if status.is_success() => Ok(header_u64(response.headers(), header::CONTENT_LENGTH)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(StorageError::Status(status)),
+        }
+    }
+}
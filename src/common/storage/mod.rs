@@ -0,0 +1,187 @@
+//! Blob storage for device uploads and the artifacts the workers derive from them.
+//!
+//! Every file lives under a flat key such as `{dongle_id}_{route}--{segment}--{file}`.
+//! The backend is picked once at startup from `STORAGE_BACKEND` (`mkv`, `local` or `s3`).
+pub mod local;
+pub mod mkv;
+pub mod s3;
+mod writer;
+
+use std::{env, io, pin::Pin, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{stream, Stream, TryStreamExt};
+use once_cell::sync::Lazy;
+
+pub use writer::BlobWriter;
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("object not found: {0}")]
+    NotFound(String),
+    #[error("object already exists: {0}")]
+    AlreadyExists(String),
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    #[error("requested range not satisfiable")]
+    InvalidRange,
+    #[error("unexpected status {0} from storage backend")]
+    Status(reqwest::StatusCode),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<StorageError> for io::Error {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::Other, e),
+        }
+    }
+}
+
+pub type StorageResult<T> = std::result::Result<T, StorageError>;
+
+/// A single HTTP style byte range. End offsets are inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    From(u64),
+    Inclusive(u64, u64),
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parse a `Range: bytes=...` header. Multi-range requests are not supported.
+    pub fn from_header(value: &str) -> Option<ByteRange> {
+        let spec = value.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        match (start.trim(), end.trim()) {
+            ("", suffix) => suffix.parse().ok().map(ByteRange::Suffix),
+            (start, "") => start.parse().ok().map(ByteRange::From),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(ByteRange::Inclusive(start, end))
+            }
+        }
+    }
+
+    pub fn to_header(&self) -> String {
+        match self {
+            ByteRange::From(start) => format!("bytes={start}-"),
+            ByteRange::Inclusive(start, end) => format!("bytes={start}-{end}"),
+            ByteRange::Suffix(len) => format!("bytes=-{len}"),
+        }
+    }
+
+    /// Clamp the range against an object of `size` bytes. None if it can't be satisfied.
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        if size == 0 {
+            return None;
+        }
+        let (start, end) = match *self {
+            ByteRange::From(start) => (start, size - 1),
+            ByteRange::Inclusive(start, end) => (start, end.min(size - 1)),
+            ByteRange::Suffix(len) => (size.saturating_sub(len), size - 1),
+        };
+        (start <= end && start < size).then_some((start, end))
+    }
+}
+
+pub struct GetObject {
+    pub stream: ByteStream,
+    /// Bytes in `stream`.
+    pub content_length: u64,
+    /// Size of the whole object.
+    pub total_size: u64,
+    /// The inclusive range that was served when a range was requested.
+    pub range: Option<(u64, u64)>,
+}
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Store `body` under `key`. Keys are write-once: putting an existing key fails with
+    /// `AlreadyExists` so duplicate uploads can be detected.
+    async fn put(&self, key: &str, body: ByteStream, content_length: Option<u64>) -> StorageResult<()>;
+
+    async fn get_range(&self, key: &str, range: Option<ByteRange>) -> StorageResult<GetObject>;
+
+    async fn delete(&self, key: &str) -> StorageResult<()>;
+
+    async fn list_prefix(&self, prefix: &str) -> StorageResult<Vec<String>>;
+
+    /// Size of the object in bytes, or None if it doesn't exist.
+    async fn stat(&self, key: &str) -> StorageResult<Option<u64>>;
+
+    async fn get(&self, key: &str) -> StorageResult<GetObject> {
+        self.get_range(key, None).await
+    }
+
+    async fn get_bytes(&self, key: &str) -> StorageResult<Bytes> {
+        let object = self.get(key).await?;
+        let chunks: Vec<Bytes> = object.stream.try_collect().await?;
+        Ok(chunks.concat().into())
+    }
+
+    async fn put_bytes(&self, key: &str, data: Vec<u8>) -> StorageResult<()> {
+        let len = data.len() as u64;
+        let body: ByteStream = Box::pin(stream::once(async move { Ok(Bytes::from(data)) }));
+        self.put(key, body, Some(len)).await
+    }
+}
+
+static STORE: Lazy<Arc<dyn BlobStore>> = Lazy::new(|| {
+    let backend = env::var("STORAGE_BACKEND").unwrap_or_else(|_| "mkv".to_string());
+    match backend.as_str() {
+        "mkv" => Arc::new(mkv::MkvStore::from_env()),
+        "local" => Arc::new(local::LocalStore::from_env()),
+        "s3" => Arc::new(s3::S3Store::from_env()),
+        other => panic!("Unknown STORAGE_BACKEND `{other}`. Expected mkv, local or s3"),
+    }
+});
+
+/// The configured blob store.
+pub fn blob_store() -> Arc<dyn BlobStore> {
+    STORE.clone()
+}
+
+/// Jobs queued before the storage refactor carry a full MKV URL instead of a key.
+pub fn key_from_url(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+/// Keys are flat names. Anything that could escape a directory or a bucket path is rejected.
+pub fn validate_key(key: &str) -> StorageResult<()> {
+    if key.is_empty() || key.starts_with('.') || key.contains('/') || key.contains('\\') {
+        return Err(StorageError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_range_headers() {
+        assert_eq!(ByteRange::from_header("bytes=0-99"), Some(ByteRange::Inclusive(0, 99)));
+        assert_eq!(ByteRange::from_header("bytes=100-"), Some(ByteRange::From(100)));
+        assert_eq!(ByteRange::from_header("bytes=-50"), Some(ByteRange::Suffix(50)));
+        assert_eq!(ByteRange::from_header("bytes=10-5"), None);
+        assert_eq!(ByteRange::from_header("bytes=0-1,4-5"), None);
+    }
+
+    #[test]
+    fn resolves_ranges_against_size() {
+        assert_eq!(ByteRange::Inclusive(0, 999).resolve(100), Some((0, 99)));
+        assert_eq!(ByteRange::Suffix(10).resolve(100), Some((90, 99)));
+        assert_eq!(ByteRange::From(100).resolve(100), None);
+    }
+}
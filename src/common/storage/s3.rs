@@ -0,0 +1,361 @@
+use std::{env, io, time::Duration};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{header, Client, Method, RequestBuilder, StatusCode};
+use sha2::{Digest, Sha256};
+
+use super::{BlobStore, ByteRange, ByteStream, GetObject, StorageError, StorageResult};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bodies of unknown length are sent a part of this size at a time. S3 wants at least 5 MiB
+/// for every part but the last.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// S3 compatible object storage (AWS, MinIO, R2, ...). Requests are signed with SigV4
+/// using path style addressing, `{endpoint}/{bucket}/{key}`, which every S3 clone supports.
+pub struct S3Store {
+    endpoint: url::Url,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    client: Client,
+}
+
+impl S3Store {
+    pub fn from_env() -> Self {
+        let endpoint = env::var("S3_ENDPOINT").expect("S3_ENDPOINT env variable not set");
+        Self {
+            endpoint: url::Url::parse(&endpoint).expect("S3_ENDPOINT is not a valid URL"),
+            bucket: env::var("S3_BUCKET").expect("S3_BUCKET env variable not set"),
+            region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            access_key: env::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY env variable not set"),
+            secret_key: env::var("S3_SECRET_KEY").expect("S3_SECRET_KEY env variable not set"),
+            client: Client::builder()
+                .connect_timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build S3 client"),
+        }
+    }
+
+    fn host(&self) -> String {
+        let host = self.endpoint.host_str().unwrap_or_default();
+        match self.endpoint.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        }
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        format!("/{}/{}", uri_encode(&self.bucket), uri_encode(key))
+    }
+
+    /// Build a signed request. `query` must already be sorted by parameter name.
+    fn signed(&self, method: Method, path: &str, query: &[(&str, String)]) -> RequestBuilder {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = self.host();
+        let payload_hash = "UNSIGNED-PAYLOAD";
+
+        let canonical_query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", uri_encode(k), uri_encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{path}\n{canonical_query}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let k_date = hmac(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes());
+        let k_region = hmac(&k_date, self.region.as_bytes());
+        let k_service = hmac(&k_region, b"s3");
+        let k_signing = hmac(&k_service, b"aws4_request");
+        let signature = hex::encode(hmac(&k_signing, string_to_sign.as_bytes()));
+
+        let mut url = format!("{}{}", self.endpoint.as_str().trim_end_matches('/'), path);
+        if !canonical_query.is_empty() {
+            url = format!("{url}?{canonical_query}");
+        }
+        self.client
+            .request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(
+                header::AUTHORIZATION,
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.access_key
+                ),
+            )
+    }
+
+    async fn put_object(&self, key: &str, body: reqwest::Body, content_length: u64) -> StorageResult<()> {
+        let response = self
+            .signed(Method::PUT, &self.object_path(key), &[])
+            .header(header::CONTENT_LENGTH, content_length)
+            .header(header::IF_NONE_MATCH, "*")
+            .body(body)
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT => Err(StorageError::AlreadyExists(key.to_string())),
+            status => Err(StorageError::Status(status)),
+        }
+    }
+
+    /// S3 won't take a chunked body without the streaming signature, so a body of unknown
+    /// length goes up as a multipart upload, holding one part of it at a time. One that fits
+    /// in the first part is sent as a plain PUT.
+    async fn put_unsized(&self, key: &str, mut body: ByteStream) -> StorageResult<()> {
+        let first = read_part(&mut body).await?;
+        if first.len() < PART_SIZE {
+            let len = first.len() as u64;
+            return self.put_object(key, reqwest::Body::from(first), len).await;
+        }
+        // The completion checks this too, but only after the whole body went up
+        if self.stat(key).await?.is_some() {
+            return Err(StorageError::AlreadyExists(key.to_string()));
+        }
+
+        let path = self.object_path(key);
+        let response = self
+            .signed(Method::POST, &path, &[("uploads", String::new())])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(StorageError::Status(response.status()));
+        }
+        let body_text = response.text().await?;
+        let Some(upload_id) = xml_values(&body_text, "UploadId").first().map(|id| xml_unescape(id)) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no UploadId in the multipart upload response").into());
+        };
+
+        let result = self.upload_parts(key, &upload_id, first, &mut body).await;
+        if result.is_err() {
+            // Parts of an upload that's never completed are kept, and billed, until it's aborted
+            let abort = self
+                .signed(Method::DELETE, &path, &[("uploadId", upload_id.clone())])
+                .send()
+                .await;
+            if let Err(e) = abort {
+                tracing::error!("Failed to abort the multipart upload of {key}: {e}");
+            }
+        }
+        result
+    }
+
+    async fn upload_parts(&self, key: &str, upload_id: &str, first: Vec<u8>, body: &mut ByteStream) -> StorageResult<()> {
+        let path = self.object_path(key);
+        let mut etags = Vec::new();
+        let mut part = first;
+        loop {
+            let query = [("partNumber", (etags.len() + 1).to_string()), ("uploadId", upload_id.to_string())];
+            let len = part.len() as u64;
+            let response = self
+                .signed(Method::PUT, &path, &query)
+                .header(header::CONTENT_LENGTH, len)
+                .body(part)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                return Err(StorageError::Status(response.status()));
+            }
+            let Some(etag) = response.headers().get(header::ETAG).and_then(|v| v.to_str().ok()) else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "no ETag for an uploaded part").into());
+            };
+            etags.push(etag.to_string());
+            part = read_part(body).await?;
+            if part.is_empty() {
+                break;
+            }
+        }
+
+        let mut complete = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in etags.iter().enumerate() {
+            complete.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{etag}</ETag></Part>", i + 1));
+        }
+        complete.push_str("</CompleteMultipartUpload>");
+        let response = self
+            .signed(Method::POST, &path, &[("uploadId", upload_id.to_string())])
+            .header(header::CONTENT_LENGTH, complete.len())
+            .header(header::IF_NONE_MATCH, "*")
+            .body(complete)
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => (),
+            StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT => return Err(StorageError::AlreadyExists(key.to_string())),
+            status => return Err(StorageError::Status(status)),
+        }
+        // The completion can still fail after the 200 went out, the error is in the body then
+        let body_text = response.text().await?;
+        match xml_values(&body_text, "Code").first() {
+            None => Ok(()),
+            Some(&"PreconditionFailed") => Err(StorageError::AlreadyExists(key.to_string())),
+            Some(code) => Err(io::Error::new(io::ErrorKind::Other, format!("multipart upload of {key} failed: {code}")).into()),
+        }
+    }
+}
+
+/// Up to `PART_SIZE` bytes from the body, fewer only at its end.
+async fn read_part(body: &mut ByteStream) -> StorageResult<Vec<u8>> {
+    let mut part = Vec::with_capacity(PART_SIZE);
+    while part.len() < PART_SIZE {
+        match body.try_next().await? {
+            Some(chunk) => part.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    Ok(part)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// SigV4 flavour of percent encoding: everything but the unreserved characters.
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn xml_values<'a>(body: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    body.split(open.as_str())
+        .skip(1)
+        .filter_map(|rest| rest.split_once(close.as_str()).map(|(value, _)| value))
+        .collect()
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn header_u64(headers: &header::HeaderMap, name: header::HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    async fn put(&self, key: &str, body: ByteStream, content_length: Option<u64>) -> StorageResult<()> {
+        super::validate_key(key)?;
+        match content_length {
+            Some(content_length) => self.put_object(key, reqwest::Body::wrap_stream(body), content_length).await,
+            None => self.put_unsized(key, body).await,
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: Option<ByteRange>) -> StorageResult<GetObject> {
+        super::validate_key(key)?;
+        let mut request = self.signed(Method::GET, &self.object_path(key), &[]);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range.to_header());
+        }
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::PARTIAL_CONTENT => (),
+            StatusCode::NOT_FOUND => return Err(StorageError::NotFound(key.to_string())),
+            StatusCode::RANGE_NOT_SATISFIABLE => return Err(StorageError::InvalidRange),
+            status => return Err(StorageError::Status(status)),
+        }
+
+        let headers = response.headers();
+        let content_length = header_u64(headers, header::CONTENT_LENGTH).unwrap_or_default();
+        let content_range = headers
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("bytes "))
+            .and_then(|v| v.split_once('/'))
+            .and_then(|(range, total)| {
+                let (start, end) = range.split_once('-')?;
+                Some(((start.parse().ok()?, end.parse().ok()?), total.parse().ok()?))
+            });
+        let (range, total_size) = match content_range {
+            Some((range, total_size)) => (Some(range), total_size),
+            None => (None, content_length),
+        };
+        let stream = response
+            .bytes_stream()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        Ok(GetObject {
+            stream: Box::pin(stream),
+            content_length,
+            total_size,
+            range,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        super::validate_key(key)?;
+        let response = self.signed(Method::DELETE, &self.object_path(key), &[]).send().await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND => Err(StorageError::NotFound(key.to_string())),
+            status => Err(StorageError::Status(status)),
+        }
+    }
+
+    async fn list_prefix(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let path = format!("/{}", uri_encode(&self.bucket));
+        let mut keys = Vec::new();
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let mut query = vec![];
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.clone()));
+            }
+            query.push(("list-type", "2".to_string()));
+            query.push(("prefix", prefix.to_string()));
+
+            let response = self.signed(Method::GET, &path, &query).send().await?;
+            if !response.status().is_success() {
+                return Err(StorageError::Status(response.status()));
+            }
+            let body = response.text().await?;
+            keys.extend(xml_values(&body, "Key").into_iter().map(xml_unescape));
+
+            let truncated = xml_values(&body, "IsTruncated").first() == Some(&"true");
+            continuation_token = xml_values(&body, "NextContinuationToken").first().map(|t| xml_unescape(t));
+            if !truncated || continuation_token.is_none() {
+                break;
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn stat(&self, key: &str) -> StorageResult<Option<u64>> {
+        super::validate_key(key)?;
+        let response = self.signed(Method::HEAD, &self.object_path(key), &[]).send().await?;
+        match response.status() {
+            status if status.is_success() => Ok(header_u64(response.headers(), header::CONTENT_LENGTH)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(StorageError::Status(status)),
+        }
+    }
+}
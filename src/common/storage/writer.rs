@@ -0,0 +1,103 @@
+use std::{io, mem};
+
+use bytes::Bytes;
+use futures::stream;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::{blob_store, ByteStream, StorageResult};
+
+/// Buffered bytes are handed to the store once there is at least this much.
+const CHUNK_SIZE: usize = 256 * 1024;
+/// Chunks that can be in flight before `flush_chunks` waits for the store to catch up.
+const CHANNEL_DEPTH: usize = 4;
+
+/// Streams an object into the blob store while it is still being produced, so the
+/// producer never holds more than a few chunks of it. Bytes go into a buffer through
+/// `std::io::Write`, `flush_chunks` passes full chunks on and `finish` commits the object.
+/// Dropping the writer without finishing aborts the upload.
+pub struct BlobWriter {
+    key: String,
+    buf: Vec<u8>,
+    size: u64,
+    tx: Option<mpsc::Sender<io::Result<Bytes>>>,
+    upload: Option<JoinHandle<StorageResult<()>>>,
+}
+
+impl BlobWriter {
+    pub fn create(key: &str) -> Self {
+        let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(CHANNEL_DEPTH);
+        let body: ByteStream = Box::pin(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        }));
+        let store = blob_store();
+        let upload_key = key.to_string();
+        let upload = tokio::spawn(async move { store.put(&upload_key, body, None).await });
+        Self {
+            key: key.to_string(),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            size: 0,
+            tx: Some(tx),
+            upload: Some(upload),
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Pass the buffer on once it holds a full chunk. Waits while the store is behind.
+    pub async fn flush_chunks(&mut self) {
+        if self.buf.len() >= CHUNK_SIZE {
+            let chunk = mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+            self.send(chunk).await;
+        }
+    }
+
+    async fn send(&mut self, chunk: Vec<u8>) {
+        if let Some(tx) = &self.tx {
+            if tx.send(Ok(Bytes::from(chunk))).await.is_err() {
+                // The put already gave up, finish() reports why
+                self.tx = None;
+            }
+        }
+    }
+
+    /// Write out the rest and wait for the store to commit the object. Returns its size.
+    pub async fn finish(mut self) -> StorageResult<u64> {
+        let chunk = mem::take(&mut self.buf);
+        if !chunk.is_empty() {
+            self.send(chunk).await;
+        }
+        self.tx = None;
+        let upload = self.upload.take().expect("upload is only taken by finish or drop");
+        match upload.await {
+            Ok(result) => result.map(|()| self.size),
+            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e).into()),
+        }
+    }
+}
+
+impl io::Write for BlobWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        self.size += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        if let Some(upload) = self.upload.take() {
+            // Fail the body rather than ending it so a backend that already has it doesn't
+            // commit a truncated object
+            if let Some(tx) = self.tx.take() {
+                let _ = tx.try_send(Err(io::Error::new(io::ErrorKind::Other, "upload aborted")));
+            }
+            upload.abort();
+        }
+    }
+}
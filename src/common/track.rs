@@ -0,0 +1,222 @@
+//! A route's GPS track stitched together from the per-segment `coords.json` files, and its
+//! GPX, GeoJSON and KML renderings.
+use std::fmt::Write;
+
+use chrono::{DateTime, SecondsFormat};
+use serde::Deserialize;
+use serde_json::json;
+
+/// Meters per degree of latitude, close enough for measuring how far a point is off a line.
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// One element of `coords.json`, as written by the log parser.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Coord {
+    /// Seconds since the route started
+    pub t: u64,
+    pub lat: f64,
+    pub lng: f64,
+    #[serde(default)]
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    pub time_millis: i64,
+    pub lat: f64,
+    pub lng: f64,
+    /// m/s
+    pub speed: f64,
+}
+
+impl TrackPoint {
+    pub fn from_coord(route_start_millis: i64, coord: &Coord) -> Self {
+        Self {
+            time_millis: route_start_millis + coord.t as i64 * 1000,
+            lat: coord.lat,
+            lng: coord.lng,
+            speed: coord.speed,
+        }
+    }
+
+    fn timestamp(&self) -> String {
+        DateTime::from_timestamp_millis(self.time_millis)
+            .unwrap_or_default()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackFormat {
+    Gpx,
+    GeoJson,
+    Kml,
+}
+
+impl TrackFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            TrackFormat::Gpx => "application/gpx+xml",
+            TrackFormat::GeoJson => "application/geo+json",
+            TrackFormat::Kml => "application/vnd.google-earth.kml+xml",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TrackFormat::Gpx => "gpx",
+            TrackFormat::GeoJson => "geojson",
+            TrackFormat::Kml => "kml",
+        }
+    }
+
+    pub fn render(self, name: &str, points: &[TrackPoint]) -> String {
+        match self {
+            TrackFormat::Gpx => to_gpx(name, points),
+            TrackFormat::GeoJson => to_geojson(name, points),
+            TrackFormat::Kml => to_kml(name, points),
+        }
+    }
+}
+
+/// Douglas-Peucker simplification. Drops every point that is less than `epsilon_meters` off
+/// the line between the points kept around it. The first and last point are always kept.
+pub fn simplify(points: &[TrackPoint], epsilon_meters: f64) -> Vec<TrackPoint> {
+    if points.len() < 3 || epsilon_meters <= 0.0 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    // An explicit stack, a long drive would go too deep for recursion on a straight-ish road
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let mut max_distance = 0.0;
+        let mut farthest = first;
+        for index in first + 1..last {
+            let distance = distance_to_segment(&points[index], &points[first], &points[last]);
+            if distance > max_distance {
+                max_distance = distance;
+                farthest = index;
+            }
+        }
+        if max_distance > epsilon_meters {
+            keep[farthest] = true;
+            stack.push((first, farthest));
+            stack.push((farthest, last));
+        }
+    }
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(point, keep)| keep.then_some(*point))
+        .collect()
+}
+
+/// Meters from `point` to the segment `start`-`end`, on a flat projection around `start`.
+fn distance_to_segment(point: &TrackPoint, start: &TrackPoint, end: &TrackPoint) -> f64 {
+    let meters_per_degree_lng = METERS_PER_DEGREE * start.lat.to_radians().cos();
+    let project = |p: &TrackPoint| {
+        (
+            (p.lng - start.lng) * meters_per_degree_lng,
+            (p.lat - start.lat) * METERS_PER_DEGREE,
+        )
+    };
+    let (px, py) = project(point);
+    let (ex, ey) = project(end);
+    let length_squared = ex * ex + ey * ey;
+    if length_squared == 0.0 {
+        return (px * px + py * py).sqrt();
+    }
+    let t = ((px * ex + py * ey) / length_squared).clamp(0.0, 1.0);
+    let (dx, dy) = (px - t * ex, py - t * ey);
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn to_gpx(name: &str, points: &[TrackPoint]) -> String {
+    let mut gpx = String::new();
+    gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    gpx.push_str("<gpx version=\"1.1\" creator=\"connect-killer\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n");
+    let _ = writeln!(gpx, "  <trk>\n    <name>{}</name>\n    <trkseg>", xml_escape(name));
+    for point in points {
+        let _ = writeln!(
+            gpx,
+            "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\"><time>{}</time><extensions><speed>{:.2}</speed></extensions></trkpt>",
+            point.lat,
+            point.lng,
+            point.timestamp(),
+            point.speed,
+        );
+    }
+    gpx.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+    gpx
+}
+
+/// A single LineString feature, with the time and speed of each point alongside it.
+pub fn to_geojson(name: &str, points: &[TrackPoint]) -> String {
+    let feature = json!({
+        "type": "FeatureCollection",
+        "features": [{
+            "type": "Feature",
+            "properties": {
+                "name": name,
+                "times": points.iter().map(TrackPoint::timestamp).collect::<Vec<_>>(),
+                "speeds": points.iter().map(|p| p.speed).collect::<Vec<_>>(),
+            },
+            "geometry": {
+                "type": "LineString",
+                "coordinates": points.iter().map(|p| [p.lng, p.lat]).collect::<Vec<_>>(),
+            },
+        }],
+    });
+    feature.to_string()
+}
+
+/// A gx:Track so the times survive, Google Earth and most other readers understand it.
+pub fn to_kml(name: &str, points: &[TrackPoint]) -> String {
+    let mut kml = String::new();
+    kml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    kml.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n");
+    let _ = writeln!(kml, "  <Document>\n    <name>{}</name>\n    <Placemark>", xml_escape(name));
+    let _ = writeln!(kml, "      <name>{}</name>\n      <gx:Track>", xml_escape(name));
+    for point in points {
+        let _ = writeln!(kml, "        <when>{}</when>", point.timestamp());
+    }
+    for point in points {
+        let _ = writeln!(kml, "        <gx:coord>{:.7} {:.7} 0</gx:coord>", point.lng, point.lat);
+    }
+    kml.push_str("      </gx:Track>\n    </Placemark>\n  </Document>\n</kml>\n");
+    kml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, lng: f64) -> TrackPoint {
+        TrackPoint { time_millis: 0, lat, lng, speed: 0.0 }
+    }
+
+    #[test]
+    fn simplify_keeps_corners() {
+        // A straight line east with a little jitter, then a turn north
+        let points = vec![
+            point(0.0, 0.0),
+            point(0.000_001, 0.001),
+            point(0.0, 0.002),
+            point(0.000_001, 0.003),
+            point(0.001, 0.003),
+            point(0.002, 0.003),
+        ];
+        let simplified = simplify(&points, 5.0);
+        assert_eq!(simplified, vec![points[0], points[3], points[5]]);
+        assert_eq!(simplify(&points, 0.0), points);
+    }
+}
@@ -0,0 +1,161 @@
+//! Remuxing the camera uploads into mp4 without re-encoding them.
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use ffmpeg_next::{codec, encoder, format as ffmpeg_format, media, Dictionary, Error as FfmpegError, Rational};
+
+/// The raw hevc files have no timestamps, the cameras run at 20fps.
+pub const CAMERA_FPS: i32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mp4Layout {
+    /// A regular mp4 with the index at the end, for downloads.
+    Progressive,
+    /// An empty moov followed by a fragment per keyframe, for HLS.
+    Fragmented,
+}
+
+/// Copy the video packets between `start_millis` and `end_millis` out of `inputs` (file, time
+/// the file starts at) into one mp4 and return its duration in milliseconds. Without
+/// re-encoding it can only start on a keyframe, so it starts at the first one at or after
+/// `start_millis`. The openpilot encoders don't use b-frames, so presentation order is
+/// decode order.
+pub fn remux_to_mp4(
+    inputs: &[(PathBuf, i64)],
+    start_millis: i64,
+    end_millis: i64,
+    output: &Path,
+    layout: Mp4Layout,
+) -> Result<i64, FfmpegError> {
+    ffmpeg_next::init()?;
+    let mut octx = ffmpeg_format::output_as(&output, "mp4")?;
+    let frame_millis = 1000 / CAMERA_FPS as i64;
+    let mut out_time_base = Rational::new(1, 1000);
+    let mut first_millis: Option<i64> = None;
+    let mut last_millis = 0;
+    let mut last_pts = -1;
+
+    for (path, file_start_millis) in inputs {
+        let mut options = Dictionary::new();
+        options.set("framerate", &CAMERA_FPS.to_string());
+        let mut ictx = ffmpeg_format::input_with_dictionary(path, options)?;
+        let input = ictx.streams().best(media::Type::Video).ok_or(FfmpegError::StreamNotFound)?;
+        let input_index = input.index();
+        let input_time_base = input.time_base();
+
+        if octx.nb_streams() == 0 {
+            // The segments of a camera all come from the same encoder settings
+            let mut output_stream = octx.add_stream(encoder::find(codec::Id::None))?;
+            output_stream.set_parameters(input.parameters());
+            output_stream.set_time_base(Rational::new(1, 1000));
+            unsafe {
+                (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+            match layout {
+                Mp4Layout::Progressive => octx.write_header()?,
+                Mp4Layout::Fragmented => {
+                    let mut movflags = Dictionary::new();
+                    movflags.set("movflags", "frag_keyframe+empty_moov+default_base_moof");
+                    octx.write_header_with(movflags)?;
+                }
+            };
+            out_time_base = octx.stream(0).ok_or(FfmpegError::StreamNotFound)?.time_base();
+        }
+        let to_out_ts = |millis: i64| millis * out_time_base.denominator() as i64 / (1000 * out_time_base.numerator() as i64);
+
+        let mut file_first_ts: Option<i64> = None;
+        let mut frame_index = 0;
+        for (stream, mut packet) in ictx.packets() {
+            if stream.index() != input_index {
+                continue;
+            }
+            // Raw hevc has no timestamps of its own, fall back to counting frames
+            let offset_millis = match packet.pts().or(packet.dts()) {
+                Some(ts) => {
+                    let first_ts = *file_first_ts.get_or_insert(ts);
+                    (ts - first_ts) * 1000 * input_time_base.numerator() as i64 / input_time_base.denominator() as i64
+                }
+                None => frame_index * frame_millis,
+            };
+            frame_index += 1;
+            let millis = file_start_millis + offset_millis;
+            if millis >= end_millis {
+                break;
+            }
+            if first_millis.is_none() && (millis < start_millis || !packet.is_key()) {
+                continue;
+            }
+            last_millis = millis - *first_millis.get_or_insert(millis);
+
+            let mut pts = to_out_ts(last_millis);
+            if pts <= last_pts {
+                pts = last_pts + 1;
+            }
+            last_pts = pts;
+            packet.set_stream(0);
+            packet.set_pts(Some(pts));
+            packet.set_dts(Some(pts));
+            packet.set_duration(to_out_ts(frame_millis));
+            packet.set_position(-1);
+            packet.write_interleaved(&mut octx)?;
+        }
+    }
+
+    if first_millis.is_none() {
+        // Nothing but packets before the start, or no keyframe at all
+        return Err(FfmpegError::InvalidData);
+    }
+    octx.write_trailer()?;
+    Ok(last_millis + frame_millis)
+}
+
+/// Offset of the first `moof` box of a fragmented mp4. Everything before it (ftyp and moov)
+/// is the HLS init section, the rest are the media fragments.
+pub fn first_fragment_offset(path: &Path) -> io::Result<Option<u64>> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let mut offset = 0;
+    while offset + 8 <= file_size {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8])?;
+        let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        if &header[4..8] == b"moof" {
+            return Ok(Some(offset));
+        }
+        match size {
+            0 => return Ok(None), // the last box runs to the end of the file
+            1 => {
+                file.read_exact(&mut header[8..16])?;
+                size = u64::from_be_bytes(header[8..16].try_into().unwrap_or_default());
+            }
+            _ => {}
+        }
+        if size < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "mp4 box smaller than its header"));
+        }
+        offset += size;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn finds_first_fragment() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for (kind, size) in [(b"ftyp", 24u32), (b"moov", 40), (b"moof", 16), (b"mdat", 32)] {
+            file.write_all(&size.to_be_bytes()).unwrap();
+            file.write_all(kind).unwrap();
+            file.write_all(&vec![0u8; size as usize - 8]).unwrap();
+        }
+        file.flush().unwrap();
+        assert_eq!(first_fragment_offset(file.path()).unwrap(), Some(64));
+    }
+}
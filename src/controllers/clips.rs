@@ -0,0 +1,183 @@
+#![allow(clippy::unused_async)]
+use std::env;
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+};
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    middleware::{auth::MyJWT, jwt},
+    models::{
+        clips::{CLM, CLIP_DONE},
+        devices::DM,
+        routes::RM,
+    },
+    workers::clip_renderer::{ClipCamera, ClipRendererWorker, ClipRendererWorkerArgs},
+};
+
+/// Longest clip that can be asked for, 10 minutes.
+const MAX_CLIP_MILLIS: i64 = 10 * 60 * 1000;
+/// How long the signed download link works for.
+const CLIP_URL_EXPIRY_SECS: u64 = 3600 * 24;
+
+#[derive(Deserialize)]
+pub struct ClipRequest {
+    camera: String,
+    /// Offsets into the route
+    start_millis: i64,
+    end_millis: i64,
+}
+
+#[derive(Serialize)]
+pub struct ClipResponse {
+    id: i32,
+    route: String,
+    camera: String,
+    start_millis: i64,
+    end_millis: i64,
+    status: String,
+    progress: f32,
+    error: Option<String>,
+    size: Option<i64>,
+    /// Signed download link once the clip is done
+    url: Option<String>,
+    create_time: i64,
+}
+
+impl ClipResponse {
+    fn new(clip: CLM, token: &str) -> Self {
+        let url = (clip.status == CLIP_DONE).then(|| {
+            format!(
+                "{}/v1/clips/{}/clip.mp4?sig={}",
+                env::var("API_ENDPOINT").expect("API_ENDPOINT env variable not set"),
+                clip.id,
+                token
+            )
+        });
+        ClipResponse {
+            id: clip.id,
+            route: clip.route_fullname,
+            camera: clip.camera,
+            start_millis: clip.start_millis,
+            end_millis: clip.end_millis,
+            status: clip.status,
+            progress: clip.progress,
+            error: clip.error,
+            size: clip.size,
+            url,
+            create_time: clip.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+/// Only the owner of the device (or a superuser) can make and see clips of its routes.
+async fn ensure_route_owner(ctx: &AppContext, auth: &MyJWT, route: &RM) -> Result<()> {
+    let Some(user_model) = &auth.user_model else {
+        return Err(Error::Unauthorized("Devices can't do this".to_string()));
+    };
+    if !user_model.superuser {
+        DM::ensure_user_device(&ctx.db, user_model.id, &route.device_dongle_id).await?;
+    }
+    Ok(())
+}
+
+fn sign(ctx: &AppContext, auth: &MyJWT) -> Result<String> {
+    let jwt_secret = ctx.config.get_jwt_config()?;
+    jwt::JWT::new(&jwt_secret.secret)
+        .generate_token(&CLIP_URL_EXPIRY_SECS, auth.claims.identity.to_string())
+        .map_err(|e| Error::Message(e.to_string()))
+}
+
+/// Queue a clip of `camera` between two offsets into the route.
+pub async fn create_clip(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path(fullname): Path<String>,
+    Json(request): Json<ClipRequest>,
+) -> Result<Response> {
+    let route = RM::find_route(&ctx.db, &fullname).await?;
+    ensure_route_owner(&ctx, &auth, &route).await?;
+    let Some(camera) = ClipCamera::from_name(&request.camera) else {
+        return loco_rs::controller::bad_request("camera must be one of qcamera, fcamera, ecamera or dcamera");
+    };
+    if request.start_millis < 0 || request.end_millis <= request.start_millis {
+        return loco_rs::controller::bad_request("end_millis has to be after start_millis");
+    }
+    if request.end_millis - request.start_millis > MAX_CLIP_MILLIS {
+        return loco_rs::controller::bad_request("clips can be at most 10 minutes long");
+    }
+
+    let clip = CLM::add_clip(
+        &ctx.db,
+        &route.fullname,
+        &route.device_dongle_id,
+        camera.name(),
+        request.start_millis,
+        request.end_millis,
+    )
+    .await?;
+    ClipRendererWorker::perform_later(&ctx, ClipRendererWorkerArgs { clip_id: clip.id }).await?;
+    format::json(ClipResponse::new(clip, ""))
+}
+
+pub async fn list_route_clips(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path(fullname): Path<String>,
+) -> Result<Response> {
+    let route = RM::find_route(&ctx.db, &fullname).await?;
+    ensure_route_owner(&ctx, &auth, &route).await?;
+    let token = sign(&ctx, &auth)?;
+    let clips = CLM::find_route_clips(&ctx.db, &fullname).await?;
+    format::json(
+        clips
+            .into_iter()
+            .map(|clip| ClipResponse::new(clip, &token))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Status and progress of a clip, with its download link when it's done.
+pub async fn get_clip(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    let clip = CLM::find_clip(&ctx.db, id).await?;
+    let route = RM::find_route(&ctx.db, &clip.route_fullname).await?;
+    ensure_route_owner(&ctx, &auth, &route).await?;
+    let token = sign(&ctx, &auth)?;
+    format::json(ClipResponse::new(clip, &token))
+}
+
+/// The rendered mp4. Reached through the signed link, which the auth middleware accepts as `sig`.
+pub async fn download_clip(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let clip = CLM::find_clip(&ctx.db, id).await?;
+    let route = RM::find_route(&ctx.db, &clip.route_fullname).await?;
+    if !route.is_public {
+        ensure_route_owner(&ctx, &auth, &route).await?;
+    }
+    let Some(storage_key) = clip.storage_key.filter(|_| clip.status == CLIP_DONE) else {
+        return loco_rs::controller::not_found();
+    };
+    match super::connectdata::asset_download(storage_key, headers).await {
+        Ok(response) => Ok(response),
+        Err((status, msg)) => Ok((status, msg).into_response()),
+    }
+}
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("v1")
+        .add("/route/:fullname/clips", get(list_route_clips).post(create_clip))
+        .add("/clips/:id", get(get_clip))
+        .add("/clips/:id/clip.mp4", get(download_clip))
+}
@@ -0,0 +1,149 @@
+#![allow(clippy::unused_async)]
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Extension,
+};
+use loco_rs::prelude::*;
+use serde::Serialize;
+
+use super::ws::ConnectionManager;
+use crate::{
+    middleware::auth::MyJWT,
+    models::cloudlog_alert_rules::{AlertRuleParams, CARM},
+};
+
+#[derive(Serialize)]
+pub struct AlertRuleResponse {
+    id: i32,
+    name: String,
+    enabled: bool,
+    level: Option<String>,
+    min_levelnum: Option<i32>,
+    module: Option<String>,
+    func_name: Option<String>,
+    msg_regex: Option<String>,
+    branch: Option<String>,
+    commit: Option<String>,
+    threshold: i32,
+    window_secs: i32,
+    cooldown_secs: i32,
+    webhook_url: String,
+    /// Unix time, 0 if it never fired
+    last_fired_time: i64,
+    /// Why the last webhook call failed
+    last_error: Option<String>,
+    create_time: i64,
+    update_time: i64,
+}
+
+impl From<CARM> for AlertRuleResponse {
+    fn from(rule: CARM) -> Self {
+        AlertRuleResponse {
+            id: rule.id,
+            name: rule.name,
+            enabled: rule.enabled,
+            level: rule.level,
+            min_levelnum: rule.min_levelnum,
+            module: rule.module,
+            func_name: rule.func_name,
+            msg_regex: rule.msg_regex,
+            branch: rule.branch,
+            commit: rule.commit,
+            threshold: rule.threshold,
+            window_secs: rule.window_secs,
+            cooldown_secs: rule.cooldown_secs,
+            webhook_url: rule.webhook_url,
+            last_fired_time: rule.last_fired_at,
+            last_error: rule.last_error,
+            create_time: rule.created_at.and_utc().timestamp(),
+            update_time: rule.updated_at.and_utc().timestamp(),
+        }
+    }
+}
+
+fn ensure_superuser(auth: &MyJWT) -> Result<()> {
+    if !auth.user_model.as_ref().map_or(false, |user_model| user_model.superuser) {
+        return Err(Error::Unauthorized("Only superusers can manage cloudlog alerts".to_string()));
+    }
+    Ok(())
+}
+
+fn validate(params: &AlertRuleParams) -> std::result::Result<(), &'static str> {
+    if params.threshold < 0 || params.window_secs <= 0 || params.cooldown_secs < 0 {
+        return Err("threshold and cooldown_secs can't be negative, window_secs has to be positive");
+    }
+    if !params.webhook_url.starts_with("https://") && !params.webhook_url.starts_with("http://") {
+        return Err("webhook_url has to be an http(s) URL");
+    }
+    if let Some(msg_regex) = &params.msg_regex {
+        if regex::Regex::new(msg_regex).is_err() {
+            return Err("msg_regex is not a valid regex");
+        }
+    }
+    Ok(())
+}
+
+pub async fn list_rules(auth: MyJWT, State(ctx): State<AppContext>) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    let rules = CARM::find_rules(&ctx.db).await?;
+    format::json(rules.into_iter().map(AlertRuleResponse::from).collect::<Vec<_>>())
+}
+
+/// New rules apply on this instance right away and on the others within a minute.
+pub async fn create_rule(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Extension(manager): Extension<Arc<ConnectionManager>>,
+    Json(params): Json<AlertRuleParams>,
+) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    if let Err(message) = validate(&params) {
+        return loco_rs::controller::bad_request(message);
+    }
+    let rule = CARM::add_rule(&ctx.db, params).await?;
+    manager.cloudlog_alerts.invalidate().await;
+    format::json(AlertRuleResponse::from(rule))
+}
+
+pub async fn get_rule(auth: MyJWT, State(ctx): State<AppContext>, Path(id): Path<i32>) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    format::json(AlertRuleResponse::from(CARM::find_rule(&ctx.db, id).await?))
+}
+
+pub async fn update_rule(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Extension(manager): Extension<Arc<ConnectionManager>>,
+    Path(id): Path<i32>,
+    Json(params): Json<AlertRuleParams>,
+) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    if let Err(message) = validate(&params) {
+        return loco_rs::controller::bad_request(message);
+    }
+    let rule = CARM::update_rule(&ctx.db, id, params).await?;
+    manager.cloudlog_alerts.invalidate().await;
+    format::json(AlertRuleResponse::from(rule))
+}
+
+pub async fn delete_rule(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Extension(manager): Extension<Arc<ConnectionManager>>,
+    Path(id): Path<i32>,
+) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    CARM::find_rule(&ctx.db, id).await?;
+    CARM::delete_rule(&ctx.db, id).await?;
+    manager.cloudlog_alerts.invalidate().await;
+    format::empty()
+}
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("v1/admin")
+        .add("/cloudlog_alerts", get(list_rules).post(create_rule))
+        .add("/cloudlog_alerts/:id", get(get_rule).put(update_rule).delete(delete_rule))
+}
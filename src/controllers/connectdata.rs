@@ -1,11 +1,14 @@
 #![allow(clippy::unused_async)]
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, convert::Infallible, env, sync::Arc};
 
 use loco_rs::prelude::*;
 use axum::{
     extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Response},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::get, Extension,
 
   };
@@ -15,18 +18,25 @@ use serde_json::Value as JsonValue;
 use crate::{
     common::{
         re::*,
-        mkv_helpers
+        storage::{self, ByteRange, StorageError},
     },
     enforce_ownership_rule,
     models::{
+        cloudlogs::{CloudlogSearch, CLGM},
         devices::DM,
         routes::RM,
+        storage_objects::SOM,
     }
 };
 
-use super::ws::ConnectionManager;
+use futures::stream::{self, Stream};
+use tokio::sync::broadcast::error::RecvError;
+use super::ws::{CloudlogLine, ConnectionManager};
 use chrono::{Timelike, Datelike, Utc, TimeZone};
 
+const DEFAULT_CLOUDLOG_RETENTION_DAYS: i64 = 30;
+const MAX_CLOUDLOG_LIMIT: usize = 5000;
+
 
 #[derive(Deserialize)]
 pub struct UlogQuery {
@@ -84,76 +94,92 @@ pub struct SegmentLogResponse {
     pub has_more: bool,
 }
 
+fn content_type_for_key(lookup_key: &str) -> &'static str {
+    match lookup_key.rsplit('.').next() {
+        Some("ts") => "video/mp2t",
+        Some("mp4") => "video/mp4",
+        Some("m4s") => "video/iso.segment",
+        Some("json") => "application/json",
+        Some("jpg") => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
 pub async fn asset_download(
     lookup_key: String,
-    client: &reqwest::Client,
     headers: HeaderMap,
-) -> Result<Response<reqwest::Body>, (StatusCode, &'static str)> {
+) -> Result<Response, (StatusCode, &'static str)> {
     // Validate the lookup_key to allow only alphanumeric, '_', '-' and '.' characters
     let valid_key_pattern = regex::Regex::new(r"^[a-zA-Z0-9_.-]+$").unwrap();
     if !valid_key_pattern.is_match(&lookup_key) {
         return Err((StatusCode::BAD_REQUEST, "Invalid lookup key"));
     }
-    let internal_file_url = mkv_helpers::get_mkv_file_url(&lookup_key);
-
-    // Prepare a request to fetch the file from storage
-    let mut request_builder = client.get(&internal_file_url);
 
     // Check for range header and forward it if present
-    let has_range = headers.get(hyper::header::RANGE).is_some();
-    if has_range {
-        if let Some(range) = headers.get(hyper::header::RANGE) {
-            request_builder = request_builder.header(hyper::header::RANGE, range.clone());
+    let range = headers
+        .get(hyper::header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(ByteRange::from_header);
+
+    let object = match storage::blob_store().get_range(&lookup_key, range).await {
+        Ok(object) => object,
+        Err(StorageError::NotFound(_)) => return Err((StatusCode::NOT_FOUND, "File not found")),
+        Err(StorageError::InvalidRange) => return Err((StatusCode::RANGE_NOT_SATISFIABLE, "Range not satisfiable")),
+        Err(e) => {
+            tracing::error!("Failed to fetch {lookup_key} from storage: {e}");
+            return Err((StatusCode::BAD_GATEWAY, "Internal server error"));
         }
-    }
-
-    let res = request_builder.send().await;
+    };
 
-    match res {
-        Ok(response) => {
-            let mut status = response.status();
-            if has_range && status == StatusCode::OK { // override the file servers response
-                status = StatusCode::PARTIAL_CONTENT;
-            }
+    let status = if range.is_some() { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK };
+    let mut response_builder = Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, content_type_for_key(&lookup_key))
+        .header(hyper::header::CONTENT_LENGTH, object.content_length)
+        .header(hyper::header::ACCEPT_RANGES, "bytes");
+    if range.is_some() {
+        let (start, end) = object.range.unwrap_or((0, object.total_size.saturating_sub(1)));
+        response_builder = response_builder.header(
+            hyper::header::CONTENT_RANGE,
+            format!("bytes {start}-{end}/{}", object.total_size)
+        );
+    }
+    if lookup_key.ends_with(".ts") {
+        response_builder = response_builder.header(
+            hyper::header::CONTENT_DISPOSITION,
+            format!("inline; filename=\"{lookup_key}\"")
+        );
+    } else {
+        response_builder = response_builder.header(
+            hyper::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{lookup_key}\"")
+        );
+    }
+    response_builder = response_builder.header(hyper::header::CACHE_CONTROL, "public, max-age=31536000");
+    let proxy_response = response_builder.body(axum::body::Body::from_stream(object.stream)).unwrap();
+    Ok(proxy_response)
+}
 
-            let mut response_builder = Response::builder().status(status);
-            let mut is_video = false;
-            let mut content_type = None;
-            if lookup_key.ends_with(".ts") {
-                is_video = true;
-                content_type = Some("video/mp2t");
-            }
-            for (key, value) in response.headers().iter() {
-                if key == hyper::header::TRANSFER_ENCODING {
-                    continue;
-                }
-                // Don't forward backend's Content-Type or Content-Disposition for video files
-                if is_video && (key == hyper::header::CONTENT_TYPE || key == hyper::header::CONTENT_DISPOSITION) {
-                    continue;
+/// Delete every stored file whose key starts with `prefix` and release its storage from the
+/// device's usage. Returns how many were found.
+pub async fn delete_files_with_prefix(
+    db: &DatabaseConnection,
+    prefix: &str,
+) -> std::result::Result<usize, StorageError> {
+    let store = storage::blob_store();
+    let keys = store.list_prefix(prefix).await?;
+    tracing::info!("Deleting {} files from kv store", keys.len());
+    for key in &keys {
+        match store.delete(key).await {
+            Ok(()) | Err(StorageError::NotFound(_)) => {
+                if let Err(e) = SOM::untrack(db, key).await {
+                    tracing::error!("Failed to release storage for {key}: {e}");
                 }
-                response_builder = response_builder.header(key, value);
             }
-            if let Some(ct) = content_type {
-                response_builder = response_builder.header(hyper::header::CONTENT_TYPE, ct);
-            }
-            if is_video {
-                response_builder = response_builder.header(
-                    hyper::header::CONTENT_DISPOSITION,
-                    format!("inline; filename=\"{lookup_key}\"")
-                );
-            } else {
-                response_builder = response_builder.header(
-                    hyper::header::CONTENT_DISPOSITION,
-                    format!("attachment; filename=\"{lookup_key}\"")
-                );
-            }
-            response_builder = response_builder.header(hyper::header::CACHE_CONTROL, "public, max-age=31536000");
-            let body = reqwest::Body::wrap_stream(response.bytes_stream());
-            let proxy_response = response_builder.body(body).unwrap();
-            Ok(proxy_response)
+            Err(e) => tracing::error!("Failed to delete {key}: {e}"),
         }
-        Err(_) => Err((StatusCode::BAD_GATEWAY, "Internal server error")),
     }
+    Ok(keys.len())
 }
 
 pub async fn ensure_user_is_owner(
@@ -193,41 +219,48 @@ pub async fn is_owner(
     false
 }
 
+/// The rlog parser writes full rate `rlog_` versions of the qlog derived files. Serve those
+/// when the segment has them and fall back to the qlog ones otherwise.
+pub(crate) async fn prefer_rlog_derived(canonical_route_name: &str, segment: &str, file: &str) -> String {
+    // canonical_route_name include dongleid already
+    let rlog_key = format!("{canonical_route_name}--{segment}--rlog_{file}");
+    match storage::blob_store().stat(&rlog_key).await {
+        Ok(Some(_)) => rlog_key,
+        _ => format!("{canonical_route_name}--{segment}--{file}"),
+    }
+}
+
 pub async fn events_download(
     Path((_dongle_id, canonical_route_name, segment)): Path<(String, String, String)>,
     State(_ctx): State<AppContext>,
-    Extension(client): Extension<reqwest::Client>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let lookup_key = format!("{canonical_route_name}--{segment}--events.json");  // canonical_route_name include dongleid already
-    return asset_download(lookup_key, &client, headers).await;
+    let lookup_key = prefer_rlog_derived(&canonical_route_name, &segment, "events.json").await;
+    return asset_download(lookup_key, headers).await;
 }
 
 pub async fn coords_download(
     Path((_dongle_id, canonical_route_name, segment)): Path<(String, String, String)>,
     State(_ctx): State<AppContext>,
-    Extension(client): Extension<reqwest::Client>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let lookup_key = format!("{canonical_route_name}--{segment}--coords.json");  // canonical_route_name include dongleid already
-    return asset_download(lookup_key, &client, headers).await;
+    let lookup_key = prefer_rlog_derived(&canonical_route_name, &segment, "coords.json").await;
+    return asset_download(lookup_key, headers).await;
 }
 
 pub async fn sprite_download(
     Path((_dongle_id, canonical_route_name, segment)): Path<(String, String, String)>,
     State(_ctx): State<AppContext>,
-    Extension(client): Extension<reqwest::Client>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
     let lookup_key = format!("{canonical_route_name}--{segment}--sprite.jpg");  // canonical_route_name include dongleid already
-    return asset_download(lookup_key, &client, headers).await;
+    return asset_download(lookup_key, headers).await;
 }
 
 pub async fn auth_file_download(
     auth: crate::middleware::auth::MyJWT,
     Path((dongle_id, route_name, segment, file)): Path<(String, String, String, String)>,
     State(ctx): State<AppContext>,
-    Extension(client): Extension<reqwest::Client>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
     let owner = is_owner(&auth, &ctx.db, &dongle_id).await;
@@ -239,14 +272,13 @@ pub async fn auth_file_download(
     }
 
     let lookup_key = format!("{dongle_id}_{route_name}--{segment}--{file}");
-    return asset_download(lookup_key, &client, headers).await;
+    return asset_download(lookup_key, headers).await;
 }
 
 pub async fn bootlog_file_download(
     auth: crate::middleware::auth::MyJWT,
     Path(bootlog_file): Path<String>,
     State(ctx): State<AppContext>,
-    Extension(client): Extension<reqwest::Client>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
     let bootlog_re_string = format!(r"({DONGLE_ID})_boot_{ROUTE_NAME}");
@@ -260,7 +292,7 @@ pub async fn bootlog_file_download(
 
             // Pass the captured dongle_id to ensure_user_is_owner
             ensure_user_is_owner(&auth, &ctx.db, dongle_id).await?;
-            return asset_download(bootlog_file, &client, headers).await;
+            return asset_download(bootlog_file, headers).await;
         }
     }
 
@@ -278,7 +310,7 @@ pub async fn bootlog_file_download(
 // ) -> impl IntoResponse {
 //     ensure_user_is_owner(&auth, &ctx.db, &dongle_id).await?;
 //     let lookup_key = format!("{dongle_id}_{route_name}--{segment}--{file}");  // Note that route_name does not include dongle_id
-//     return asset_download(lookup_key, &client, headers).await;
+//     return asset_download(lookup_key, headers).await;
 // }
 
 
@@ -286,7 +318,6 @@ async fn delete_route(
     auth: crate::middleware::auth::MyJWT,
     State(ctx): State<AppContext>,
     Path((dongle_id, timestamp)): Path<(String, String)>,
-    Extension(client): Extension<reqwest::Client>,
 ) -> impl IntoResponse {
     let dongle_id = if let Some(device_model) = auth.device_model {
         device_model.dongle_id
@@ -307,39 +338,21 @@ async fn delete_route(
     let canonical_route_name = format!("{}|{}", &dongle_id, &timestamp);
     RM::delete_route(&ctx.db, &canonical_route_name).await?; // should cascade to segments
 
-    let query = mkv_helpers::list_keys_starting_with(&canonical_route_name.replace("|", "_"));
-    let response = client.get(&query).send().await.unwrap();
-    if !response.status().is_success() {
-        tracing::info!("Failed to get keys");
-        return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to get keys").into_response());
-    }
-    let body = response.text().await.unwrap();
-    let json: serde_json::Value = serde_json::from_str(&body)?; // Convert response text into JSON
-
-    let keys = json["keys"].as_array().unwrap();
-    tracing::info!("Deleting {} files from kv store", keys.len());
-
-    for key_value in keys {
-        let file_name = key_value.as_str().unwrap().trim_start_matches('/').to_string(); // Convert to string for independent ownership
-        let file_url = mkv_helpers::get_mkv_file_url(&file_name);
-
-        let _ = client
-            .delete(&file_url)
-            .send()
-            .await
-            .map_err(|e|
-                return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-            );
-    }
+    let deleted = match delete_files_with_prefix(&ctx.db, &canonical_route_name.replace("|", "_")).await {
+        Ok(deleted) => deleted,
+        Err(e) => {
+            tracing::info!("Failed to get keys: {e}");
+            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to get keys").into_response());
+        }
+    };
 
-    return Ok((StatusCode::OK, format!("Deleted {} files", keys.len())).into_response());
+    return Ok((StatusCode::OK, format!("Deleted {} files", deleted)).into_response());
 }
 
 async fn delete_data(
     auth: crate::middleware::auth::MyJWT,
     State(ctx): State<AppContext>,
     Path(dongle_id): Path<String>,
-    Extension(client): Extension<reqwest::Client>,
 ) -> impl IntoResponse {
 
     let dongle_id = if let Some(device_model) = auth.device_model {
@@ -358,41 +371,26 @@ async fn delete_data(
             );
         }
         let mut active_device_model = device_model.into_active_model();
-        active_device_model.server_storage = ActiveValue::Set(0);
         active_device_model.locations = ActiveValue::Set(None);
         active_device_model.alias = ActiveValue::Set("".to_string());
         active_device_model.owner_id = ActiveValue::Set(None);
         active_device_model.update(&ctx.db).await?;
     }
 
-    let query = mkv_helpers::list_keys_starting_with(&dongle_id);
-    let response = client.get(&query).send().await.unwrap();
-    if !response.status().is_success() {
-        tracing::info!("Failed to get keys");
-        return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to get keys").into_response());
-    }
-    let body = response.text().await.unwrap();
-    let json: serde_json::Value = serde_json::from_str(&body)?; // Convert response text into JSON
-
-    let keys = json["keys"].as_array().unwrap();
-    tracing::info!("Deleting {} files from kv store", keys.len());
-
-    for key_value in keys {
-        let file_name = key_value.as_str().unwrap().trim_start_matches('/').to_string(); // Convert to string for independent ownership
-        let file_url = mkv_helpers::get_mkv_file_url(&file_name);
-
-        let _ = client
-            .delete(&file_url)
-            .send()
-            .await
-            .map_err(|e|
-                return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-            );
-    }
+    let deleted = match delete_files_with_prefix(&ctx.db, &dongle_id).await {
+        Ok(deleted) => deleted,
+        Err(e) => {
+            tracing::info!("Failed to get keys: {e}");
+            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to get keys").into_response());
+        }
+    };
+    // Files stored before objects were tracked release nothing when untracked, so a wiped
+    // device is set back to zero outright
+    SOM::replace_device_objects(&ctx.db, &dongle_id, &[]).await?;
     // We cant delete the device model but we still want to delete all the routes and segments. We want to keep the device in the db so it can
     // be used for the new customer that pairs the device.
     RM::delete_device_routes(&ctx.db, &dongle_id).await?;
-    return Ok((StatusCode::OK, format!("Deleted {} files", keys.len())).into_response());
+    return Ok((StatusCode::OK, format!("Deleted {} files", deleted)).into_response());
 }
 
 
@@ -510,14 +508,175 @@ pub struct CloudlogAllQuery {
     pub limit: Option<usize>,
 }
 
-pub async fn get_all_cloudlogs(
-    auth: crate::middleware::auth::MyJWT,
-    State(_ctx): State<AppContext>,
-    Query(mut query): Query<CloudlogAllQuery>,
-    Extension(manager): Extension<Arc<ConnectionManager>>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    // Only allow superusers for all-devices query
-    // Allow specific users to access all logs for FrogPilot-Testing branch or any branch that starts with "FrogPilot"
+impl CloudlogAllQuery {
+    /// Whether a cloudlog line from `dongle_id` passes the filters. Pagination isn't part of it.
+    pub fn matches(&self, dongle_id: &str, branch: &str, module: &str, log: &Value) -> bool {
+        if self.dongle_id.as_deref().map_or(false, |filter_dongle| filter_dongle != dongle_id) {
+            return false;
+        }
+        if self.branch.as_deref().map_or(false, |filter_branch| filter_branch != branch) {
+            return false;
+        }
+        if self.module.as_deref().map_or(false, |filter_module| filter_module != module) {
+            return false;
+        }
+        // Filter by level
+        if let Some(ref filter_level) = self.level {
+            if let Some(level) = log.get("level").and_then(|v| v.as_str()) {
+                if level != filter_level { return false; }
+            } else { return false; }
+        }
+        // Filter by levelnum
+        if let Some(filter_levelnum) = self.levelnum {
+            if let Some(levelnum) = log.get("levelnum").and_then(|v| v.as_u64()) {
+                if let Some(ref op) = self.levelnum_op {
+                    match op.as_str() {
+                        "eq" => if levelnum != filter_levelnum { return false; },
+                        "gt" => if levelnum <= filter_levelnum { return false; },
+                        "lt" => if levelnum >= filter_levelnum { return false; },
+                        _ => return false,
+                    }
+                } else {
+                    if levelnum != filter_levelnum { return false; }
+                }
+            } else { return false; }
+        }
+        // Filter by funcName (now func_name)
+        if let Some(ref filter_func) = self.func_name {
+            if let Some(func) = log.get("funcName").and_then(|v| v.as_str()) {
+                if func != filter_func { return false; }
+            } else { return false; }
+        }
+        // Filter by date
+        if let Some(created) = log.get("created").and_then(|v| v.as_f64()) {
+            if let Some(date_from) = self.date_from {
+                if created < date_from { return false; }
+            }
+            if let Some(date_to) = self.date_to {
+                if created > date_to { return false; }
+            }
+            // Updated chrono usage
+            let dt = Utc.timestamp_opt(created as i64, (created.fract() * 1e9) as u32).single().map(|dt_utc| dt_utc.naive_utc());
+            if let Some(dt) = dt {
+                if let Some(year) = self.year {
+                    if dt.year() != year { return false; }
+                }
+                if let Some(month) = self.month {
+                    if dt.month() != month { return false; }
+                }
+                if let Some(day) = self.day {
+                    if dt.day() != day { return false; }
+                }
+                if let Some(hour) = self.hour {
+                    if dt.hour() != hour { return false; }
+                }
+                if let Some(minute) = self.minute {
+                    if dt.minute() != minute { return false; }
+                }
+                if let Some(second) = self.second {
+                    if dt.second() != second { return false; }
+                }
+                if let Some(minute_from) = self.minute_from {
+                    if dt.minute() < minute_from { return false; }
+                }
+                if let Some(minute_to) = self.minute_to {
+                    if dt.minute() > minute_to { return false; }
+                }
+                if let Some(second_from) = self.second_from {
+                    if dt.second() < second_from { return false; }
+                }
+                if let Some(second_to) = self.second_to {
+                    if dt.second() > second_to { return false; }
+                }
+            } else { return false; } // If timestamp is invalid, skip
+        }
+        // Filter by ctx fields
+        if let Some(ctx) = log.get("ctx") {
+            if let Some(ref ctx_branch) = self.ctx_branch {
+                if ctx.get("branch").and_then(|v| v.as_str()) != Some(ctx_branch) { return false; }
+            }
+            if let Some(ref ctx_commit) = self.ctx_commit {
+                if ctx.get("commit").and_then(|v| v.as_str()) != Some(ctx_commit) { return false; }
+            }
+            if let Some(ref ctx_device) = self.ctx_device {
+                if ctx.get("device").and_then(|v| v.as_str()) != Some(ctx_device) { return false; }
+            }
+            if let Some(ctx_dirty) = self.ctx_dirty {
+                if ctx.get("dirty").and_then(|v| v.as_bool()) != Some(ctx_dirty) { return false; }
+            }
+            if let Some(ref ctx_dongle_id) = self.ctx_dongle_id {
+                if ctx.get("dongle_id").and_then(|v| v.as_str()) != Some(ctx_dongle_id) { return false; }
+            }
+            if let Some(ref ctx_origin) = self.ctx_origin {
+                if ctx.get("origin").and_then(|v| v.as_str()) != Some(ctx_origin) { return false; }
+            }
+            if let Some(ref ctx_version) = self.ctx_version {
+                if ctx.get("version").and_then(|v| v.as_str()) != Some(ctx_version) { return false; }
+            }
+        } else {
+            // If ctx filter is set but log has no ctx, skip
+            if self.ctx_branch.is_some() || self.ctx_commit.is_some() || self.ctx_device.is_some() || self.ctx_dirty.is_some() || self.ctx_dongle_id.is_some() || self.ctx_origin.is_some() || self.ctx_version.is_some() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The same filters for the stored cloudlogs
+    pub fn to_search(&self) -> CloudlogSearch {
+        let mut time_parts = Vec::new();
+        for (field, op, value) in [
+            ("year", "=", self.year),
+            ("month", "=", self.month.map(|v| v as i32)),
+            ("day", "=", self.day.map(|v| v as i32)),
+            ("hour", "=", self.hour.map(|v| v as i32)),
+            ("minute", "=", self.minute.map(|v| v as i32)),
+            ("second", "=", self.second.map(|v| v as i32)),
+            ("minute", ">=", self.minute_from.map(|v| v as i32)),
+            ("minute", "<=", self.minute_to.map(|v| v as i32)),
+            ("second", ">=", self.second_from.map(|v| v as i32)),
+            ("second", "<=", self.second_to.map(|v| v as i32)),
+        ] {
+            if let Some(value) = value {
+                time_parts.push((field, op, value));
+            }
+        }
+        let mut ctx = Vec::new();
+        for (field, value) in [
+            ("branch", self.ctx_branch.clone()),
+            ("device", self.ctx_device.clone()),
+            ("dirty", self.ctx_dirty.map(|dirty| dirty.to_string())),
+            ("dongle_id", self.ctx_dongle_id.clone()),
+            ("origin", self.ctx_origin.clone()),
+            ("version", self.ctx_version.clone()),
+        ] {
+            if let Some(value) = value {
+                ctx.push((field, value));
+            }
+        }
+        CloudlogSearch {
+            dongle_id: self.dongle_id.clone(),
+            branch: self.branch.clone(),
+            commit: self.ctx_commit.clone(),
+            module: self.module.clone(),
+            func_name: self.func_name.clone(),
+            level: self.level.clone(),
+            levelnum: self.levelnum.map(|v| v as i32),
+            levelnum_op: self.levelnum_op.clone(),
+            from_millis: self.date_from.map(|secs| (secs * 1000.0) as i64),
+            to_millis: self.date_to.map(|secs| (secs * 1000.0) as i64),
+            time_parts,
+            ctx,
+        }
+    }
+}
+
+/// Everyone's cloudlogs are for superusers. FrogAI only gets the FrogPilot branches, and the
+/// testing branch when it doesn't ask for one.
+fn authorize_all_cloudlogs(
+    auth: &crate::middleware::auth::MyJWT,
+    query: &mut CloudlogAllQuery,
+) -> Result<(), (StatusCode, &'static str)> {
     let frogai_id = Uuid::parse_str("f0b1a2c3-d4e5-6789-abcd-ef0123456789").unwrap();
     let is_frogai = auth.user_model.as_ref().map_or(false, |u| u.identity == frogai_id);
     if let Some(user_model) = &auth.user_model {
@@ -536,159 +695,154 @@ pub async fn get_all_cloudlogs(
     } else {
         return Err((StatusCode::FORBIDDEN, "Devices can't do this"));
     }
+    Ok(())
+}
 
-    let cloudlog_cache = manager.cloudlog_cache.read().await;
-    let mut results = Vec::new();
-    let offset = query.offset.unwrap_or(0);
-    let limit = query.limit.unwrap_or(100);
-    let mut count = 0;
-    let mut skipped = 0;
-
-    for (dongle_id, device_logs) in cloudlog_cache.iter() {
-        if let Some(ref filter_dongle) = query.dongle_id {
-            if dongle_id != filter_dongle { continue; }
-        }
-        for (branch, modules) in device_logs.iter() {
-            if let Some(ref filter_branch) = query.branch {
-                if branch != filter_branch { continue; }
+/// Stored cloudlogs matching `query`, newest first. The total goes in `X-Total-Count`.
+async fn search_cloudlogs(
+    db: &DatabaseConnection,
+    query: &CloudlogAllQuery,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    let limit = query.limit.unwrap_or(100).min(MAX_CLOUDLOG_LIMIT) as u64;
+    let offset = query.offset.unwrap_or(0) as u64;
+    let search = query.to_search();
+    if !search.supported_on(db.get_database_backend()) {
+        return Err((StatusCode::BAD_REQUEST, "Time of day and ctx filters aren't available on this database"));
+    }
+    let (lines, total) = CLGM::search(db, &search, limit, offset)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to search cloudlogs"))?;
+    let results: Vec<Value> = lines
+        .into_iter()
+        .map(|line| {
+            // Attach context for device, branch, module
+            let mut log_with_ctx = line.log;
+            if let Some(obj) = log_with_ctx.as_object_mut() {
+                obj.insert("_dongle_id".to_string(), Value::String(line.dongle_id));
+                obj.insert("_branch".to_string(), Value::String(line.branch));
+                obj.insert("_module".to_string(), Value::String(line.module));
             }
-            for (module, logs) in modules.iter() {
-                if let Some(ref filter_module) = query.module {
-                    if module != filter_module { continue; }
-                }
-                for log in logs.iter() {
-                    // Filter by level
-                    if let Some(ref filter_level) = query.level {
-                        if let Some(level) = log.get("level").and_then(|v| v.as_str()) {
-                            if level != filter_level { continue; }
-                        } else { continue; }
-                    }
-                    // Filter by levelnum
-                    if let Some(filter_levelnum) = query.levelnum {
-                        if let Some(levelnum) = log.get("levelnum").and_then(|v| v.as_u64()) {
-                            if let Some(ref op) = query.levelnum_op {
-                                match op.as_str() {
-                                    "eq" => if levelnum != filter_levelnum { continue; },
-                                    "gt" => if levelnum <= filter_levelnum { continue; },
-                                    "lt" => if levelnum >= filter_levelnum { continue; },
-                                    _ => continue,
-                                }
-                            } else {
-                                if levelnum != filter_levelnum { continue; }
-                            }
-                        } else { continue; }
-                    }
-                    // Filter by funcName (now func_name)
-                    if let Some(ref filter_func) = query.func_name {
-                        if let Some(func) = log.get("funcName").and_then(|v| v.as_str()) {
-                            if func != filter_func { continue; }
-                        } else { continue; }
-                    }
-                    // Filter by date
-                    if let Some(created) = log.get("created").and_then(|v| v.as_f64()) {
-                        if let Some(date_from) = query.date_from {
-                            if created < date_from { continue; }
-                        }
-                        if let Some(date_to) = query.date_to {
-                            if created > date_to { continue; }
-                        }
-                        // Updated chrono usage
-                        let dt = Utc.timestamp_opt(created as i64, (created.fract() * 1e9) as u32).single().map(|dt_utc| dt_utc.naive_utc());
-                        if let Some(dt) = dt {
-                            if let Some(year) = query.year {
-                                if dt.year() != year { continue; }
-                            }
-                            if let Some(month) = query.month {
-                                if dt.month() != month { continue; }
-                            }
-                            if let Some(day) = query.day {
-                                if dt.day() != day { continue; }
-                            }
-                            if let Some(hour) = query.hour {
-                                if dt.hour() != hour { continue; }
-                            }
-                            if let Some(minute) = query.minute {
-                                if dt.minute() != minute { continue; }
-                            }
-                            if let Some(second) = query.second {
-                                if dt.second() != second { continue; }
-                            }
-                            if let Some(minute_from) = query.minute_from {
-                                if dt.minute() < minute_from { continue; }
-                            }
-                            if let Some(minute_to) = query.minute_to {
-                                if dt.minute() > minute_to { continue; }
-                            }
-                            if let Some(second_from) = query.second_from {
-                                if dt.second() < second_from { continue; }
-                            }
-                            if let Some(second_to) = query.second_to {
-                                if dt.second() > second_to { continue; }
-                            }
-                        } else { continue; } // If timestamp is invalid, skip
-                    }
-                    // Filter by ctx fields
-                    if let Some(ctx) = log.get("ctx") {
-                        if let Some(ref ctx_branch) = query.ctx_branch {
-                            if ctx.get("branch").and_then(|v| v.as_str()) != Some(ctx_branch) { continue; }
-                        }
-                        if let Some(ref ctx_commit) = query.ctx_commit {
-                            if ctx.get("commit").and_then(|v| v.as_str()) != Some(ctx_commit) { continue; }
-                        }
-                        if let Some(ref ctx_device) = query.ctx_device {
-                            if ctx.get("device").and_then(|v| v.as_str()) != Some(ctx_device) { continue; }
-                        }
-                        if let Some(ctx_dirty) = query.ctx_dirty {
-                            if ctx.get("dirty").and_then(|v| v.as_bool()) != Some(ctx_dirty) { continue; }
-                        }
-                        if let Some(ref ctx_dongle_id) = query.ctx_dongle_id {
-                            if ctx.get("dongle_id").and_then(|v| v.as_str()) != Some(ctx_dongle_id) { continue; }
-                        }
-                        if let Some(ref ctx_origin) = query.ctx_origin {
-                            if ctx.get("origin").and_then(|v| v.as_str()) != Some(ctx_origin) { continue; }
-                        }
-                        if let Some(ref ctx_version) = query.ctx_version {
-                            if ctx.get("version").and_then(|v| v.as_str()) != Some(ctx_version) { continue; }
-                        }
-                    } else {
-                        // If ctx filter is set but log has no ctx, skip
-                        if query.ctx_branch.is_some() || query.ctx_commit.is_some() || query.ctx_device.is_some() || query.ctx_dirty.is_some() || query.ctx_dongle_id.is_some() || query.ctx_origin.is_some() || query.ctx_version.is_some() {
-                            continue;
-                        }
-                    }
-                    // Pagination
-                    if skipped < offset {
-                        skipped += 1;
-                        continue;
-                    }
-                    if count >= limit {
-                        break;
+            log_with_ctx
+        })
+        .collect();
+    let json_bytes = serde_json::to_vec(&results).map_err(|_| {
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to serialize cloudlogs")
+    })?;
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/json".to_string()),
+            ("X-Total-Count", total.to_string()),
+        ],
+        json_bytes,
+    ))
+}
+
+pub async fn get_all_cloudlogs(
+    auth: crate::middleware::auth::MyJWT,
+    State(ctx): State<AppContext>,
+    Query(mut query): Query<CloudlogAllQuery>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    authorize_all_cloudlogs(&auth, &mut query)?;
+    search_cloudlogs(&ctx.db, &query).await
+}
+
+/// A device's stored cloudlogs, with the filters of `get_all_cloudlogs`.
+pub async fn get_device_cloudlogs(
+    auth: crate::middleware::auth::MyJWT,
+    State(ctx): State<AppContext>,
+    Path(dongle_id): Path<String>,
+    Query(mut query): Query<CloudlogAllQuery>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    ensure_user_is_owner(&auth, &ctx.db, &dongle_id).await?;
+    query.dongle_id = Some(dongle_id);
+    search_cloudlogs(&ctx.db, &query).await
+}
+
+fn cloudlog_retention() -> chrono::Duration {
+    let days = env::var("CLOUDLOG_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_CLOUDLOG_RETENTION_DAYS);
+    chrono::Duration::days(days)
+}
+
+/// Deletes stored cloudlogs older than `CLOUDLOG_RETENTION_DAYS`.
+pub async fn prune_cloudlogs(db: &DatabaseConnection) {
+    let cutoff = (Utc::now() - cloudlog_retention()).timestamp_millis();
+    match CLGM::delete_before(db, cutoff).await {
+        Ok(0) => {}
+        Ok(deleted) => tracing::info!("Pruned {deleted} cloudlogs"),
+        Err(e) => tracing::error!("Failed to prune cloudlogs: {e}"),
+    }
+}
+
+fn cloudlog_event(line: &CloudlogLine) -> Event {
+    let mut log = line.log.clone();
+    if let Some(obj) = log.as_object_mut() {
+        obj.insert("_dongle_id".to_string(), Value::String(line.dongle_id.clone()));
+        obj.insert("_branch".to_string(), Value::String(line.branch.clone()));
+        obj.insert("_module".to_string(), Value::String(line.module.clone()));
+    }
+    Event::default()
+        .event("cloudlog")
+        .json_data(log)
+        .unwrap_or_else(|_| Event::default().event("cloudlog").data("{}"))
+}
+
+/// Server-sent `cloudlog` events for the lines passing `query` as they come in. A client too
+/// slow to keep up gets a `lagged` event with the number of lines it missed.
+fn cloudlog_sse(
+    manager: &ConnectionManager,
+    query: CloudlogAllQuery,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = manager.cloudlog_tail.subscribe();
+    let events = stream::unfold((receiver, query), |(mut receiver, query)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(line) => {
+                    if query.matches(&line.dongle_id, &line.branch, &line.module, &line.log) {
+                        return Some((Ok(cloudlog_event(&line)), (receiver, query)));
                     }
-                    // Attach context for device, branch, module
-                    let mut log_with_ctx = log.clone();
-                    log_with_ctx["_dongle_id"] = serde_json::Value::String(dongle_id.clone());
-                    log_with_ctx["_branch"] = serde_json::Value::String(branch.clone());
-                    log_with_ctx["_module"] = serde_json::Value::String(module.clone());
-                    results.push(log_with_ctx);
-                    count += 1;
                 }
-                if count >= limit { break; }
+                Err(RecvError::Lagged(missed)) => {
+                    let event = Event::default().event("lagged").data(missed.to_string());
+                    return Some((Ok(event), (receiver, query)));
+                }
+                Err(RecvError::Closed) => return None,
             }
-            if count >= limit { break; }
         }
-        if count >= limit { break; }
-    }
-    let json_bytes = serde_json::to_vec(&results).map_err(|_| {
-        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to serialize cloudlogs")
-    })?;
-    Ok((StatusCode::OK, [("Content-Type", "application/json")], json_bytes))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Live tail of a device's cloudlogs, a couple of seconds behind.
+pub async fn tail_device_cloudlogs(
+    auth: crate::middleware::auth::MyJWT,
+    State(ctx): State<AppContext>,
+    Path(dongle_id): Path<String>,
+    Query(mut query): Query<CloudlogAllQuery>,
+    Extension(manager): Extension<Arc<ConnectionManager>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    ensure_user_is_owner(&auth, &ctx.db, &dongle_id).await?;
+    query.dongle_id = Some(dongle_id);
+    Ok(cloudlog_sse(&manager, query))
+}
+
+/// Live tail of everyone's cloudlogs, same filters and access as `get_all_cloudlogs`.
+pub async fn tail_all_cloudlogs(
+    auth: crate::middleware::auth::MyJWT,
+    Query(mut query): Query<CloudlogAllQuery>,
+    Extension(manager): Extension<Arc<ConnectionManager>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    authorize_all_cloudlogs(&auth, &mut query)?;
+    Ok(cloudlog_sse(&manager, query))
 }
 
 // List top-level keys (sorted, paginated) with auth and pattern check
 pub async fn api_useradmin_log_keys(
     auth: crate::middleware::auth::MyJWT,
     Query(query): Query<LogListQuery>,
-    Extension(client): Extension<reqwest::Client>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
     // Only allow superusers
     if !auth.user_model.as_ref().map(|u| u.superuser).unwrap_or(false) {
@@ -702,13 +856,12 @@ pub async fn api_useradmin_log_keys(
     let page = query.page.unwrap_or(0);
     let page_size = query.page_size.unwrap_or(100);
     let log_key = format!("{}.log", query.event);
-    let url = mkv_helpers::get_mkv_file_url(&log_key);
-    let resp = client.get(&url).send().await.map_err(|_| (StatusCode::BAD_GATEWAY, "Failed to fetch log file"))?;
-    if !resp.status().is_success() {
-        return Err((StatusCode::NOT_FOUND, "Log file not found"));
-    }
-    let data = resp.text().await.map_err(|_| (StatusCode::BAD_GATEWAY, "Failed to read log file"))?;
-    let json: JsonValue = serde_json::from_str(&data).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid JSON"))?;
+    let data = match storage::blob_store().get_bytes(&log_key).await {
+        Ok(data) => data,
+        Err(StorageError::NotFound(_)) => return Err((StatusCode::NOT_FOUND, "Log file not found")),
+        Err(_) => return Err((StatusCode::BAD_GATEWAY, "Failed to fetch log file")),
+    };
+    let json: JsonValue = serde_json::from_slice(&data).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid JSON"))?;
     let obj = json.as_object().ok_or((StatusCode::BAD_REQUEST, "Expected JSON object at top level"))?;
     let mut keys: Vec<String> = obj.keys().cloned().collect();
     keys.sort();
@@ -732,5 +885,8 @@ pub fn routes() -> Routes {
         .add("/delete/:dongle_id/:timestamp", delete(delete_route))
         .add("/bootlog/:bootlog_file", get(bootlog_file_download))
         .add("/:dongle_id/cloudlogs", get(get_cloudlog_cache))
+        .add("/:dongle_id/cloudlogs/history", get(get_device_cloudlogs))
+        .add("/:dongle_id/cloudlogs/live", get(tail_device_cloudlogs))
         .add("/cloudlogs/all", get(get_all_cloudlogs))
+        .add("/cloudlogs/live", get(tail_all_cloudlogs))
 }
@@ -1,78 +1,282 @@
 #![allow(clippy::unused_async)]
-use futures::StreamExt;
+use futures::{stream, StreamExt, TryStreamExt};
 use loco_rs::prelude::*;
-use bytes::BytesMut;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    Json,
   };
+use serde::{Deserialize, Serialize};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{env, io};
+use std::sync::{
+    atomic::{AtomicBool, AtomicI64, Ordering},
+    Arc, Mutex,
+};
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use crate::{
+    models::{
+        devices::DM,
+        storage_objects::SOM,
+        upload_sessions::USM,
+    },
     workers::{
         bootlog_parser::{
             BootlogParserWorker, 
             BootlogParserWorkerArgs
         },
+        crash_parser::{
+            CrashParserWorker,
+            CrashParserWorkerArgs
+        },
         log_parser::{
             LogSegmentWorker, 
             LogSegmentWorkerArgs
         }
     },
-    common,
+    common::{
+        quota,
+        re::*,
+        storage::{self, BlobStore, ByteStream, StorageError, StorageResult},
+    },
 };
 
+const DEFAULT_MAX_UPLOADS_PER_DEVICE: usize = 4;
+const DEFAULT_UPLOAD_SESSION_TTL_HOURS: i64 = 24;
+
+static DEVICE_UPLOAD_SLOTS: Lazy<DashMap<String, Arc<Semaphore>>> = Lazy::new(|| {
+    DashMap::new()
+});
+
+fn max_uploads_per_device() -> usize {
+    env::var("MAX_UPLOADS_PER_DEVICE")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_MAX_UPLOADS_PER_DEVICE)
+}
+
+/// Take one of the device's upload slots. Returns None when the device already has
+/// `MAX_UPLOADS_PER_DEVICE` uploads in flight. The slot is released when the permit is dropped.
+pub fn acquire_upload_slot(dongle_id: &str) -> Option<OwnedSemaphorePermit> {
+    let semaphore = DEVICE_UPLOAD_SLOTS
+        .entry(dongle_id.to_string())
+        .or_insert_with(|| Arc::new(Semaphore::new(max_uploads_per_device())))
+        .clone();
+    semaphore.try_acquire_owned().ok()
+}
+
+pub struct StreamedUpload {
+    pub status: StatusCode,
+    pub bytes: i64,
+}
+
+/// Pipe the request body straight into the blob store, counting bytes as they pass.
+/// A duplicate key is reported as FORBIDDEN, matching what MKV used to answer.
+///
+/// `max_bytes` is the room left under the quota. A body that goes past it, or past the
+/// Content-Length it declared, is cut off there. The stores drop whatever they got of a
+/// body that fails, so nothing of it is left behind.
+pub async fn stream_to_store(
+    key: &str,
+    headers: &HeaderMap,
+    body: axum::body::Body,
+    max_bytes: Option<i64>,
+) -> std::result::Result<StreamedUpload, (StatusCode, &'static str)> {
+    // The backend wants the length up front since we can't buffer to find it
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    put_limited(storage::blob_store().as_ref(), key, body.into_data_stream(), content_length, max_bytes).await
+}
+
+async fn put_limited<S, E>(
+    store: &dyn BlobStore,
+    key: &str,
+    body: S,
+    content_length: Option<u64>,
+    max_bytes: Option<i64>,
+) -> std::result::Result<StreamedUpload, (StatusCode, &'static str)>
+where
+    S: futures::Stream<Item = std::result::Result<bytes::Bytes, E>> + Send + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let counter = Arc::new(AtomicI64::new(0));
+    let body_failed = Arc::new(AtomicBool::new(false));
+    let cut_off: Arc<Mutex<Option<(StatusCode, &'static str)>>> = Arc::new(Mutex::new(None));
+    let (stream_counter, stream_failed, stream_cut_off) = (counter.clone(), body_failed.clone(), cut_off.clone());
+    let stream = body.map(move |chunk| match chunk {
+        Ok(data) => {
+            let bytes = stream_counter.fetch_add(data.len() as i64, Ordering::Relaxed) + data.len() as i64;
+            let reason = if content_length.is_some_and(|content_length| bytes as u64 > content_length) {
+                Some((StatusCode::BAD_REQUEST, "Body is longer than its Content-Length"))
+            } else if max_bytes.is_some_and(|max_bytes| bytes > max_bytes) {
+                Some((StatusCode::INSUFFICIENT_STORAGE, "Storage quota exceeded"))
+            } else {
+                None
+            };
+            match reason {
+                Some((status, msg)) => {
+                    *stream_cut_off.lock().unwrap() = Some((status, msg));
+                    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+                }
+                None => Ok(data),
+            }
+        }
+        Err(e) => {
+            stream_failed.store(true, Ordering::Relaxed);
+            Err(io::Error::new(io::ErrorKind::Other, e))
+        }
+    });
+
+    let result = store.put(key, Box::pin(stream), content_length).await;
+    let bytes = counter.load(Ordering::Relaxed);
+    let cut_off = *cut_off.lock().unwrap();
+    if let (Err(_), Some((status, msg))) = (&result, cut_off) {
+        tracing::warn!("Cut off the upload of {key} after {bytes} bytes: {msg}");
+        return Err((status, msg));
+    }
+    match result {
+        Ok(()) => Ok(StreamedUpload { status: StatusCode::CREATED, bytes }),
+        Err(StorageError::AlreadyExists(_)) => Ok(StreamedUpload { status: StatusCode::FORBIDDEN, bytes }),
+        Err(e) if body_failed.load(Ordering::Relaxed) => {
+            tracing::warn!("Error reading request body for {key}: {e}");
+            Err((StatusCode::BAD_REQUEST, "Error reading request body"))
+        }
+        Err(e) => {
+            tracing::error!("PUT to storage failed for {key}: {e}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong"))
+        }
+    }
+}
+
+async fn add_server_storage(ctx: &AppContext, file_key: &str, bytes: i64) {
+    if let Err(e) = SOM::track(&ctx.db, file_key, bytes).await {
+        tracing::error!("Failed to update storage usage for {file_key}. DB Error {}", e.to_string());
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> i64 {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(0)
+}
+
+fn full_match(pattern: &str) -> Regex {
+    Regex::new(&format!("^(?:{pattern})$")).unwrap()
+}
+
+static DONGLE_ID_RE: Lazy<Regex> = Lazy::new(|| full_match(DONGLE_ID));
+static ROUTE_NAME_RE: Lazy<Regex> = Lazy::new(|| full_match(ROUTE_NAME));
+static NUMBER_RE: Lazy<Regex> = Lazy::new(|| full_match(NUMBER));
+static UPLOAD_FILENAME_RE: Lazy<Regex> = Lazy::new(|| full_match(UPLOAD_FILENAME));
+static BOOTLOG_FILENAME_RE: Lazy<Regex> = Lazy::new(|| full_match(&format!(r"(?:{ROUTE_NAME})\.(?:bz2|zst)")));
+static COMMIT_RE: Lazy<Regex> = Lazy::new(|| full_match(HEX));
+static SAFE_FILENAME_RE: Lazy<Regex> = Lazy::new(|| full_match(SAFE_FILENAME));
+
+/// Check every path component of an upload against its pattern before it goes anywhere
+/// near a storage key.
+fn validate_path(components: &[(&Lazy<Regex>, &str, &'static str)]) -> std::result::Result<(), (StatusCode, &'static str)> {
+    for (re, value, msg) in components {
+        if !re.is_match(value) {
+            tracing::warn!("Rejecting upload: {msg} `{value}`");
+            return Err((StatusCode::BAD_REQUEST, msg));
+        }
+    }
+    Ok(())
+}
+
+fn validate_segment_path(
+    dongle_id: &str,
+    timestamp: &str,
+    segment: &str,
+    file: &str,
+) -> std::result::Result<(), (StatusCode, &'static str)> {
+    validate_path(&[
+        (&DONGLE_ID_RE, dongle_id, "Invalid dongle_id"),
+        (&ROUTE_NAME_RE, timestamp, "Invalid route name"),
+        (&NUMBER_RE, segment, "Invalid segment number"),
+        (&UPLOAD_FILENAME_RE, file, "Invalid file name"),
+    ])
+}
+
+/// Make sure the device exists, has uploads turned on and has room for `incoming` more
+/// bytes of `file`. Returns the most the body may send, None when there's no quota. A device may only upload under its own dongle id. Uploads can also come
+/// in with the owner's token (via athena), in which case the user has to own the device.
+async fn enforce_upload_permission(
+    ctx: &AppContext,
+    auth: &crate::middleware::auth::MyJWT,
+    dongle_id: &str,
+    file: &str,
+    incoming: i64,
+) -> std::result::Result<Option<i64>, (StatusCode, &'static str)> {
+    let device = match (&auth.device_model, &auth.user_model) {
+        (Some(device), _) if device.dongle_id == dongle_id => device.clone(),
+        (Some(device), _) => {
+            tracing::error!("{} tried to upload under {dongle_id}", device.dongle_id);
+            return Err((StatusCode::FORBIDDEN, "dongle_id does not match identity"));
+        }
+        (None, Some(user_model)) => {
+            let Ok(device) = DM::find_device(&ctx.db, dongle_id).await else {
+                return Err((StatusCode::UNAUTHORIZED, "Only registered devices can upload"));
+            };
+            if !user_model.superuser && device.owner_id != Some(user_model.id) {
+                return Err((StatusCode::FORBIDDEN, "You are not the owner"));
+            }
+            device
+        }
+        (None, None) => return Err((StatusCode::UNAUTHORIZED, "Only registered devices can upload")),
+    };
+    if !device.uploads_allowed {
+        return Err((StatusCode::FORBIDDEN, "Uploads ignored"));
+    }
+    quota::enforce_upload_quota(&ctx.db, &device, file, incoming).await
+}
+
 pub async fn upload_bootlogs(
     auth: crate::middleware::auth::MyJWT,
     Path((dongle_id, file)): Path<(String, String)>,
     State(ctx): State<AppContext>,
-    axum::Extension(client): axum::Extension<reqwest::Client>,
+    headers: HeaderMap,
     body: axum::body::Body,
 ) -> Result<(StatusCode, &'static str)> {
-    //enforce_device_upload_permission!(auth);
-    let full_url = common::mkv_helpers::get_mkv_file_url(&format!("{}_boot_{}", dongle_id, file));
-    
-    let mut buffer = BytesMut::new();
-    let mut stream = body.into_data_stream();
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(data) => buffer.extend_from_slice(&data),
-            Err(_) => return Ok((StatusCode::BAD_REQUEST, "Error reading request body")),
-        }
+    if let Err(e) = validate_path(&[
+        (&DONGLE_ID_RE, dongle_id.as_str(), "Invalid dongle_id"),
+        (&BOOTLOG_FILENAME_RE, file.as_str(), "Invalid file name"),
+    ]) {
+        return Ok(e);
     }
-    let data = buffer.freeze();
-    let data_len = data.len() as i64;
-    tracing::info!("File `{}` received is {} bytes", full_url, data.len()
-    );
+    let room = match enforce_upload_permission(&ctx, &auth, &dongle_id, &file, content_length(&headers)).await {
+        Ok(room) => room,
+        Err(e) => return Ok(e),
+    };
+    let Some(_slot) = acquire_upload_slot(&dongle_id) else {
+        tracing::warn!("Too many uploads in flight for {dongle_id}");
+        return Ok((StatusCode::TOO_MANY_REQUESTS, "Too many uploads in flight"));
+    };
+    let file_key = format!("{}_boot_{}", dongle_id, file);
 
-    // Post the binary data to the specified URL
-    let response = client.put(&full_url)
-        .body(data)
-        .send()
-        .await;
-    match response {
-        Ok(response) => {
-            let status = response.status();
+    // Stream the binary data into storage
+    match stream_to_store(&file_key, &headers, body, room).await {
+        Ok(upload) => {
+            let status = upload.status;
             tracing::trace!("Got Ok response with status {status}");
+            tracing::info!("File `{}` received is {} bytes", file_key, upload.bytes);
             match status {
                 StatusCode::FORBIDDEN => { tracing::error!("Duplicate file uploaded"); return Ok((status, "Duplicate File Upload")); }
                 StatusCode::CREATED | StatusCode::OK => {
-                    if let Some(device) = auth.device_model {
-                        let prev_server_usage = device.server_storage;
-                        let mut active_device = device.into_active_model();
-                        active_device.server_storage = ActiveValue::Set(data_len + prev_server_usage);
-                        match active_device.update(&ctx.db).await {
-                            Ok(_) => (),
-                            Err(e) => {
-                                tracing::error!("Failed to update active route model. DB Error {}", e.to_string());
-                            }
-                        }
-                
-                    }
+                    add_server_storage(&ctx, &file_key, upload.bytes).await;
                     // Enqueue the file for processing
-                    tracing::debug!("File Uploaded Successfully. Queuing worker for {full_url}");
+                    tracing::debug!("File Uploaded Successfully. Queuing worker for {file_key}");
                     let result = BootlogParserWorker::perform_later(&ctx, 
                     BootlogParserWorkerArgs {
-                        internal_file_url: full_url.clone(),
+                        file_key: file_key.clone(),
                         dongle_id: dongle_id,
                         file_name: file,
                         create_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64,
@@ -89,11 +293,7 @@ pub async fn upload_bootlogs(
                 _ => {tracing::error!("Unhandled status {}. File not uploaded.", status); return Ok((status, "Unhandled status. File not uploaded."));}
             }
         },
-        Err(e) => {
-            
-            tracing::error!("PUT request failed: {}", format!("{}", e));
-            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong"));
-        }
+        Err(e) => Ok(e),
     }
 }
 
@@ -101,59 +301,60 @@ pub async fn upload_crash(
     auth: crate::middleware::auth::MyJWT,
     Path((dongle_id, id, commit, name)): Path<(String, String, String, String)>,//:dongle_id/crash/:log_id/:commit/:name
     State(ctx): State<AppContext>,
-    axum::Extension(client): axum::Extension<reqwest::Client>,
+    headers: HeaderMap,
     body: axum::body::Body,
 ) -> Result<(StatusCode, &'static str)> {
-    //enforce_device_upload_permission!(auth);
-    let full_url = common::mkv_helpers::get_mkv_file_url(&format!("{}_crash_{}_{}_{}", dongle_id, id, commit, name));
-    
-    let mut buffer = BytesMut::new();
-    let mut stream = body.into_data_stream();
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(data) => buffer.extend_from_slice(&data),
-            Err(_) => return Ok((StatusCode::BAD_REQUEST, "Error reading request body")),
-        }
+    if let Err(e) = validate_path(&[
+        (&DONGLE_ID_RE, dongle_id.as_str(), "Invalid dongle_id"),
+        (&ROUTE_NAME_RE, id.as_str(), "Invalid crash id"),
+        (&COMMIT_RE, commit.as_str(), "Invalid commit"),
+        (&SAFE_FILENAME_RE, name.as_str(), "Invalid file name"),
+    ]) {
+        return Ok(e);
     }
-    let data = buffer.freeze();
-    let data_len = data.len() as i64;
-    tracing::info!("File `{}` received is {} bytes", full_url, data.len());
+    let room = match enforce_upload_permission(&ctx, &auth, &dongle_id, &name, content_length(&headers)).await {
+        Ok(room) => room,
+        Err(e) => return Ok(e),
+    };
+    let Some(_slot) = acquire_upload_slot(&dongle_id) else {
+        tracing::warn!("Too many uploads in flight for {dongle_id}");
+        return Ok((StatusCode::TOO_MANY_REQUESTS, "Too many uploads in flight"));
+    };
+    let file_key = format!("{}_crash_{}_{}_{}", dongle_id, id, commit, name);
 
-    // Post the binary data to the specified URL
-    let response = client.put(&full_url)
-        .body(data)
-        .send()
-        .await;
-    match response {
-        Ok(response) => {
-            let status = response.status();
+    // Stream the binary data into storage
+    match stream_to_store(&file_key, &headers, body, room).await {
+        Ok(upload) => {
+            let status = upload.status;
             tracing::trace!("Got Ok response with status {status}");
+            tracing::info!("File `{}` received is {} bytes", file_key, upload.bytes);
             match status {
                 StatusCode::FORBIDDEN => { tracing::error!("Duplicate file uploaded"); return Ok((status, "Duplicate File Upload")); }
                 StatusCode::CREATED | StatusCode::OK => {
-                    // Enqueue the file for processing
-                    tracing::debug!("{full_url} file Uploaded Successfully");
-                    if let Some(device) = auth.device_model {
-                        let prev_server_usage = device.server_storage;
-                        let mut active_device = device.into_active_model();
-                        active_device.server_storage = ActiveValue::Set(data_len + prev_server_usage);
-                        match active_device.update(&ctx.db).await {
-                            Ok(_) => (),
-                            Err(e) => {
-                                tracing::error!("Failed to update active route model. DB Error {}", e.to_string());
-                            }
+                    tracing::debug!("{file_key} file Uploaded Successfully");
+                    add_server_storage(&ctx, &file_key, upload.bytes).await;
+                    let result = CrashParserWorker::perform_later(&ctx,
+                        CrashParserWorkerArgs {
+                            file_key: file_key.clone(),
+                            dongle_id: dongle_id,
+                            log_id: id,
+                            commit: commit,
+                            name: name,
+                            create_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64,
+                        },
+                    ).await;
+                    match result {
+                        Ok(_) => { tracing::debug!("Queued Worker"); return Ok((status, "Queued Worker")); }
+                        Err(e) => {
+                            tracing::error!("Failed to queue worker: {}", format!("{}", e));
+                            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to queue worker."));
                         }
                     }
-                    return Ok((status, "File uploaded."));
                 }
                 _ => {tracing::error!("Unhandled status. File not uploaded."); return Ok((status, "Unhandled status. File not uploaded."));}
             }
         },
-        Err(e) => {
-            
-            tracing::error!("PUT request failed: {}", format!("{}", e));
-            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong"));
-        }
+        Err(e) => Ok(e),
     }
 }
 
@@ -162,79 +363,59 @@ pub async fn upload_driving_logs(
     auth: crate::middleware::auth::MyJWT,
     Path((dongle_id, timestamp, segment, file)): Path<(String, String, String, String)>,
     State(ctx): State<AppContext>,
-    axum::Extension(client): axum::Extension<reqwest::Client>,
+    headers: HeaderMap,
     body: axum::body::Body,
 ) -> Result<(StatusCode, &'static str)> {
     let start = Instant::now();
-    //enforce_device_upload_permission!(auth);
-    // Construct the URL to store the file
-    let full_url = common::mkv_helpers::get_mkv_file_url(&format!("{}_{}--{}--{}", dongle_id, timestamp, segment, file));
-    tracing::trace!("full_url: {full_url}");
-    // Check for duplicate file
-    //let response = client.request(&full_url).send().await;
-
-    // Collect the binary data from the body
-    let mut buffer = BytesMut::new();
-    let mut stream = body.into_data_stream();
-
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(data) => buffer.extend_from_slice(&data),
-            Err(e) => {
-                tracing::warn!("Error reading request body: {}", e);
-                return Ok((StatusCode::BAD_REQUEST, "Error reading request body"));
-            }
-        }
+    if let Err(e) = validate_segment_path(&dongle_id, &timestamp, &segment, &file) {
+        return Ok(e);
     }
-    
-    let data = buffer.freeze();
-    let data_len = data.len() as i64;
-
-    let duration = start.elapsed();
-    let secs = duration.as_secs_f64();
-    let bytes_per_sec = data_len as f64 / secs;
-    let mb_per_sec = bytes_per_sec / (1024.0 * 1024.0);
-
-    tracing::info!(
-        "File {} downloaded in {} bytes in {:.2?} seconds ({:.2} MB/s)",
-        full_url,
-        data_len,
-        duration,
-        mb_per_sec
-    );
+    let room = match enforce_upload_permission(&ctx, &auth, &dongle_id, &file, content_length(&headers)).await {
+        Ok(room) => room,
+        Err(e) => return Ok(e),
+    };
+    let Some(_slot) = acquire_upload_slot(&dongle_id) else {
+        tracing::warn!("Too many uploads in flight for {dongle_id}");
+        return Ok((StatusCode::TOO_MANY_REQUESTS, "Too many uploads in flight"));
+    };
+    // Construct the key to store the file under
+    let file_key = format!("{}_{}--{}--{}", dongle_id, timestamp, segment, file);
+    tracing::trace!("file_key: {file_key}");
 
-    // Post the binary data to the specified URL
-    let response = client.put(&full_url)
-        .body(data)
-        .send()
-        .await;
+    // Stream the binary data into storage
+    let response = stream_to_store(&file_key, &headers, body, room).await;
 
     match response {
-        Ok(response) => {
-            let status = response.status();
+        Ok(upload) => {
+            let status = upload.status;
             tracing::trace!("Got Ok response with status {status}");
+
+            let duration = start.elapsed();
+            let secs = duration.as_secs_f64();
+            let bytes_per_sec = upload.bytes as f64 / secs;
+            let mb_per_sec = bytes_per_sec / (1024.0 * 1024.0);
+
+            tracing::info!(
+                "File {} uploaded {} bytes in {:.2?} seconds ({:.2} MB/s)",
+                file_key,
+                upload.bytes,
+                duration,
+                mb_per_sec
+            );
+
             match status {
-                StatusCode::FORBIDDEN => { tracing::error!("Duplicate file uploaded");}
+                // The first upload was already queued
+                StatusCode::FORBIDDEN => { tracing::error!("Duplicate file uploaded"); return Ok((status, "Duplicate File Upload")); }
                 StatusCode::CREATED | StatusCode::OK => {
-                    if let Some(device) = auth.device_model {
-                        let prev_server_usage = device.server_storage;
-                        let mut active_device = device.into_active_model();
-                        active_device.server_storage = ActiveValue::Set(data_len + prev_server_usage);
-                        match active_device.update(&ctx.db).await {
-                            Ok(_) => (),
-                            Err(e) => {
-                                tracing::error!("Failed to update active route model. DB Error {}", e.to_string());
-                            }
-                        }
-                    }
+                    add_server_storage(&ctx, &file_key, upload.bytes).await;
                 }
                 _ => {tracing::error!("Unhandled status. File not uploaded."); return Ok((status, "Unhandled status. File not uploaded."));}
             }
             // Enqueue the file for processing
-            tracing::debug!("File Uploaded Successfully. Queuing worker for {full_url}");
+            tracing::debug!("File Uploaded Successfully. Queuing worker for {file_key}");
             let result = LogSegmentWorker::perform_later(&ctx, 
                 LogSegmentWorkerArgs {
-                    internal_file_url: full_url,
+                    file_key: file_key,
                     dongle_id: dongle_id,
                     timestamp: timestamp,
                     segment: segment,
@@ -254,10 +435,354 @@ pub async fn upload_driving_logs(
             }
 
         },
+        Err(e) => Ok(e),
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct CreateUploadParams {
+    pub size: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct UploadSessionResponse {
+    pub session_id: String,
+    pub offset: i64,
+    pub size: Option<i64>,
+    pub expires_at: i64,
+}
+
+impl From<&USM> for UploadSessionResponse {
+    fn from(session: &USM) -> Self {
+        UploadSessionResponse {
+            session_id: session.session_id.to_string(),
+            offset: session.committed_offset,
+            size: session.total_size,
+            expires_at: session.expires_at.and_utc().timestamp(),
+        }
+    }
+}
+
+fn upload_session_ttl() -> chrono::Duration {
+    let hours = env::var("UPLOAD_SESSION_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_UPLOAD_SESSION_TTL_HOURS);
+    chrono::Duration::hours(hours)
+}
+
+/// Parse `Content-Range: bytes <start>-<end>/<total|*>` into (start, end, total).
+fn parse_content_range(headers: &HeaderMap) -> Option<(i64, i64, Option<i64>)> {
+    let value = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let start = start.trim().parse::<i64>().ok()?;
+    let end = end.trim().parse::<i64>().ok()?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse::<i64>().ok()?),
+    };
+    if end < start {
+        return None;
+    }
+    Some((start, end, total))
+}
+
+fn session_response(status: StatusCode, session: &USM) -> Response {
+    (status, Json(UploadSessionResponse::from(session))).into_response()
+}
+
+/// The session, if the caller may upload to its device: the device itself, its owner or a
+/// superuser.
+async fn find_upload_session(
+    ctx: &AppContext,
+    auth: &crate::middleware::auth::MyJWT,
+    session_id: &str,
+) -> std::result::Result<USM, (StatusCode, &'static str)> {
+    let Ok(session_id) = Uuid::parse_str(session_id) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid session id"));
+    };
+    let session = match USM::find_session(&ctx.db, &session_id).await {
+        Ok(session) => session,
+        Err(ModelError::EntityNotFound) => return Err((StatusCode::NOT_FOUND, "Upload session not found or expired")),
         Err(e) => {
-            
-            tracing::error!("PUT request failed: {}", format!("{}", e));
-            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong"));
+            tracing::error!("Failed to look up upload session {session_id}: {e}");
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong"));
+        }
+    };
+    let allowed = match (&auth.device_model, &auth.user_model) {
+        (Some(device), _) => device.dongle_id == session.dongle_id,
+        (None, Some(user_model)) => {
+            user_model.superuser
+                || DM::find_device(&ctx.db, &session.dongle_id)
+                    .await
+                    .is_ok_and(|device| device.owner_id == Some(user_model.id))
+        }
+        (None, None) => false,
+    };
+    if !allowed {
+        // Don't tell others which sessions exist
+        return Err((StatusCode::NOT_FOUND, "Upload session not found or expired"));
+    }
+    Ok(session)
+}
+
+/// A put found the part already there, left by a request that wrote it but never committed
+/// it or by one still racing this one. A part of the right size can be committed as is,
+/// anything else is deleted so the retry can write it again. Returns whether to commit.
+async fn recover_existing_part(store: &dyn BlobStore, part_key: &str, expected_len: i64) -> StorageResult<bool> {
+    match store.stat(part_key).await? {
+        Some(size) if size as i64 == expected_len => Ok(true),
+        Some(_) => {
+            store.delete(part_key).await?;
+            Ok(false)
+        }
+        None => Ok(false),
+    }
+}
+
+async fn delete_upload_parts(session: &USM) {
+    let store = storage::blob_store();
+    for index in 0..session.part_count {
+        let part_key = session.part_key(index);
+        if let Err(e) = store.delete(&part_key).await {
+            tracing::error!("Failed to delete upload part {part_key}: {e}");
+        }
+    }
+}
+
+/// Start (or resume) a resumable upload for a segment file. The optional `size` query
+/// lets finalize verify that every byte arrived.
+pub async fn create_upload_session(
+    auth: crate::middleware::auth::MyJWT,
+    Path((dongle_id, timestamp, segment, file)): Path<(String, String, String, String)>,
+    State(ctx): State<AppContext>,
+    Query(params): Query<CreateUploadParams>,
+) -> Result<Response> {
+    if let Err(e) = validate_segment_path(&dongle_id, &timestamp, &segment, &file) {
+        return Ok(e.into_response());
+    }
+    if let Err(e) = enforce_upload_permission(&ctx, &auth, &dongle_id, &file, params.size.unwrap_or(0)).await {
+        return Ok(e.into_response());
+    }
+    // An expired session, or one for a different size of the file, starts over
+    let file_key = USM::file_key_for(&dongle_id, &timestamp, &segment, &file);
+    match USM::find_file_session(&ctx.db, &file_key).await {
+        Ok(Some(stale)) if stale.expires_at <= chrono::Utc::now().naive_utc()
+            || (params.size.is_some() && params.size != stale.total_size) =>
+        {
+            tracing::info!("Starting the upload of {file_key} over");
+            delete_upload_parts(&stale).await;
+            if let Err(e) = USM::delete_session(&ctx.db, stale.id).await {
+                tracing::error!("Failed to delete upload session {}: {e}", stale.session_id);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!("Failed to look up the upload session of {file_key}: {e}");
+            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to create upload session").into_response());
+        }
+    }
+    match USM::find_or_create(&ctx.db, &dongle_id, &timestamp, &segment, &file, params.size, upload_session_ttl()).await {
+        Ok(session) => {
+            tracing::debug!("Upload session {} for {} at offset {}", session.session_id, session.file_key, session.committed_offset);
+            Ok(session_response(StatusCode::OK, &session))
+        }
+        Err(e) => {
+            tracing::error!("Failed to create upload session: {e}");
+            Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to create upload session").into_response())
+        }
+    }
+}
+
+pub async fn get_upload_session(
+    auth: crate::middleware::auth::MyJWT,
+    Path(session_id): Path<String>,
+    State(ctx): State<AppContext>,
+) -> Result<Response> {
+    match find_upload_session(&ctx, &auth, &session_id).await {
+        Ok(session) => Ok(session_response(StatusCode::OK, &session)),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+/// Append a byte range to the session. The range has to start at the committed offset,
+/// otherwise 409 is returned with the offset the device should resume from.
+pub async fn put_upload_chunk(
+    auth: crate::middleware::auth::MyJWT,
+    Path(session_id): Path<String>,
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    body: axum::body::Body,
+) -> Result<Response> {
+    let session = match find_upload_session(&ctx, &auth, &session_id).await {
+        Ok(session) => session,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let Some(_slot) = acquire_upload_slot(&session.dongle_id) else {
+        tracing::warn!("Too many uploads in flight for {}", session.dongle_id);
+        return Ok((StatusCode::TOO_MANY_REQUESTS, "Too many uploads in flight").into_response());
+    };
+    let Some((start, end, total)) = parse_content_range(&headers) else {
+        return Ok((StatusCode::BAD_REQUEST, "Missing or invalid Content-Range").into_response());
+    };
+    if start != session.committed_offset {
+        tracing::debug!("Chunk for {} starts at {start} but committed offset is {}", session.file_key, session.committed_offset);
+        return Ok(session_response(StatusCode::CONFLICT, &session));
+    }
+    if let (Some(total), Some(total_size)) = (total, session.total_size) {
+        if total != total_size {
+            return Ok((StatusCode::BAD_REQUEST, "Content-Range total does not match the session size").into_response());
+        }
+    }
+    if session.total_size.is_some_and(|total_size| end >= total_size) {
+        return Ok((StatusCode::RANGE_NOT_SATISFIABLE, "Range is past the end of the file").into_response());
+    }
+
+    let expected_len = end - start + 1;
+    // Parts aren't counted until finalize, so check the whole file so far against the quota
+    let room = match enforce_upload_permission(&ctx, &auth, &session.dongle_id, &session.file, end + 1).await {
+        Ok(room) => room,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let part_key = session.part_key(session.part_count);
+    match stream_to_store(&part_key, &headers, body, room).await {
+        Ok(upload) => match upload.status {
+            StatusCode::CREATED | StatusCode::OK => {
+                if upload.bytes != expected_len {
+                    tracing::warn!("Chunk for {} was {} bytes, expected {expected_len}", session.file_key, upload.bytes);
+                    if let Err(e) = storage::blob_store().delete(&part_key).await {
+                        tracing::error!("Failed to delete short upload part {part_key}: {e}");
+                    }
+                    return Ok((StatusCode::BAD_REQUEST, "Chunk length does not match Content-Range").into_response());
+                }
+                match session.commit_part(&ctx.db, upload.bytes, upload_session_ttl()).await {
+                    Ok(session) => Ok(session_response(StatusCode::OK, &session)),
+                    Err(e) => {
+                        tracing::error!("Failed to commit upload part: {e}");
+                        Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to commit chunk").into_response())
+                    }
+                }
+            }
+            StatusCode::FORBIDDEN => match recover_existing_part(storage::blob_store().as_ref(), &part_key, expected_len).await {
+                Ok(true) => match session.commit_part(&ctx.db, expected_len, upload_session_ttl()).await {
+                    Ok(session) => Ok(session_response(StatusCode::OK, &session)),
+                    Err(e) => {
+                        tracing::error!("Failed to commit upload part: {e}");
+                        Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to commit chunk").into_response())
+                    }
+                },
+                // The stale part is gone, the device resends from the same offset
+                Ok(false) => Ok(session_response(StatusCode::CONFLICT, &session)),
+                Err(e) => {
+                    tracing::error!("Failed to recover upload part {part_key}: {e}");
+                    Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to commit chunk").into_response())
+                }
+            },
+            status => {
+                tracing::error!("Unhandled status {status}. Chunk not uploaded.");
+                Ok((status, "Unhandled status. Chunk not uploaded.").into_response())
+            }
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+/// Stitch the parts together into the real segment file and hand it to the log parser.
+pub async fn finalize_upload_session(
+    auth: crate::middleware::auth::MyJWT,
+    Path(session_id): Path<String>,
+    State(ctx): State<AppContext>,
+) -> Result<Response> {
+    let session = match find_upload_session(&ctx, &auth, &session_id).await {
+        Ok(session) => session,
+        Err(e) => return Ok(e.into_response()),
+    };
+    if !session.is_complete() {
+        return Ok(session_response(StatusCode::CONFLICT, &session));
+    }
+
+    let store = storage::blob_store();
+    let part_keys: Vec<String> = (0..session.part_count)
+        .map(|index| session.part_key(index))
+        .collect();
+    let parts_store = store.clone();
+    let parts: ByteStream = Box::pin(
+        stream::iter(part_keys)
+            .then(move |part_key| {
+                let store = parts_store.clone();
+                async move { store.get(&part_key).await.map(|part| part.stream) }
+            })
+            .map_err(io::Error::from)
+            .try_flatten(),
+    );
+
+    let status = match store.put(&session.file_key, parts, Some(session.committed_offset as u64)).await {
+        Ok(()) => StatusCode::CREATED,
+        Err(StorageError::AlreadyExists(_)) => StatusCode::FORBIDDEN,
+        Err(e) => {
+            tracing::error!("Failed to assemble {}: {e}", session.file_key);
+            return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to assemble upload").into_response());
+        }
+    };
+    match status {
+        StatusCode::FORBIDDEN => { tracing::error!("Duplicate file uploaded");}
+        StatusCode::CREATED | StatusCode::OK => {
+            add_server_storage(&ctx, &session.file_key, session.committed_offset).await;
+        }
+        _ => {
+            tracing::error!("Unhandled status {status}. File not assembled.");
+            return Ok((status, "Unhandled status. File not assembled.").into_response());
+        }
+    }
+
+    delete_upload_parts(&session).await;
+    if let Err(e) = USM::delete_session(&ctx.db, session.id).await {
+        tracing::error!("Failed to delete upload session {}: {e}", session.session_id);
+    }
+    if status == StatusCode::FORBIDDEN {
+        // The first upload was already queued
+        return Ok((status, "Duplicate File Upload").into_response());
+    }
+
+    tracing::debug!("Resumable upload finalized. Queuing worker for {}", session.file_key);
+    let result = LogSegmentWorker::perform_later(&ctx,
+        LogSegmentWorkerArgs {
+            file_key: session.file_key,
+            dongle_id: session.dongle_id,
+            timestamp: session.timestamp,
+            segment: session.segment,
+            file: session.file,
+            create_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64,
+        },
+    ).await;
+    match result {
+        Ok(_) => {
+            tracing::debug!("Queued Worker");
+            Ok((status, "Queued Worker").into_response())
+        }
+        Err(e) => {
+            tracing::error!("Failed to queue worker: {}", format!("{}", e));
+            Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed to queue worker.").into_response())
+        }
+    }
+}
+
+/// Drop sessions the device never finished along with any parts they left behind.
+pub async fn gc_expired_upload_sessions(db: &DatabaseConnection) {
+    let sessions = match USM::find_expired(db).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            tracing::error!("Failed to find expired upload sessions: {e}");
+            return;
+        }
+    };
+    for session in sessions {
+        tracing::info!("Expiring upload session {} for {}", session.session_id, session.file_key);
+        delete_upload_parts(&session).await;
+        if let Err(e) = USM::delete_session(db, session.id).await {
+            tracing::error!("Failed to delete upload session {}: {e}", session.session_id);
         }
     }
 }
@@ -269,4 +794,56 @@ pub fn routes() -> Routes {
         .add("/:dongle_id/:timestamp/:segment/:file", put(upload_driving_logs))
         .add("/:dongle_id/crash/:log_id/:commit/:name", put(upload_crash))
         .add("/:dongle_id/boot/:file", put(upload_bootlogs))
+        .add("/uploads/:dongle_id/:timestamp/:segment/:file", post(create_upload_session))
+        .add("/upload_sessions/:session_id", get(get_upload_session).put(put_upload_chunk))
+        .add("/upload_sessions/:session_id/finalize", post(finalize_upload_session))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::storage::local::LocalStore;
+
+    fn bytes_stream(data: &'static [u8]) -> ByteStream {
+        Box::pin(stream::iter([Ok(bytes::Bytes::from_static(data))]))
+    }
+
+    #[tokio::test]
+    async fn recovers_part_left_by_failed_commit() {
+        let root = std::env::temp_dir().join(format!("upload-parts-{}", Uuid::new_v4()));
+        let store = LocalStore::new(root.clone());
+
+        // The put went through but the commit didn't, the retry finds a complete part
+        store.put("abc_upload_part_00000", bytes_stream(b"0123456789"), Some(10)).await.unwrap();
+        assert!(recover_existing_part(&store, "abc_upload_part_00000", 10).await.unwrap());
+
+        // A part that doesn't match the retried range is dropped so it can be written again
+        assert!(!recover_existing_part(&store, "abc_upload_part_00000", 4).await.unwrap());
+        assert_eq!(store.stat("abc_upload_part_00000").await.unwrap(), None);
+        store.put("abc_upload_part_00000", bytes_stream(b"0123"), Some(4)).await.unwrap();
+
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[tokio::test]
+    async fn cuts_off_chunked_body_over_quota() {
+        let root = std::env::temp_dir().join(format!("upload-quota-{}", Uuid::new_v4()));
+        let store = LocalStore::new(root.clone());
+        let chunked = || stream::iter((0..4).map(|_| Ok::<_, io::Error>(bytes::Bytes::from_static(b"0123456789"))));
+
+        // No Content-Length, so only counting the body can stop it
+        let result = put_limited(&store, "abc_qlog.zst", chunked(), None, Some(25)).await;
+        assert_eq!(result.err().map(|(status, _)| status), Some(StatusCode::INSUFFICIENT_STORAGE));
+        assert_eq!(store.stat("abc_qlog.zst").await.unwrap(), None);
+
+        // Nor can a body get in by declaring less than it sends
+        let result = put_limited(&store, "abc_qlog.zst", chunked(), Some(10), None).await;
+        assert_eq!(result.err().map(|(status, _)| status), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(store.stat("abc_qlog.zst").await.unwrap(), None);
+
+        let upload = put_limited(&store, "abc_qlog.zst", chunked(), None, Some(40)).await.ok().unwrap();
+        assert_eq!(upload.bytes, 40);
+
+        std::fs::remove_dir_all(root).ok();
+    }
 }
@@ -0,0 +1,179 @@
+#![allow(clippy::unused_async)]
+use std::collections::HashMap;
+
+use axum::extract::{Path, Query, State};
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    middleware::auth::MyJWT,
+    models::{
+        crash_groups::CGM,
+        crashes::{CrashFilter, CM},
+        devices::DM,
+    },
+};
+
+const DEFAULT_CRASH_LIMIT: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct CrashQuery {
+    dongle_id: Option<String>,
+    commit: Option<String>,
+    branch: Option<String>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct CommitCount {
+    commit: String,
+    branch: Option<String>,
+    count: i64,
+    devices: i64,
+}
+
+#[derive(Serialize)]
+pub struct CrashGroupResponse {
+    signature: String,
+    exception_type: String,
+    file: Option<String>,
+    line: Option<i32>,
+    function: Option<String>,
+    first_seen: i64,
+    last_seen: i64,
+    count: i64,
+    commits: Vec<CommitCount>,
+}
+
+#[derive(Serialize)]
+pub struct CrashResponse {
+    dongle_id: String,
+    log_id: String,
+    commit: String,
+    branch: Option<String>,
+    name: String,
+    exception_type: String,
+    message: String,
+    stack_trace: String,
+    file: Option<String>,
+    line: Option<i32>,
+    function: Option<String>,
+    create_time: i64,
+}
+
+impl From<CM> for CrashResponse {
+    fn from(crash: CM) -> Self {
+        CrashResponse {
+            dongle_id: crash.dongle_id,
+            log_id: crash.log_id,
+            commit: crash.commit,
+            branch: crash.branch,
+            name: crash.name,
+            exception_type: crash.exception_type,
+            message: crash.message,
+            stack_trace: crash.stack_trace,
+            file: crash.file,
+            line: crash.line,
+            function: crash.function,
+            create_time: crash.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+/// Superusers see the whole fleet, everyone else only the devices they own.
+async fn crash_filter(
+    ctx: &AppContext,
+    auth: &MyJWT,
+    query: &CrashQuery,
+) -> Result<CrashFilter> {
+    let Some(user_model) = &auth.user_model else {
+        return Err(Error::Unauthorized("Devices can't do this".to_string()));
+    };
+    let dongle_ids = if user_model.superuser {
+        query.dongle_id.clone().map(|dongle_id| vec![dongle_id])
+    } else {
+        let owned: Vec<String> = DM::find_user_devices(&ctx.db, user_model.id)
+            .await
+            .into_iter()
+            .map(|device| device.dongle_id)
+            .collect();
+        match &query.dongle_id {
+            Some(dongle_id) if !owned.contains(dongle_id) => {
+                return Err(Error::Unauthorized("You are not the owner".to_string()));
+            }
+            Some(dongle_id) => Some(vec![dongle_id.clone()]),
+            None => Some(owned),
+        }
+    };
+    Ok(CrashFilter {
+        dongle_ids,
+        commit: query.commit.clone(),
+        branch: query.branch.clone(),
+    })
+}
+
+/// Crash groups with how often they happened on each commit/branch, most frequent first.
+pub async fn list_groups(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Query(query): Query<CrashQuery>,
+) -> Result<Response> {
+    let filter = crash_filter(&ctx, &auth, &query).await?;
+    let counts = CM::group_counts(&ctx.db, &filter).await?;
+
+    let mut commits_by_group: HashMap<i32, Vec<CommitCount>> = HashMap::new();
+    for (crash_group_id, commit, branch, count, devices) in counts {
+        commits_by_group
+            .entry(crash_group_id)
+            .or_default()
+            .push(CommitCount { commit, branch, count, devices });
+    }
+    let groups = CGM::find_by_ids(&ctx.db, commits_by_group.keys().copied().collect()).await?;
+
+    let mut response: Vec<CrashGroupResponse> = groups
+        .into_iter()
+        .map(|group| {
+            let mut commits = commits_by_group.remove(&group.id).unwrap_or_default();
+            commits.sort_by(|a, b| b.count.cmp(&a.count));
+            CrashGroupResponse {
+                signature: group.signature,
+                exception_type: group.exception_type,
+                file: group.file,
+                line: group.line,
+                function: group.function,
+                first_seen: group.first_seen.and_utc().timestamp(),
+                last_seen: group.last_seen.and_utc().timestamp(),
+                count: commits.iter().map(|c| c.count).sum(),
+                commits,
+            }
+        })
+        .collect();
+    response.sort_by(|a, b| b.count.cmp(&a.count));
+    format::json(response)
+}
+
+/// The most recent crashes in a group.
+pub async fn list_group_crashes(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path(signature): Path<String>,
+    Query(query): Query<CrashQuery>,
+) -> Result<Response> {
+    let filter = crash_filter(&ctx, &auth, &query).await?;
+    let group = CGM::find_by_signature(&ctx.db, &signature).await?;
+    let limit = query.limit.unwrap_or(DEFAULT_CRASH_LIMIT).min(1000);
+    let crashes = CM::find_group_crashes(&ctx.db, group.id, &filter, limit).await?;
+    format::json(
+        crashes
+            .into_iter()
+            .map(CrashResponse::from)
+            .collect::<Vec<_>>(),
+    )
+}
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("v1/crashes")
+        .add("/groups", get(list_groups))
+        .add("/groups/:signature", get(list_group_crashes))
+}
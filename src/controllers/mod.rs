@@ -7,4 +7,10 @@ pub mod ws;
 pub mod v1_responses;
 pub mod maps;
 pub mod params;
-pub mod stats;
\ No newline at end of file
+pub mod stats;
+pub mod crashes;
+pub mod clips;
+pub mod reprocess;
+pub mod worker_jobs;
+pub mod offline_queue;
+pub mod cloudlog_alerts;
\ No newline at end of file
@@ -0,0 +1,125 @@
+#![allow(clippy::unused_async)]
+use axum::extract::{Path, Query, State};
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    middleware::auth::MyJWT,
+    models::{device_msg_queues::DMQM, devices::DM},
+};
+
+const DEFAULT_MSG_LIMIT: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct OfflineQueueQuery {
+    pub status: Option<String>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct OfflineMsgResponse {
+    id: i32,
+    method: String,
+    params: Option<serde_json::Value>,
+    /// queued, sent, done, failed, expired or cancelled
+    status: String,
+    attempts: i32,
+    max_attempts: i32,
+    /// Unix time, 0 never expires
+    expiry: i64,
+    /// Unix time of the last attempt, 0 if it never went out
+    sent_time: i64,
+    /// What the device answered
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+    create_time: i64,
+    update_time: i64,
+}
+
+impl From<DMQM> for OfflineMsgResponse {
+    fn from(msg: DMQM) -> Self {
+        OfflineMsgResponse {
+            params: msg.json_rpc_request.get("params").cloned(),
+            id: msg.id,
+            method: msg.method,
+            status: msg.status,
+            attempts: msg.attempts,
+            max_attempts: msg.max_attempts,
+            expiry: msg.expires_at,
+            sent_time: msg.sent_at,
+            result: msg.result,
+            error: msg.error,
+            create_time: msg.created_at.and_utc().timestamp(),
+            update_time: msg.updated_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OfflineQueueResponse {
+    total: u64,
+    messages: Vec<OfflineMsgResponse>,
+}
+
+async fn ensure_device_access(ctx: &AppContext, auth: &MyJWT, dongle_id: &str) -> Result<()> {
+    let Some(user_model) = &auth.user_model else {
+        return Err(Error::Unauthorized("Devices can't do this".to_string()));
+    };
+    if !user_model.superuser {
+        DM::ensure_user_device(&ctx.db, user_model.id, dongle_id).await?;
+    }
+    Ok(())
+}
+
+/// Commands queued for a device while it was offline, newest first.
+pub async fn list_msgs(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path(dongle_id): Path<String>,
+    Query(query): Query<OfflineQueueQuery>,
+) -> Result<Response> {
+    ensure_device_access(&ctx, &auth, &dongle_id).await?;
+    let (msgs, total) = DMQM::find_device_msgs(
+        &ctx.db,
+        &dongle_id,
+        query.status.as_deref(),
+        query.limit.unwrap_or(DEFAULT_MSG_LIMIT),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    format::json(OfflineQueueResponse {
+        total,
+        messages: msgs.into_iter().map(OfflineMsgResponse::from).collect(),
+    })
+}
+
+pub async fn get_msg(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path((dongle_id, id)): Path<(String, i32)>,
+) -> Result<Response> {
+    ensure_device_access(&ctx, &auth, &dongle_id).await?;
+    format::json(OfflineMsgResponse::from(DMQM::find_device_msg(&ctx.db, &dongle_id, id).await?))
+}
+
+/// A command that already has an answer can't be cancelled.
+pub async fn cancel_msg(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path((dongle_id, id)): Path<(String, i32)>,
+) -> Result<Response> {
+    ensure_device_access(&ctx, &auth, &dongle_id).await?;
+    let msg = DMQM::find_device_msg(&ctx.db, &dongle_id, id).await?;
+    if !DMQM::cancel(&ctx.db, &dongle_id, msg.id).await? {
+        return loco_rs::controller::bad_request(format!("the command is already {}", msg.status));
+    }
+    format::json(OfflineMsgResponse::from(DMQM::find_device_msg(&ctx.db, &dongle_id, id).await?))
+}
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("v1")
+        .add("/devices/:dongle_id/athena_offline_queue", get(list_msgs))
+        .add("/devices/:dongle_id/athena_offline_queue/:id", get(get_msg).delete(cancel_msg))
+}
@@ -0,0 +1,139 @@
+#![allow(clippy::unused_async)]
+use axum::extract::{Path, State};
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    middleware::auth::MyJWT,
+    models::reprocess_jobs::{ReprocessScope, JOB_CANCELLED, JOB_DONE, RJM},
+    workers::{
+        log_parser::PARSER_VERSION,
+        reprocess::{ReprocessWorker, ReprocessWorkerArgs},
+    },
+};
+
+const RECENT_JOBS: u64 = 50;
+
+/// What to parse again. `segment`, `route` and `dongle_id` pick the segments, `below_version`
+/// keeps only those parsed by an older parser. Any combination works, e.g. `below_version`
+/// alone is everything that is out of date.
+#[derive(Deserialize)]
+pub struct ReprocessRequest {
+    dongle_id: Option<String>,
+    route: Option<String>,
+    segment: Option<String>,
+    below_version: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct ReprocessJobResponse {
+    id: i32,
+    dongle_id: Option<String>,
+    route: Option<String>,
+    segment: Option<String>,
+    below_version: Option<i32>,
+    status: String,
+    total: i32,
+    processed: i32,
+    failed: i32,
+    progress: f32,
+    /// Last segment that failed and why
+    error: Option<String>,
+    create_time: i64,
+    update_time: i64,
+}
+
+impl From<RJM> for ReprocessJobResponse {
+    fn from(job: RJM) -> Self {
+        ReprocessJobResponse {
+            progress: job.progress(),
+            id: job.id,
+            dongle_id: job.dongle_id,
+            route: job.route_fullname,
+            segment: job.segment_name,
+            below_version: job.below_version,
+            status: job.status,
+            total: job.total,
+            processed: job.processed,
+            failed: job.failed,
+            error: job.error,
+            create_time: job.created_at.and_utc().timestamp(),
+            update_time: job.updated_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReprocessJobsResponse {
+    parser_version: i32,
+    jobs: Vec<ReprocessJobResponse>,
+}
+
+fn ensure_superuser(auth: &MyJWT) -> Result<()> {
+    if !auth.user_model.as_ref().map_or(false, |user_model| user_model.superuser) {
+        return Err(Error::Unauthorized("Only superusers can reprocess logs".to_string()));
+    }
+    Ok(())
+}
+
+/// Queue a reprocess job, it's worked through after the jobs before it.
+pub async fn create_job(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Json(request): Json<ReprocessRequest>,
+) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    let scope = ReprocessScope {
+        dongle_id: request.dongle_id,
+        route_fullname: request.route,
+        segment_name: request.segment,
+        below_version: request.below_version,
+    };
+    if scope.is_empty() {
+        return loco_rs::controller::bad_request("give a segment, route, dongle_id or below_version");
+    }
+    let job = RJM::add_job(&ctx.db, scope).await?;
+    tracing::info!("Queued reprocess job {} for {} segments", job.id, job.total);
+    // Off the request, a blocking worker mode would run the whole job here otherwise
+    tokio::spawn({
+        let ctx = ctx.clone();
+        async move {
+            if let Err(e) = ReprocessWorker::perform_later(&ctx, ReprocessWorkerArgs::default()).await {
+                tracing::error!("Failed to queue reprocessing: {e}");
+            }
+        }
+    });
+    format::json(ReprocessJobResponse::from(job))
+}
+
+/// The current parser version and the most recent jobs, newest first.
+pub async fn list_jobs(auth: MyJWT, State(ctx): State<AppContext>) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    let jobs = RJM::find_recent_jobs(&ctx.db, RECENT_JOBS).await?;
+    format::json(ReprocessJobsResponse {
+        parser_version: PARSER_VERSION,
+        jobs: jobs.into_iter().map(ReprocessJobResponse::from).collect(),
+    })
+}
+
+pub async fn get_job(auth: MyJWT, State(ctx): State<AppContext>, Path(id): Path<i32>) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    format::json(ReprocessJobResponse::from(RJM::find_job(&ctx.db, id).await?))
+}
+
+/// Stops a job after the batch it's on. Segments it already did stay reprocessed.
+pub async fn cancel_job(auth: MyJWT, State(ctx): State<AppContext>, Path(id): Path<i32>) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    let job = RJM::find_job(&ctx.db, id).await?;
+    if job.status != JOB_DONE {
+        RJM::set_status(&ctx.db, id, JOB_CANCELLED).await?;
+    }
+    format::json(ReprocessJobResponse::from(RJM::find_job(&ctx.db, id).await?))
+}
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("v1/admin")
+        .add("/reprocess", get(list_jobs).post(create_job))
+        .add("/reprocess/:id", get(get_job).delete(cancel_job))
+}
@@ -1,31 +1,30 @@
 #![allow(clippy::unused_async)]
-use async_compression::tokio::bufread;
 use loco_rs::prelude::*;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use axum::{
     extract::{Query, State}, Extension,
 };
-use tokio_util::io::StreamReader;
 extern crate url;
-use std::{collections::HashMap, env, io::Cursor};
+use std::{collections::HashMap, env};
 use axum::response::{Redirect, IntoResponse};
 use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 
 use crate::{
     enforce_ownership_rule,
     cereal::log_capnp::event as LogEvent, 
-    common::{mkv_helpers, re::*}, 
+    common::{log_reader::LogReader, re::*, storage}, 
     models::{
         users::UM,
         routes::RM,
         devices::DM,
         bootlogs::BM,
         segments::SM,
+        worker_jobs::WJM,
     },
     views,
 };
+use super::worker_jobs::JobQuery;
 
 #[derive(Deserialize)]
 pub struct OneBox {
@@ -65,6 +64,17 @@ pub struct CloudlogsTemplate {
 }
 
 
+#[derive(Serialize)]
+pub struct JobsTemplate {
+    /// "all" or one of the job statuses
+    pub status: String,
+    pub total: u64,
+    pub offset: u64,
+    pub limit: u64,
+    pub jobs: Vec<WJM>,
+}
+
+
 #[derive(Serialize, Default)]
 pub struct MasterTemplate {
     pub api_host: String,
@@ -237,11 +247,13 @@ pub struct UlogText {
 }
 
 
+/// The page can't usefully show more than this of a single event type.
+const MAX_RENDERED_UNLOG: usize = 16 * 1024 * 1024;
+
 pub async fn qlog_render(
     auth: crate::middleware::auth::MyJWT,
     ViewEngine(v): ViewEngine<TeraView>,
     State(ctx): State<AppContext>,
-    Extension(client): Extension<Client>,
     Query(params): Query<UlogQuery>
 ) -> Result<impl IntoResponse> {
     // Validate the URL
@@ -264,21 +276,9 @@ pub async fn qlog_render(
             return Err(Error::Message("Devices can't do this".to_string()));
         }
 
-        // Always use mkv_helpers::get_mkv_file_url with the second part (lookup key)
-        let internal_file_url = mkv_helpers::get_mkv_file_url(&captures[1]);
-        
-        // Proceed with the request using the `internal_file_url`
-        let request = client.get(&internal_file_url);
-    
-        // Get the data and save it as a string to pass to admin_segment_ulog
-        match request.send().await {
-            Ok(response) => {
-                if response.status().is_success() {
-                    response
-                } else {
-                    return Err(Error::Message(format!("Failed to get file: {}", response.status())));
-                }
-            }
+        // Always fetch by the captured lookup key, never by whatever else is in the url
+        match storage::blob_store().get(&captures[1]).await {
+            Ok(object) => object.stream,
             Err(e) => {
                 return Err(Error::Message(format!("Failed to get file: {}", e)));
             }
@@ -287,38 +287,23 @@ pub async fn qlog_render(
         return Err(Error::Message("Invalid file name".to_string()));
     };
                 
-    // Decompress the file
-    use futures_util::TryStreamExt;
-    let bytes_stream = response
-        .bytes_stream()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
-    
-    let stream_reader = StreamReader::new(bytes_stream);
-    
-    let file_name = params.url.clone();
-    let mut decoder: std::pin::Pin<Box<dyn tokio::io::AsyncRead + Send>> = if file_name.ends_with(".bz2") {
-        Box::pin(bufread::BzDecoder::new(stream_reader))
-    } else if file_name.ends_with(".zst") {
-        Box::pin(bufread::ZstdDecoder::new(stream_reader))
-    } else {
-        return Err(Error::Message("Invalid file type. Must end with .bz2 or .zst".to_string()));
-    };
-    
-    let mut decompressed_data = Vec::new();
-    match tokio::io::AsyncReadExt::read_to_end(&mut decoder, &mut decompressed_data).await {
-        Ok(_) => (),
-        Err(e) => return Err(Error::Message(e.to_string()))
-    };
-
-    let mut cursor = Cursor::new(decompressed_data);
+    let mut reader = LogReader::new(response, &params.url).map_err(|e| Error::Message(e.to_string()))?;
     let mut unlog_data = Vec::new();
-    let reader_options = capnp::message::ReaderOptions::default();
-    
+    let mut truncated = false;
+
     // Create a set to store event names
     let mut event_types = std::collections::HashSet::new();
 
     use std::io::Write;
-    while let Ok(message_reader) = capnp::serialize::read_message(&mut cursor, reader_options) {
+    loop {
+        let message_reader = match reader.next_message().await {
+            Ok(Some(message_reader)) => message_reader,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Stopped reading {} early: {}", params.url, e);
+                break;
+            }
+        };
         let event = match message_reader.get_root::<LogEvent::Reader>() {
             Ok(event) => event,
             Err(e) => {
@@ -337,8 +322,9 @@ pub async fn qlog_render(
                 event_types.insert(type_name.clone());
                 // If an event is requested, only output that event's data
                 if let Some(ref requested_event) = params.event {
-                    if type_name == *requested_event {
+                    if type_name == *requested_event && !truncated {
                         writeln!(&mut unlog_data, "{:#?}", event).unwrap_or(());
+                        truncated = unlog_data.len() > MAX_RENDERED_UNLOG;
                     }
                 }
             }
@@ -349,6 +335,9 @@ pub async fn qlog_render(
     let mut event_list: Vec<String> = event_types.into_iter().collect();
     event_list.sort();
 
+    if truncated {
+        writeln!(&mut unlog_data, "... truncated at {} MiB", MAX_RENDERED_UNLOG / 1024 / 1024).unwrap_or(());
+    }
     let data = if let Some(_) = params.event {
         String::from_utf8(unlog_data).unwrap_or_else(|_| "Failed to convert log data to string".to_string())
     } else if !event_list.is_empty() {
@@ -374,6 +363,31 @@ pub async fn cloudlogs_view(
     })
 }
 
+/// Worker jobs with their errors and a retry button, the failed ones unless asked otherwise.
+pub async fn jobs_view(
+    auth: crate::middleware::auth::MyJWT,
+    ViewEngine(v): ViewEngine<TeraView>,
+    State(ctx): State<AppContext>,
+    Query(query): Query<JobQuery>,
+) -> Result<impl IntoResponse> {
+    if !auth.user_model.map_or(false, |user_model| user_model.superuser) {
+        return unauthorized("Only superusers can see worker jobs");
+    }
+    let status = query.status.unwrap_or_else(|| "failed".to_string());
+    let limit = query.limit.unwrap_or(100);
+    let offset = query.offset.unwrap_or(0);
+    let (jobs, total) = WJM::find_jobs(
+        &ctx.db,
+        Some(status.as_str()).filter(|status| *status != "all"),
+        query.worker.as_deref(),
+        query.dongle_id.as_deref(),
+        limit,
+        offset,
+    )
+    .await?;
+    views::route::admin_jobs(v, JobsTemplate { status, total, offset, limit, jobs })
+}
+
 pub async fn login(
     ViewEngine(v): ViewEngine<TeraView>,
     State(_ctx): State<AppContext>,
@@ -403,6 +417,7 @@ pub fn routes() -> Routes {
         .add("/", get(onebox_handler))
         .add("/login", get(login))
         .add("/cloudlogs", get(cloudlogs_view))
+        .add("/jobs", get(jobs_view))
         .add("/qlog", get(qlog_render))
         .add("/auth/logout", get(logout))
 
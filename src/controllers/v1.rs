@@ -2,7 +2,7 @@ use loco_rs::prelude::*;
 use axum::{
     extract::{Path, Query, State}, routing::patch, Extension
 };
-use reqwest::{StatusCode,Client};
+use reqwest::StatusCode;
 use serde_json::{json, Value};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -17,9 +17,10 @@ use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use jsonwebtoken::get_current_timestamp;
 
-use crate::{common, 
+use crate::{common::{self, quota::QuotaUsage, track::{self, TrackFormat, TrackPoint}}, 
     middleware::{jwt, auth::MyJWT}, 
     models::{
+        bootlogs::{BootlogSearch, BM},
         devices::DM,
         segments::SM,
         routes::RM,
@@ -93,13 +94,12 @@ pub async fn get_route_files(
     auth: MyJWT,
     State(ctx): State<AppContext>,
     Path(route_id): Path<String>,
-    Extension(client): Extension<Client>
 ) -> impl IntoResponse {
     // Do not need to check for data ownership because its done when you try to fetch the data
     let jwt_secret = ctx.config.get_jwt_config()?;
     if let Ok(token) = jwt::JWT::new(&jwt_secret.secret).generate_token(&(3600 * 24 as u64), auth.claims.identity.to_string()) {
         println!("Fetching files for Route ID: {}", route_id);
-        let response = get_links_for_route(&route_id, &client, &token).await;
+        let response = get_links_for_route(&route_id, &token).await;
         match response {
             Ok((_status, body)) => Ok(format::json(body)),
             Err(_) => unauthorized("err"),
@@ -117,6 +117,7 @@ async fn get_camera_stream<F>(
     Path(canonical_route_name): Path<String>,
     mut url_and_duration: F,
     url_field: &'static str,
+    init_file: Option<&'static str>,
 ) -> Result<Response>
 where
     F: FnMut(&mut SM) -> Option<(String, f64)> + Send,
@@ -140,23 +141,30 @@ where
 
     let mut response = String::new();
     response.push_str("#EXTM3U\n");
-    response.push_str("#EXT-X-VERSION:3\n");
+    // fMP4 segments need EXT-X-MAP, which needs a newer version
+    response.push_str(if init_file.is_some() { "#EXT-X-VERSION:7\n" } else { "#EXT-X-VERSION:3\n" });
     response.push_str("#EXT-X-TARGETDURATION:61\n");
     response.push_str("#EXT-X-MEDIA-SEQUENCE:0\n");
     response.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n");
 
     let mut prev_seg_number = 0;
+    let mut first = true;
     for segment in segment_models.iter_mut() {
-        if prev_seg_number < segment.number {
-            response.push_str("#EXT-X-DISCONTINUITY\n");
-            prev_seg_number = segment.number;
-        }
         if let Some((url, duration)) = url_and_duration(segment) {
+            // Every fMP4 segment was remuxed on its own, so each has its own init section and
+            // its timestamps start over at 0
+            if prev_seg_number < segment.number || (init_file.is_some() && !first) {
+                response.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+            if let (Some(init_file), Some((dir, _))) = (init_file, url.rsplit_once('/')) {
+                response.push_str(&format!("#EXT-X-MAP:URI=\"{}/{}?exp={}&sig={}\"\n", dir, init_file, exp, token));
+            }
             let url = format!("{}?exp={}&sig={}", url, exp, token);
             response.push_str(&format!("#EXTINF:{},{}\n", duration, segment.number));
             response.push_str(&format!("{}\n", url));
+            first = false;
         }
-        prev_seg_number += 1;
+        prev_seg_number = segment.number + 1;
     }
     response.push_str("#EXT-X-ENDLIST\n");
     Ok(response.into_response())
@@ -170,7 +178,35 @@ async fn get_qcam_stream(auth: MyJWT, State(ctx): State<AppContext>, Path(canoni
         } else {
             None
         }
-    }, "qcam_url").await
+    }, "qcam_url", None).await
+}
+
+/// The hevc cameras are served as the fMP4 the CameraRemuxWorker makes of them, so a segment
+/// only shows up once it has been remuxed.
+fn remuxed_camera_url(camera_url: &str, duration: f32, media_file: &str) -> Option<(String, f64)> {
+    if camera_url.is_empty() || duration <= 0.0 {
+        return None;
+    }
+    let (dir, _) = camera_url.rsplit_once('/')?;
+    Some((format!("{dir}/{media_file}"), duration as f64))
+}
+
+async fn get_fcam_stream(auth: MyJWT, State(ctx): State<AppContext>, Path(canonical_route_name): Path<String>) -> Result<Response> {
+    get_camera_stream(auth, State(ctx), Path(canonical_route_name), |seg| {
+        remuxed_camera_url(&seg.fcam_url, seg.fcam_duration, "fcamera.m4s")
+    }, "fcam_url", Some("fcamera_init.mp4")).await
+}
+
+async fn get_ecam_stream(auth: MyJWT, State(ctx): State<AppContext>, Path(canonical_route_name): Path<String>) -> Result<Response> {
+    get_camera_stream(auth, State(ctx), Path(canonical_route_name), |seg| {
+        remuxed_camera_url(&seg.ecam_url, seg.ecam_duration, "ecamera.m4s")
+    }, "ecam_url", Some("ecamera_init.mp4")).await
+}
+
+async fn get_dcam_stream(auth: MyJWT, State(ctx): State<AppContext>, Path(canonical_route_name): Path<String>) -> Result<Response> {
+    get_camera_stream(auth, State(ctx), Path(canonical_route_name), |seg| {
+        remuxed_camera_url(&seg.dcam_url, seg.dcam_duration, "dcamera.m4s")
+    }, "dcam_url", Some("dcamera_init.mp4")).await
 }
 
 async fn get_share_signature(
@@ -194,31 +230,24 @@ async fn get_share_signature(
 
 async fn get_links_for_route(
     route_id: &str,
-    client: &Client,
     jwt: &str
 ) -> Result<(StatusCode, FilesResponse), Box<dyn Error>> {
-    let key = common::mkv_helpers::list_keys_starting_with(&route_id.replace("|", "_"));
-    // Fetch data from the URL
-    let response = client.get(&key).send().await?;
-    let code = response.status();
-    // Parse the JSON response
-    let data: Value = response.json().await?;
-    // Ensure "keys" is an array and handle potential errors
-    let keys = data["keys"].as_array();
+    let keys = common::storage::blob_store()
+        .list_prefix(&route_id.replace("|", "_"))
+        .await?;
+    let code = StatusCode::OK;
     // Process keys to construct URLs
     let mut urls = Vec::new();
 
-    if let Some(keys) = keys {
-        keys.iter().filter_map(|key| key.as_str()).for_each(|key_str| {
-            if let [prefix, route] = key_str.split('_').collect::<Vec<_>>()[..] {
-                urls.push(format!("{}/connectdata{}/{}?sig={}",
-                    env::var("API_ENDPOINT").expect("API_ENDPOINT env variable not set"),
-                    prefix,
-                    transform_route_string(route),
-                    jwt
-                ));
-            }
-        });
+    for key_str in &keys {
+        if let [prefix, route] = key_str.split('_').collect::<Vec<_>>()[..] {
+            urls.push(format!("{}/connectdata/{}/{}?sig={}",
+                env::var("API_ENDPOINT").expect("API_ENDPOINT env variable not set"),
+                prefix,
+                transform_route_string(route),
+                jwt
+            ));
+        }
     }
     
     // Assuming sort_keys_to_response is an async function that takes a Vec<String> and returns a FilesResponse
@@ -444,6 +473,7 @@ async fn device_info(
     } else {
         auth.device_model.unwrap()
     };
+    let storage = QuotaUsage::for_device(&ctx.db, &device).await;
 
     format::json(
         DeviceInfoResponse {
@@ -458,6 +488,7 @@ async fn device_info(
             prime_type: device.prime_type,
             trial_claimed: true,
             sim_id: device.sim_id,
+            storage: Some(storage),
             ..Default::default()
         }
     )
@@ -557,7 +588,7 @@ async fn device_stats(
     let one_week_ago_millis = utc_time_now_millis - Duration::from_secs(7 * 24 * 60 * 60).as_millis() as i64;
 
     // Get total stats
-    let (total_length, route_count, total_millis) = RM::total_length_count_and_time_filtered(
+    let (total_length, route_count, total_millis, total_engaged) = RM::total_length_count_and_time_filtered(
         &ctx.db,
         &dongle_id,
         None, // No time filter for total stats
@@ -565,7 +596,7 @@ async fn device_stats(
     ).await?;
 
     // Get stats for the past week
-    let (week_length, week_count, week_millis) = RM::total_length_count_and_time_filtered(
+    let (week_length, week_count, week_millis, week_engaged) = RM::total_length_count_and_time_filtered(
         &ctx.db,
         &dongle_id,
         Some(one_week_ago_millis), // From one week ago
@@ -574,16 +605,8 @@ async fn device_stats(
 
 
     let ret = DeviceStatsResponse{
-        all: DeviceStats {
-            distance: total_length,
-            routes: route_count,
-            minutes: (total_millis/(1000*60)) as i32
-        },
-        week: DeviceStats {
-            distance: week_length,
-            routes: week_count,
-            minutes: (week_millis/(1000*60)) as i32
-        },
+        all: DeviceStats::new(total_length, route_count, total_millis, total_engaged),
+        week: DeviceStats::new(week_length, week_count, week_millis, week_engaged),
     };
 
     format::json(ret)
@@ -597,12 +620,76 @@ async fn device_users(
     format::json(DeviceUsersResponse {..Default::default()})
 }
 
+const DEFAULT_BOOTLOG_LIMIT: u64 = 50;
+
+#[derive(Deserialize, Debug)]
+struct BootlogQuery {
+    query: Option<String>, // matched against the versions, launch log, manager and panda lines and errors
+    kernel_version: Option<String>,
+    agnos_version: Option<String>,
+    has_errors: Option<bool>,
+    start: Option<i64>,    // milliseconds since epoch
+    end: Option<i64>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+}
+
+async fn device_bootlogs(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Path(dongle_id): Path<String>,
+    Query(params): Query<BootlogQuery>,
+) -> Result<Response> {
+    if let Some(user_model) = auth.user_model {
+        if !user_model.superuser {
+            DM::ensure_user_device(&ctx.db, user_model.id, &dongle_id).await?; // just error if not found
+        }
+    } else {
+        return loco_rs::controller::bad_request("devices can't do this")
+    }
+    let search = BootlogSearch {
+        text: params.query,
+        kernel_version: params.kernel_version,
+        agnos_version: params.agnos_version,
+        has_errors: params.has_errors,
+        start: params.start,
+        end: params.end,
+    };
+    let (bootlogs, total) = BM::search_device_bootlogs(
+        &ctx.db,
+        &dongle_id,
+        &search,
+        params.limit.unwrap_or(DEFAULT_BOOTLOG_LIMIT),
+        params.offset.unwrap_or(0),
+    ).await?;
+    format::json(BootlogsResponse {
+        total,
+        bootlogs: bootlogs.into_iter().map(|bootlog| BootlogResponse {
+            id: bootlog.id,
+            boot_time: bootlog.boot_time,
+            date_time: bootlog.date_time,
+            bootlog_url: bootlog.bootlog_url,
+            unlog_url: bootlog.unlog_url,
+            kernel_version: bootlog.kernel_version,
+            agnos_version: bootlog.agnos_version,
+            openpilot_version: bootlog.openpilot_version,
+            git_commit: bootlog.git_commit,
+            launch_log: bootlog.launch_log,
+            manager_init: bootlog.manager_init,
+            panda_versions: bootlog.panda_versions,
+            errors: bootlog.errors,
+            error_count: bootlog.error_count,
+        }).collect(),
+    })
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct DeviceSegmentQuery {
     end: Option<i64>,
     start: Option<i64>,
     limit: Option<u64>,
     route_str: Option<String>,
+    flagged: Option<bool>, // only routes where the driver did or didn't press the flag button
 }
 
 async fn route_segment(
@@ -626,7 +713,7 @@ async fn route_segment(
         }
         vec!(route_model)
     } else {
-        RM::find_time_filtered_device_routes(&ctx.db, &dongle_id, params.start, params.end, params.limit).await?
+        RM::find_time_filtered_device_routes(&ctx.db, &dongle_id, params.start, params.end, params.limit, params.flagged).await?
     };
     
     route_models.retain(|route| route.maxqlog != -1); // exclude ones wher the qlog is missing
@@ -666,6 +753,74 @@ async fn route_info(
 }
 
 
+#[derive(Deserialize)]
+struct TrackQuery {
+    simplify: Option<f64>, // Douglas-Peucker tolerance in meters, the full track when left out
+}
+
+/// The whole route's GPS track in one file, stitched from the coords.json of every segment.
+async fn get_track(
+    auth: MyJWT,
+    ctx: AppContext,
+    fullname: String,
+    params: TrackQuery,
+    format: TrackFormat,
+) -> Result<Response> {
+    let route_model = RM::find_route(&ctx.db, &fullname).await?;
+    if !route_model.is_public {
+        if let Some(user_model) = &auth.user_model {
+            if !user_model.superuser {
+                DM::find_user_device(&ctx.db, user_model.id, &route_model.device_dongle_id).await?; // just error if not found
+            }
+        } else if auth.claims.identity != route_model.device_dongle_id {
+            return loco_rs::controller::unauthorized("route does not belong to device")
+        }
+    }
+
+    let segment_models = SM::find_segments_by_route(&ctx.db, &fullname).await?;
+    let canonical_route_name = fullname.replace("|", "_");
+    let mut points: Vec<TrackPoint> = Vec::new();
+    for segment in segment_models.iter() {
+        let key = super::connectdata::prefer_rlog_derived(&canonical_route_name, &segment.number.to_string(), "coords.json").await;
+        let data = match common::storage::blob_store().get_bytes(&key).await {
+            Ok(data) => data,
+            Err(common::storage::StorageError::NotFound(_)) => continue, // not parsed yet or no gps
+            Err(e) => return Err(Error::Message(e.to_string())),
+        };
+        match serde_json::from_slice::<Vec<track::Coord>>(&data) {
+            Ok(coords) => points.extend(
+                coords.iter().map(|coord| TrackPoint::from_coord(route_model.start_time_utc_millis, coord))
+            ),
+            Err(e) => tracing::warn!("Skipping unreadable {key}: {e}"),
+        }
+    }
+    if let Some(epsilon) = params.simplify {
+        points = track::simplify(&points, epsilon);
+    }
+
+    let filename = format!("{}.{}", canonical_route_name, format.extension());
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, format.content_type().to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{filename}\"")),
+        ],
+        format.render(&fullname, &points),
+    ).into_response())
+}
+
+// Wrappers for each track format
+async fn get_track_gpx(auth: MyJWT, State(ctx): State<AppContext>, Path(fullname): Path<String>, Query(params): Query<TrackQuery>) -> Result<Response> {
+    get_track(auth, ctx, fullname, params, TrackFormat::Gpx).await
+}
+
+async fn get_track_geojson(auth: MyJWT, State(ctx): State<AppContext>, Path(fullname): Path<String>, Query(params): Query<TrackQuery>) -> Result<Response> {
+    get_track(auth, ctx, fullname, params, TrackFormat::GeoJson).await
+}
+
+async fn get_track_kml(auth: MyJWT, State(ctx): State<AppContext>, Path(fullname): Path<String>, Query(params): Query<TrackQuery>) -> Result<Response> {
+    get_track(auth, ctx, fullname, params, TrackFormat::Kml).await
+}
+
 async fn patch_route(
     auth: MyJWT,
     State(ctx): State<AppContext>,
@@ -1221,6 +1376,12 @@ pub fn routes() -> Routes {
         .add("/route/:fullname", patch(patch_route))
         .add("/route/:fullname/files", get(get_route_files))
         .add("/route/:fullname/qcamera.m3u8", get(get_qcam_stream))
+        .add("/route/:fullname/fcamera.m3u8", get(get_fcam_stream))
+        .add("/route/:fullname/ecamera.m3u8", get(get_ecam_stream))
+        .add("/route/:fullname/dcamera.m3u8", get(get_dcam_stream))
+        .add("/route/:fullname/track.gpx", get(get_track_gpx))
+        .add("/route/:fullname/track.geojson", get(get_track_geojson))
+        .add("/route/:fullname/track.kml", get(get_track_kml))
         .add("/route/:fullname/share_signature", get(get_share_signature))
         .add("/:dongleId/upload_urls/", post(upload_urls_handler))
         .add(".4/:dongleId/upload_url/", get(get_upload_url))
@@ -1231,6 +1392,7 @@ pub fn routes() -> Routes {
         .add("/devices/:dongle_id/firehose", post(set_firehose))
         .add(".1/devices/:dongle_id/stats", get(device_stats))
         .add("/devices/:dongle_id/users", get(device_users))
+        .add("/devices/:dongle_id/bootlogs", get(device_bootlogs))
         .add("/devices/:dongle_id", patch(update_device_alias))
         .add(".1/devices/:dongle_id", get(device_info))
         .add("/navigation/:dongle_id/set_destination", post(set_destination))
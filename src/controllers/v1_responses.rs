@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::common::quota::QuotaUsage;
+
 /// ## Device Info Response
 /// GET /v1.1/devices/:dongle_id/
 ///
@@ -26,6 +28,8 @@ pub struct DeviceInfoResponse {
     pub last_gps_bearing: f64,        /// Direction of last location in degrees from north
     pub openpilot_version: String,    /// Last known openpilot version on device
     pub sim_id: Option<String>,               // Last known sim_id of SIM in device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<QuotaUsage>,  // Bytes stored on the server and the quotas they count against
 }
 
 /// ## Device location
@@ -47,6 +51,20 @@ pub struct DeviceStats {
     pub distance: f32,
     pub minutes: i32,
     pub routes: u32,
+    pub engaged_distance: f32, // Miles driven with openpilot engaged
+    pub engaged_percent: f32,  // Share of distance that was engaged, 0-100
+}
+
+impl DeviceStats {
+    pub fn new(distance: f32, routes: u32, millis: i64, engaged_distance: f32) -> Self {
+        DeviceStats {
+            distance,
+            minutes: (millis / (1000 * 60)) as i32,
+            routes,
+            engaged_distance,
+            engaged_percent: if distance > 0.0 { (engaged_distance / distance * 100.0).min(100.0) } else { 0.0 },
+        }
+    }
 }
 
 /// ## Device driving statistics
@@ -74,6 +92,34 @@ pub struct DeviceUsersResponse {
     pub users: Vec<DeviceUser>
 }
 
+#[derive(Serialize, Debug, Default)]
+pub struct BootlogResponse {
+    pub id: i32,
+    pub boot_time: i64,             // Milliseconds since epoch, 0 if the device didn't know the time
+    pub date_time: String,          // Boot time as written in the journal
+    pub bootlog_url: String,        // Download of the raw bootlog
+    pub unlog_url: String,          // Bootlog as text
+    pub kernel_version: String,
+    pub agnos_version: String,
+    pub openpilot_version: String,
+    pub git_commit: String,
+    pub launch_log: String,
+    pub manager_init: String,       // Manager lines, one per line
+    pub panda_versions: String,     // Panda firmware lines, one per line
+    pub errors: String,             // Error lines, one per line
+    pub error_count: i32,
+}
+
+/// ## Device boot history
+/// GET /v1/devices/:dongle_id/bootlogs
+///
+/// Bootlogs of a device, newest first, with the total number that match the query
+#[derive(Serialize, Debug, Default)]
+pub struct BootlogsResponse {
+    pub total: u64,
+    pub bootlogs: Vec<BootlogResponse>,
+}
+
 #[derive(Serialize, Deserialize, Default)]
 pub struct RouteSegment {
     pub can: bool,
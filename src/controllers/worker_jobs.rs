@@ -0,0 +1,112 @@
+#![allow(clippy::unused_async)]
+use axum::extract::{Path, Query, State};
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    middleware::auth::MyJWT,
+    models::worker_jobs::{WJM, STATUS_QUEUED, STATUS_RUNNING},
+    workers::job_tracker::retry_job,
+};
+
+const DEFAULT_JOB_LIMIT: u64 = 100;
+
+#[derive(Deserialize)]
+pub struct JobQuery {
+    pub status: Option<String>,
+    pub worker: Option<String>,
+    pub dongle_id: Option<String>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct JobResponse {
+    id: i32,
+    worker: String,
+    file_key: String,
+    dongle_id: String,
+    status: String,
+    attempts: i32,
+    duration_millis: i64,
+    last_error: Option<String>,
+    create_time: i64,
+    update_time: i64,
+}
+
+impl From<WJM> for JobResponse {
+    fn from(job: WJM) -> Self {
+        JobResponse {
+            id: job.id,
+            worker: job.worker,
+            file_key: job.file_key,
+            dongle_id: job.dongle_id,
+            status: job.status,
+            attempts: job.attempts,
+            duration_millis: job.duration_millis,
+            last_error: job.last_error,
+            create_time: job.created_at.and_utc().timestamp(),
+            update_time: job.updated_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct JobsResponse {
+    total: u64,
+    jobs: Vec<JobResponse>,
+}
+
+fn ensure_superuser(auth: &MyJWT) -> Result<()> {
+    if !auth.user_model.as_ref().map_or(false, |user_model| user_model.superuser) {
+        return Err(Error::Unauthorized("Only superusers can see worker jobs".to_string()));
+    }
+    Ok(())
+}
+
+/// Jobs of the background workers, most recently updated first. `status=failed` is the
+/// dead letter list.
+pub async fn list_jobs(
+    auth: MyJWT,
+    State(ctx): State<AppContext>,
+    Query(query): Query<JobQuery>,
+) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    let (jobs, total) = WJM::find_jobs(
+        &ctx.db,
+        query.status.as_deref(),
+        query.worker.as_deref(),
+        query.dongle_id.as_deref(),
+        query.limit.unwrap_or(DEFAULT_JOB_LIMIT),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    format::json(JobsResponse {
+        total,
+        jobs: jobs.into_iter().map(JobResponse::from).collect(),
+    })
+}
+
+pub async fn get_job(auth: MyJWT, State(ctx): State<AppContext>, Path(id): Path<i32>) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    format::json(JobResponse::from(WJM::find_job(&ctx.db, id).await?))
+}
+
+/// Queue the job again with the arguments it last ran with.
+pub async fn retry(auth: MyJWT, State(ctx): State<AppContext>, Path(id): Path<i32>) -> Result<Response> {
+    ensure_superuser(&auth)?;
+    let job = WJM::find_job(&ctx.db, id).await?;
+    if job.status == STATUS_QUEUED || job.status == STATUS_RUNNING {
+        return loco_rs::controller::bad_request("the job is already queued or running");
+    }
+    retry_job(&ctx, &job).await.map_err(Error::Message)?;
+    format::json(JobResponse::from(WJM::find_job(&ctx.db, id).await?))
+}
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("v1/admin")
+        .add("/jobs", get(list_jobs))
+        .add("/jobs/:id", get(get_job))
+        .add("/jobs/:id/retry", post(retry))
+}
@@ -19,18 +19,35 @@ use tokio::sync::{RwLock, Mutex};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::time::{self, Duration};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 use uuid::Uuid;
 
 use crate::{
+    common::{
+        athena_bus::{self, BusBackend, BusMessage},
+        cloudlog_alerts::CloudlogAlerts,
+    },
     models::{
-        _entities,
+        cloudlogs::CLGM,
         devices::DM,
-        device_msg_queues::DMQM,
+        device_msg_queues::{DMQM, DEFAULT_MAX_ATTEMPTS, OFFLINE_RPC_PREFIX},
     },
 };
 
+/// How long a device gets to answer a queued command before it's sent again
+const OFFLINE_ACK_TIMEOUT_SECS: i64 = 60;
+/// Calls forwarded from other instances that the device hasn't answered by now have timed out there
+const REMOTE_REQUEST_TTL: Duration = Duration::from_secs(60);
+/// Cloudlog lines buffered per live tail subscriber before it starts missing some
+const CLOUDLOG_TAIL_CAPACITY: usize = 1024;
+/// How often new cloudlog lines are read for the live tails and alert rules
+const CLOUDLOG_FEED_INTERVAL: Duration = Duration::from_secs(1);
+/// Ids are handed out before an insert commits, so a line can show up after one with a higher
+/// id. Lines younger than this are left for the next read to not skip over those.
+const CLOUDLOG_FEED_LAG_SECS: i64 = 2;
+const CLOUDLOG_FEED_BATCH: u64 = 1000;
+
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -99,6 +116,10 @@ pub struct JsonRpcRequest {
     pub params: Option<serde_json::Value>,
     pub jsonrpc: String,
     pub id: Id,
+    /// Unix time to hold on to the call until if the device is offline. Without it an
+    /// offline device is an error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<i64>,
 }
 
 
@@ -109,6 +130,7 @@ impl Default for JsonRpcRequest {
             params: None,
             jsonrpc: "2.0".to_string(),
             id: Id::String(generate_request_id()),
+            expiry: None,
         }
     }
 }
@@ -130,9 +152,21 @@ enum JsonRpcMessage {
     Response(JsonRpcResponse),
 }
 
+/// The socket's write half
+pub type DeviceSender = Arc<Mutex<SplitSink<WebSocket, Message>>>;
+
 pub struct DeviceConnection {
     pub connection_id: String,
-    pub sender: SplitSink<WebSocket, Message>,
+    pub sender: DeviceSender,
+}
+
+/// A stored cloudlog line, as the device sent it through `forwardLogs`
+#[derive(Debug, Clone)]
+pub struct CloudlogLine {
+    pub dongle_id: String,
+    pub branch: String,
+    pub module: String,
+    pub log: serde_json::Value,
 }
 
 pub struct ConnectionManager {
@@ -140,14 +174,27 @@ pub struct ConnectionManager {
     pub clients: Mutex<HashMap<String, tokio::sync::mpsc::Sender<JsonRpcResponse>>>,
     // branch -> module -> Vec<serde_json::Value>
     pub cloudlog_cache: RwLock<HashMap<String, HashMap<String, HashMap<String, Vec<serde_json::Value>>>>>,
+    /// This instance on the athena bus
+    pub node_id: String,
+    pub bus: BusBackend,
+    /// JSON-RPC id -> instance the call came from, and when
+    pub remote_requests: Mutex<HashMap<String, (String, Instant)>>,
+    /// Every stored cloudlog line, from the devices on any instance, for live tails
+    pub cloudlog_tail: tokio::sync::broadcast::Sender<Arc<CloudlogLine>>,
+    pub cloudlog_alerts: CloudlogAlerts,
 }
 
 impl ConnectionManager {
-    pub fn new() -> Arc<Self> {
+    pub fn new(bus: BusBackend) -> Arc<Self> {
         Arc::new(Self {
             devices: Mutex::new(HashMap::new()),
             clients: Mutex::new(HashMap::new()),
             cloudlog_cache: RwLock::new(HashMap::new()),
+            node_id: athena_bus::node_id(),
+            bus,
+            remote_requests: Mutex::new(HashMap::new()),
+            cloudlog_tail: tokio::sync::broadcast::channel(CLOUDLOG_TAIL_CAPACITY).0,
+            cloudlog_alerts: CloudlogAlerts::default(),
         })
     }
 }
@@ -168,6 +215,8 @@ async fn handle_jsonrpc_request(
         return Err(Error::Unauthorized("Devices can't do this".to_string()));
     }
     
+    let expiry = payload.expiry.take();
+    let original_id = payload.id.clone();
     let now_id = generate_request_id();
     // Convert the current id into a String.
     let mut id_string: String = payload.id.into();
@@ -176,13 +225,29 @@ async fn handle_jsonrpc_request(
     // Update payload.id with the new string.
     payload.id = Id::String(id_string.clone()); 
     
-    let message = Message::Text(serde_json::to_string(&payload)?);
-    forward_command_to_device(&endpoint_dongle_id, &manager, &message).await?;
-    
+    let request = serde_json::to_string(&payload)?;
+    // Registered before sending, the answer can come back before the send returns
     let (response_tx, mut response_rx) = tokio::sync::mpsc::channel::<JsonRpcResponse>(1);
     {
         let mut clients = manager.clients.lock().await;
-        clients.insert(id_string, response_tx);
+        clients.insert(id_string.clone(), response_tx);
+    }
+    let sent = match forward_command_to_device(&endpoint_dongle_id, &manager, &Message::Text(request.clone())).await {
+        Err(Error::DeviceNotFound) => forward_to_device_node(&ctx.db, &manager, &endpoint_dongle_id, request).await,
+        sent => sent,
+    };
+    if let Err(e) = sent {
+        manager.clients.lock().await.remove(&id_string);
+        match e {
+            Error::DeviceNotFound if expiry.map_or(false, |expiry| expiry > chrono::Utc::now().timestamp()) => {
+                // Offline, it goes out when the device is back unless it expires first
+                payload.id = original_id;
+                let queued = DMQM::queue_msg(&ctx.db, &endpoint_dongle_id, payload, expiry.unwrap_or_default(), DEFAULT_MAX_ATTEMPTS).await?;
+                tracing::info!("Queued {} for offline device {endpoint_dongle_id}", queued.method);
+                return Ok(format::json(serde_json::json!({"queued": true, "id": queued.id})));
+            }
+            e => return Err(e),
+        }
     }
     
     loop {
@@ -206,7 +271,7 @@ async fn handle_jsonrpc_request(
             Err(_e) => {
                 // Remove client on timeout.
                 let mut clients = manager.clients.lock().await;
-                clients.remove(&now_id);
+                clients.remove(&id_string);
                 return Err(Error::Timeout);
             },
         }
@@ -219,17 +284,98 @@ async fn forward_command_to_device(
     manager: &Arc<ConnectionManager>,
     message: &Message,
 ) -> Result<()> {
-    let mut device_connections = manager.devices.lock().await;
-    let device_conn = device_connections.get_mut(endpoint_dongle_id).ok_or(Error::DeviceNotFound)?;
-
-    device_conn
-        .sender
+    let sender = device_sender(manager, endpoint_dongle_id).await.ok_or(Error::DeviceNotFound)?;
+    sender
+        .lock()
+        .await
         .send(message.clone())
         .await
         .map_err(|e| Error::SendFailed(e.to_string()))?;
     Ok(())
 }
 
+/// The write half of a device's socket on this instance. The devices lock is only held to look
+/// it up, so a slow device or query doesn't hold up the others.
+async fn device_sender(manager: &ConnectionManager, dongle_id: &str) -> Option<DeviceSender> {
+    manager.devices.lock().await.get(dongle_id).map(|conn| conn.sender.clone())
+}
+
+/// The device isn't connected to this instance, hand the call to the one it is connected to.
+async fn forward_to_device_node(
+    db: &DatabaseConnection,
+    manager: &Arc<ConnectionManager>,
+    endpoint_dongle_id: &str,
+    request: String,
+) -> Result<()> {
+    if manager.bus != BusBackend::Postgres {
+        return Err(Error::DeviceNotFound);
+    }
+    match athena_bus::find_device_node(db, endpoint_dongle_id).await? {
+        Some(node_id) if node_id != manager.node_id => {
+            let message = BusMessage::Request {
+                dongle_id: endpoint_dongle_id.to_string(),
+                reply_to: manager.node_id.clone(),
+                request,
+            };
+            athena_bus::send(db, &node_id, &message).await?;
+            Ok(())
+        }
+        _ => Err(Error::DeviceNotFound),
+    }
+}
+
+/// Handles what the other instances send this one over the athena bus: calls for the devices
+/// connected here and the answers to calls this instance forwarded.
+pub async fn run_bus(db: DatabaseConnection, database_url: String, manager: Arc<ConnectionManager>) {
+    let mut messages = match athena_bus::subscribe(&db, &database_url, &manager.node_id).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            tracing::error!("Failed to join the athena bus, devices on other instances are unreachable: {e}");
+            return;
+        }
+    };
+    tracing::info!("Joined the athena bus as {}", manager.node_id);
+    while let Some(message) = messages.recv().await {
+        match message {
+            BusMessage::Request { dongle_id, reply_to, request } => {
+                let Ok(rpc) = serde_json::from_str::<JsonRpcRequest>(&request) else {
+                    tracing::warn!("Dropping a forwarded call for {dongle_id} that isn't JSON-RPC");
+                    continue;
+                };
+                let id: String = rpc.id.clone().into();
+                manager.remote_requests.lock().await.insert(id.clone(), (reply_to.clone(), Instant::now()));
+                if let Err(e) = forward_command_to_device(&dongle_id, &manager, &Message::Text(request)).await {
+                    manager.remote_requests.lock().await.remove(&id);
+                    let response = JsonRpcResponse {
+                        result: None,
+                        error: Some(serde_json::json!({"code": -32000, "message": e.to_string()})),
+                        jsonrpc: "2.0".to_string(),
+                        id: rpc.id,
+                    };
+                    let message = BusMessage::Response { response: serde_json::to_string(&response).unwrap_or_default() };
+                    if let Err(e) = athena_bus::send(&db, &reply_to, &message).await {
+                        tracing::error!("Failed to answer {reply_to} on the athena bus: {e}");
+                    }
+                }
+            }
+            BusMessage::Response { response } => {
+                let response = match serde_json::from_str::<JsonRpcResponse>(&response) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        tracing::warn!("Dropping a forwarded answer that isn't JSON-RPC: {e}");
+                        continue;
+                    }
+                };
+                let id: String = response.id.clone().into();
+                let client_sender = manager.clients.lock().await.remove(&id);
+                if let Some(client_sender) = client_sender {
+                    let _ = client_sender.send(response).await;
+                }
+            }
+        }
+    }
+}
+
 async fn exit_handler(
     ctx: &AppContext,
     endpoint_dongle_id: String,
@@ -251,6 +397,11 @@ async fn exit_handler(
         }
 
     } // unlock the mutex
+    if is_device && manager.bus == BusBackend::Postgres {
+        if let Err(e) = athena_bus::unregister_device(&ctx.db, &endpoint_dongle_id, &connection_id).await {
+            tracing::error!("Failed to unregister {endpoint_dongle_id} from the athena bus: {e}");
+        }
+    }
     if is_device {
         if let Ok(device) = DM::find_device(&ctx.db, &endpoint_dongle_id).await {
             let mut device_active_model = device.into_active_model();
@@ -274,12 +425,22 @@ async fn handle_socket(
     let connection_id = Uuid::new_v4().to_string();
 
     if is_device {
-        let mut devices: tokio::sync::MutexGuard<HashMap<String, DeviceConnection>> = manager.devices.lock().await;
-        tracing::info!("Adding device to manager: {}", endpoint_dongle_id);
-        devices.insert(endpoint_dongle_id.clone(), DeviceConnection {
-            connection_id: connection_id.clone(),
-            sender,
-        });
+        let sender: DeviceSender = Arc::new(Mutex::new(sender));
+        {
+            let mut devices: tokio::sync::MutexGuard<HashMap<String, DeviceConnection>> = manager.devices.lock().await;
+            tracing::info!("Adding device to manager: {}", endpoint_dongle_id);
+            devices.insert(endpoint_dongle_id.clone(), DeviceConnection {
+                connection_id: connection_id.clone(),
+                sender: sender.clone(),
+            });
+        }
+        // Whatever was queued while it was offline goes out first
+        deliver_offline_msgs(&ctx.db, &endpoint_dongle_id, &sender).await;
+        if manager.bus == BusBackend::Postgres {
+            if let Err(e) = athena_bus::register_device(&ctx.db, &manager.node_id, &endpoint_dongle_id, &connection_id).await {
+                tracing::error!("Failed to register {endpoint_dongle_id} on the athena bus: {e}");
+            }
+        }
     }
     
     while let Some(message_result) = receiver.next().await {
@@ -316,10 +477,32 @@ async fn handle_socket(
                 if let Ok(message) = serde_json::from_str::<JsonRpcMessage>(&text) {
                     match message {
                         JsonRpcMessage::Response(resp) => {
-                            let mut clients = manager.clients.lock().await;
                             let id_str: String = resp.id.clone().into();
-                            if let Some(client_sender) = clients.remove(&id_str) {
+                            let client_sender = manager.clients.lock().await.remove(&id_str);
+                            let remote_request = match client_sender {
+                                Some(_) => None,
+                                None => manager.remote_requests.lock().await.remove(&id_str),
+                            };
+                            if let Some(client_sender) = client_sender {
                                 let _ = client_sender.send(resp).await;
+                            } else if let Some((reply_to, _)) = remote_request {
+                                // the call came from another instance, the answer goes back there
+                                let message = BusMessage::Response { response: text.clone() };
+                                if let Err(e) = athena_bus::send(&ctx.db, &reply_to, &message).await {
+                                    tracing::error!("Failed to forward the answer to {id_str} to {reply_to}: {e}");
+                                }
+                            } else if is_device && id_str.starts_with(OFFLINE_RPC_PREFIX) {
+                                match DMQM::ack(&ctx.db, &endpoint_dongle_id, &id_str, resp.result, resp.error).await {
+                                    Ok(true) => {
+                                        tracing::debug!("{endpoint_dongle_id} answered queued command {id_str}");
+                                        // the next one was waiting on this answer
+                                        if let Some(sender) = device_sender(&manager, &endpoint_dongle_id).await {
+                                            deliver_offline_msgs(&ctx.db, &endpoint_dongle_id, &sender).await;
+                                        }
+                                    }
+                                    Ok(false) => tracing::debug!("{endpoint_dongle_id} answered {id_str}, which isn't waiting anymore"),
+                                    Err(e) => tracing::error!("Failed to store the answer to {id_str}: {e}"),
+                                }
                             }
                         },
                         JsonRpcMessage::Request(req) => {
@@ -343,6 +526,10 @@ async fn handle_socket(
                                                 }
                                             }
                             
+                                            if let Err(e) = CLGM::insert_lines(&ctx.db, &endpoint_dongle_id, &parsed_logs).await {
+                                                tracing::error!("Failed to store cloudlogs for {endpoint_dongle_id}: {e}");
+                                            }
+
                                             // Now store logs in a nested hashmap:
                                             {
                                                 let mut cloudlog_cache = manager.cloudlog_cache.write().await;
@@ -470,23 +657,128 @@ async fn handle_device_ws(
     }))
 }
 
+/// Feeds the live tails and the alert rules from the cloudlogs table rather than from the
+/// websockets, so every instance sees the lines of the devices connected to any of them.
+pub async fn run_cloudlog_feed(db: DatabaseConnection, manager: Arc<ConnectionManager>) {
+    let mut interval = time::interval(CLOUDLOG_FEED_INTERVAL);
+    // Only what comes in from now on
+    let mut last_id = loop {
+        interval.tick().await;
+        match CLGM::last_id(&db).await {
+            Ok(last_id) => break last_id,
+            Err(e) => tracing::error!("Failed to find the newest cloudlog: {e}"),
+        }
+    };
+    loop {
+        interval.tick().await;
+        let before = chrono::Utc::now().naive_utc() - chrono::Duration::seconds(CLOUDLOG_FEED_LAG_SECS);
+        let lines = match CLGM::find_after(&db, last_id, before, CLOUDLOG_FEED_BATCH).await {
+            Ok(lines) => lines,
+            Err(e) => {
+                tracing::error!("Failed to read new cloudlogs: {e}");
+                continue;
+            }
+        };
+        let Some(last) = lines.last() else {
+            continue;
+        };
+        last_id = last.id;
+
+        for device_lines in lines.chunk_by(|a, b| a.dongle_id == b.dongle_id) {
+            let logs: Vec<serde_json::Value> = device_lines.iter().map(|line| line.log.clone()).collect();
+            manager.cloudlog_alerts.check(&db, &device_lines[0].dongle_id, &logs).await;
+        }
+        if manager.cloudlog_tail.receiver_count() > 0 {
+            for line in lines {
+                // Nobody listening is fine, the line is only for live tails
+                let _ = manager.cloudlog_tail.send(Arc::new(CloudlogLine {
+                    dongle_id: line.dongle_id,
+                    branch: line.branch,
+                    module: line.module,
+                    log: line.log,
+                }));
+            }
+        }
+    }
+}
+
 pub async fn send_ping_to_all_devices(manager: Arc<ConnectionManager>, db: &DatabaseConnection) {
-    let mut devices = manager.devices.lock().await;
-    for (dongle_id, device_connection) in devices.iter_mut() {
+    if manager.bus == BusBackend::Postgres {
+        if let Err(e) = athena_bus::heartbeat(db, &manager.node_id).await {
+            tracing::error!("Failed to refresh athena bus connections: {e}");
+        }
+    }
+    manager.remote_requests.lock().await.retain(|_, (_, received)| received.elapsed() < REMOTE_REQUEST_TTL);
+    let devices: Vec<(String, DeviceSender)> = manager.devices.lock().await
+        .iter()
+        .map(|(dongle_id, conn)| (dongle_id.clone(), conn.sender.clone()))
+        .collect();
+    for (dongle_id, sender) in &devices {
         tracing::trace!("Sending ping to {}", &dongle_id);
-        if let Err(e) = device_connection.sender.send(Message::Ping(Vec::new())).await {
+        if let Err(e) = sender.lock().await.send(Message::Ping(Vec::new())).await {
             tracing::trace!("Failed to send ping to device {}: {}", dongle_id, e);
         }
     }
-    for (dongle_id, device_connection) in devices.iter_mut() {
-        if let Ok(Some(latest_msg)) = DMQM::find_latest_msg(db, &dongle_id).await {
-            if let Err(e) = device_connection.sender.send(Message::Text(latest_msg.json_rpc_request.to_string())).await {
-                tracing::error!("Failed to send jsonrpc msg to device {}: {}", dongle_id, e);
+    if let Err(e) = DMQM::expire_msgs(db).await {
+        tracing::error!("Failed to expire queued msgs: {e}");
+    }
+    let cutoff = chrono::Utc::now().timestamp() - OFFLINE_ACK_TIMEOUT_SECS;
+    if let Err(e) = DMQM::requeue_unacked(db, cutoff).await {
+        tracing::error!("Failed to requeue unanswered msgs: {e}");
+    }
+    for (dongle_id, sender) in &devices {
+        deliver_offline_msgs(db, dongle_id, sender).await;
+    }
+}
+
+/// Send a device its next queued command, oldest first. Only one is out at a time: the next
+/// goes when the device answers, see `DMQM::ack`, and an unanswered one is sent again before
+/// anything queued after it.
+async fn deliver_offline_msgs(db: &DatabaseConnection, dongle_id: &str, sender: &DeviceSender) {
+    // Held throughout, so the ping loop and an answer coming in can't both send one
+    let mut sender = sender.lock().await;
+    match DMQM::has_unacked(db, dongle_id).await {
+        Ok(false) => (),
+        Ok(true) => return,
+        Err(e) => {
+            tracing::error!("Failed to check the queued msgs of {dongle_id}: {e}");
+            return;
+        }
+    }
+    loop {
+        let msg = match DMQM::find_due_msgs(db, dongle_id, 1).await {
+            Ok(msgs) => match msgs.into_iter().next() {
+                Some(msg) => msg,
+                None => return,
+            },
+            Err(e) => {
+                tracing::error!("Failed to get queued msgs of {dongle_id}: {e}");
+                return;
             }
-            if _entities::device_msg_queues::Entity::delete_by_id(latest_msg.id).exec(db).await.is_err() {
-                tracing::error!("Failed to delete msg in queue {}", dongle_id);
+        };
+        let rpc_id = msg.next_rpc_id();
+        let mut request = msg.json_rpc_request.clone();
+        let Some(fields) = request.as_object_mut() else {
+            if let Err(e) = DMQM::fail(db, msg.id, "not a JSON-RPC request").await {
+                tracing::error!("Failed to drop msg {} of {dongle_id}: {e}", msg.id);
+                return;
+            }
+            continue;
+        };
+        fields.insert("id".to_string(), serde_json::Value::String(rpc_id.clone()));
+        // Marked first, the answer can come back before the send returns
+        if let Err(e) = DMQM::mark_sent(db, msg.id, &rpc_id).await {
+            tracing::error!("Failed to mark msg {} of {dongle_id} as sent: {e}", msg.id);
+            return;
+        }
+        if let Err(e) = sender.send(Message::Text(request.to_string())).await {
+            tracing::error!("Failed to send jsonrpc msg to device {}: {}", dongle_id, e);
+            if let Err(e) = DMQM::requeue(db, msg.id, &format!("send failed: {e}")).await {
+                tracing::error!("Failed to requeue msg {} of {dongle_id}: {e}", msg.id);
             }
         }
+        // the rest wait for its answer
+        return;
     }
 }
 
@@ -500,6 +792,7 @@ pub async fn send_reset( // called from crate::middleware::auth
             params: Some(serde_json::json!({})),
             jsonrpc: "2.0".to_string(),
             id: Id::Int(1),
+            expiry: None,
         };
         let msg = serde_json::to_string(&reset_dongle_rpc).unwrap();
         match sender.send(Message::Text(msg)).await {
@@ -1,4 +1,4 @@
-//! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.15
+//! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.3
 
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -8,11 +8,26 @@ use serde::{Deserialize, Serialize};
 pub struct Model {
     pub created_at: DateTime,
     pub updated_at: DateTime,
-    #[sea_orm(primary_key, auto_increment = false)] //TODO fix this table
+    #[sea_orm(primary_key)]
+    pub id: i32,
     pub dongle_id: String,
     pub bootlog_url: String,
     pub unlog_url: String,
     pub date_time: String,
+    pub boot_time: i64,
+    pub kernel_version: String,
+    pub agnos_version: String,
+    pub openpilot_version: String,
+    pub git_commit: String,
+    #[sea_orm(column_type = "Text")]
+    pub launch_log: String,
+    #[sea_orm(column_type = "Text")]
+    pub manager_init: String,
+    #[sea_orm(column_type = "Text")]
+    pub panda_versions: String,
+    #[sea_orm(column_type = "Text")]
+    pub errors: String,
+    pub error_count: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
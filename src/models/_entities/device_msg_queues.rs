@@ -12,6 +12,16 @@ pub struct Model {
     pub id: i32,
     pub dongle_id: String,
     pub json_rpc_request: serde_json::Value,
+    pub method: String,
+    pub status: String,
+    pub rpc_id: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub expires_at: i64,
+    pub sent_at: i64,
+    pub result: Option<serde_json::Value>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub error: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -27,6 +27,11 @@ pub struct Model {
     pub server_storage: i64,
     pub locations: Option<serde_json::Value>,
     pub firehose: bool,
+    pub storage_quota: Option<i64>,
+    pub log_quota: Option<i64>,
+    pub camera_quota: Option<i64>,
+    pub log_storage: i64,
+    pub camera_storage: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -35,10 +40,16 @@ pub enum Relation {
     AuthorizedUsers,
     #[sea_orm(has_many = "super::bootlogs::Entity")]
     Bootlogs,
+    #[sea_orm(has_many = "super::crashes::Entity")]
+    Crashes,
     #[sea_orm(has_many = "super::device_msg_queues::Entity")]
     DeviceMsgQueues,
     #[sea_orm(has_many = "super::routes::Entity")]
     Routes,
+    #[sea_orm(has_many = "super::storage_objects::Entity")]
+    StorageObjects,
+    #[sea_orm(has_many = "super::upload_sessions::Entity")]
+    UploadSessions,
     #[sea_orm(
         belongs_to = "super::users::Entity",
         from = "Column::OwnerId",
@@ -61,6 +72,12 @@ impl Related<super::bootlogs::Entity> for Entity {
     }
 }
 
+impl Related<super::crashes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Crashes.def()
+    }
+}
+
 impl Related<super::device_msg_queues::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::DeviceMsgQueues.def()
@@ -73,6 +90,18 @@ impl Related<super::routes::Entity> for Entity {
     }
 }
 
+impl Related<super::storage_objects::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::StorageObjects.def()
+    }
+}
+
+impl Related<super::upload_sessions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UploadSessions.def()
+    }
+}
+
 impl Related<super::users::Entity> for Entity {
     fn to() -> RelationDef {
         super::authorized_users::Relation::Users.def()
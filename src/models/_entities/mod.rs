@@ -5,8 +5,17 @@ pub mod prelude;
 pub mod anonlogs;
 pub mod authorized_users;
 pub mod bootlogs;
+pub mod clips;
+pub mod cloudlog_alert_rules;
+pub mod cloudlogs;
+pub mod crash_groups;
+pub mod crashes;
 pub mod device_msg_queues;
 pub mod devices;
+pub mod reprocess_jobs;
 pub mod routes;
 pub mod segments;
+pub mod storage_objects;
+pub mod upload_sessions;
 pub mod users;
+pub mod worker_jobs;
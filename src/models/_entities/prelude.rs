@@ -3,8 +3,17 @@
 pub use super::anonlogs::Entity as Anonlogs;
 pub use super::authorized_users::Entity as AuthorizedUsers;
 pub use super::bootlogs::Entity as Bootlogs;
+pub use super::clips::Entity as Clips;
+pub use super::cloudlog_alert_rules::Entity as CloudlogAlertRules;
+pub use super::cloudlogs::Entity as Cloudlogs;
+pub use super::crash_groups::Entity as CrashGroups;
+pub use super::crashes::Entity as Crashes;
 pub use super::device_msg_queues::Entity as DeviceMsgQueues;
 pub use super::devices::Entity as Devices;
+pub use super::reprocess_jobs::Entity as ReprocessJobs;
 pub use super::routes::Entity as Routes;
 pub use super::segments::Entity as Segments;
+pub use super::storage_objects::Entity as StorageObjects;
+pub use super::upload_sessions::Entity as UploadSessions;
 pub use super::users::Entity as Users;
+pub use super::worker_jobs::Entity as WorkerJobs;
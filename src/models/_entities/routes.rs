@@ -63,6 +63,14 @@ pub struct Model {
     pub share_sig: String,
     pub user_id: String,
     pub vin: String,
+    pub engaged_millis: i64,
+    #[sea_orm(column_type = "Float")]
+    pub engaged_miles: f32,
+    pub disengagements: i32,
+    pub overrides: i32,
+    pub has_user_flag: bool,
+    pub start_place: Option<String>,
+    pub end_place: Option<String>,
     pub created_at: DateTime,
     pub updated_at: DateTime,
 }
@@ -116,6 +124,13 @@ impl Default for Model {
             start_time_utc_millis: 0,
             user_id: String::default(),
             vin: String::default(),
+            engaged_millis: 0,
+            engaged_miles: 0.0,
+            disengagements: 0,
+            overrides: 0,
+            has_user_flag: false,
+            start_place: None,
+            end_place: None,
             created_at: DateTime::from_timestamp(0, 0),
             updated_at: DateTime::from_timestamp(0, 0),
         }
@@ -124,6 +139,8 @@ impl Default for Model {
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::clips::Entity")]
+    Clips,
     #[sea_orm(
         belongs_to = "super::devices::Entity",
         from = "Column::DeviceDongleId",
@@ -136,6 +153,12 @@ pub enum Relation {
     Segments,
 }
 
+impl Related<super::clips::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Clips.def()
+    }
+}
+
 impl Related<super::devices::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Devices.def()
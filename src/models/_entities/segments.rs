@@ -41,6 +41,19 @@ pub struct Model {
     pub end_time_utc_millis: i64,
     pub passive: Option<bool>,
     pub git_branch: Option<String>,
+    pub engaged_millis: i64,
+    #[sea_orm(column_type = "Float")]
+    pub engaged_miles: f32,
+    pub disengagements: i32,
+    pub overrides: i32,
+    pub has_user_flag: bool,
+    #[sea_orm(column_type = "Float")]
+    pub fcam_duration: f32,
+    #[sea_orm(column_type = "Float")]
+    pub ecam_duration: f32,
+    #[sea_orm(column_type = "Float")]
+    pub dcam_duration: f32,
+    pub parser_version: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -16,6 +16,7 @@ pub struct Model {
     pub name: String,
     pub points: i64,
     pub superuser: bool,
+    pub storage_quota: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
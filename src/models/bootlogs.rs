@@ -1,8 +1,40 @@
 use chrono::prelude::Utc;
 use loco_rs::model::ModelResult;
-use sea_orm::{entity::prelude::*, ActiveValue, TransactionTrait};
+use sea_orm::{entity::prelude::*, ActiveValue, Condition, PaginatorTrait, QueryOrder, QuerySelect, TransactionTrait};
 pub use super::_entities::bootlogs::{self, ActiveModel, Model as BM, Entity, Column};
 
+/// What the bootlog parser pulls out of a bootlog. Strings are empty when the log didn't have them.
+#[derive(Debug, Default, Clone)]
+pub struct BootInfo {
+    /// Milliseconds since the epoch, 0 if the device didn't know the time
+    pub boot_time: i64,
+    pub kernel_version: String,
+    pub agnos_version: String,
+    pub openpilot_version: String,
+    pub git_commit: String,
+    pub launch_log: String,
+    /// Manager lines from the launch log and journal, one per line
+    pub manager_init: String,
+    /// Panda firmware lines, one per line
+    pub panda_versions: String,
+    /// Error lines, one per line
+    pub errors: String,
+    pub error_count: i32,
+}
+
+/// Filters for a device's boot history. `text` matches anywhere in the versions, launch log,
+/// manager and panda lines and errors.
+#[derive(Debug, Default)]
+pub struct BootlogSearch {
+    pub text: Option<String>,
+    pub kernel_version: Option<String>,
+    pub agnos_version: Option<String>,
+    pub has_errors: Option<bool>,
+    /// Milliseconds since the epoch
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
 #[async_trait::async_trait]
 impl ActiveModelBehavior for ActiveModel {
     // extend activemodel below (keep comment for generators)
@@ -30,7 +62,8 @@ impl BM {
         dongle_id: &String, 
         bootlog_download_url: &String, 
         unlog_url: &String, 
-        date_time: &String
+        date_time: &String,
+        info: BootInfo,
     )  -> ModelResult<Self> {
         let txn = match db.begin().await {
             Ok(txn) => {
@@ -47,6 +80,16 @@ impl BM {
             bootlog_url: ActiveValue::Set(bootlog_download_url.clone()),
             unlog_url: ActiveValue::Set(unlog_url.clone()),
             date_time: ActiveValue::Set(date_time.clone()),
+            boot_time: ActiveValue::Set(info.boot_time),
+            kernel_version: ActiveValue::Set(info.kernel_version),
+            agnos_version: ActiveValue::Set(info.agnos_version),
+            openpilot_version: ActiveValue::Set(info.openpilot_version),
+            git_commit: ActiveValue::Set(info.git_commit),
+            launch_log: ActiveValue::Set(info.launch_log),
+            manager_init: ActiveValue::Set(info.manager_init),
+            panda_versions: ActiveValue::Set(info.panda_versions),
+            errors: ActiveValue::Set(info.errors),
+            error_count: ActiveValue::Set(info.error_count),
             ..Default::default()
         }
         .insert(&txn)
@@ -71,9 +114,61 @@ impl BM {
     ) -> ModelResult<Vec<BM>> {
         let routes = Entity::find()
             .filter(Column::DongleId.eq(dongle_id))
+            .order_by_desc(Column::BootTime)
+            .order_by_desc(Column::Id)
             .all(db)
             .await?;
         Ok(routes)
     }
 
+    /// A device's bootlogs matching `search`, newest first, with the total number that match.
+    pub async fn search_device_bootlogs(
+        db: &DatabaseConnection,
+        dongle_id: &str,
+        search: &BootlogSearch,
+        limit: u64,
+        offset: u64,
+    ) -> ModelResult<(Vec<BM>, u64)> {
+        let mut query = Entity::find().filter(Column::DongleId.eq(dongle_id));
+        if let Some(text) = search.text.as_deref().filter(|text| !text.is_empty()) {
+            query = query.filter(
+                Condition::any()
+                    .add(Column::KernelVersion.contains(text))
+                    .add(Column::AgnosVersion.contains(text))
+                    .add(Column::OpenpilotVersion.contains(text))
+                    .add(Column::GitCommit.contains(text))
+                    .add(Column::LaunchLog.contains(text))
+                    .add(Column::ManagerInit.contains(text))
+                    .add(Column::PandaVersions.contains(text))
+                    .add(Column::Errors.contains(text)),
+            );
+        }
+        if let Some(kernel_version) = &search.kernel_version {
+            query = query.filter(Column::KernelVersion.eq(kernel_version));
+        }
+        if let Some(agnos_version) = &search.agnos_version {
+            query = query.filter(Column::AgnosVersion.eq(agnos_version));
+        }
+        match search.has_errors {
+            Some(true) => query = query.filter(Column::ErrorCount.gt(0)),
+            Some(false) => query = query.filter(Column::ErrorCount.eq(0)),
+            None => {}
+        }
+        if let Some(start) = search.start {
+            query = query.filter(Column::BootTime.gte(start));
+        }
+        if let Some(end) = search.end {
+            query = query.filter(Column::BootTime.lte(end));
+        }
+        let total = query.clone().count(db).await?;
+        let bootlogs = query
+            .order_by_desc(Column::BootTime)
+            .order_by_desc(Column::Id)
+            .limit(limit)
+            .offset(offset)
+            .all(db)
+            .await?;
+        Ok((bootlogs, total))
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,109 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::Expr, ActiveValue, QueryOrder};
+pub use super::_entities::clips::{self, ActiveModel, Entity, Model as CLM, Column};
+
+pub const CLIP_PENDING: &str = "pending";
+pub const CLIP_RENDERING: &str = "rendering";
+pub const CLIP_DONE: &str = "done";
+pub const CLIP_FAILED: &str = "failed";
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+impl CLM {
+    /// A pending clip of `camera` from `start_millis` to `end_millis` into the route.
+    pub async fn add_clip(
+        db: &DatabaseConnection,
+        route_fullname: &str,
+        dongle_id: &str,
+        camera: &str,
+        start_millis: i64,
+        end_millis: i64,
+    ) -> ModelResult<CLM> {
+        let clip = ActiveModel {
+            route_fullname: ActiveValue::Set(route_fullname.to_string()),
+            dongle_id: ActiveValue::Set(dongle_id.to_string()),
+            camera: ActiveValue::Set(camera.to_string()),
+            start_millis: ActiveValue::Set(start_millis),
+            end_millis: ActiveValue::Set(end_millis),
+            status: ActiveValue::Set(CLIP_PENDING.to_string()),
+            progress: ActiveValue::Set(0.0),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+        Ok(clip)
+    }
+
+    pub async fn find_clip(db: &DatabaseConnection, id: i32) -> ModelResult<CLM> {
+        Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or(ModelError::EntityNotFound)
+    }
+
+    /// Newest first
+    pub async fn find_route_clips(db: &DatabaseConnection, route_fullname: &str) -> ModelResult<Vec<CLM>> {
+        let clips = Entity::find()
+            .filter(Column::RouteFullname.eq(route_fullname))
+            .order_by_desc(Column::CreatedAt)
+            .all(db)
+            .await?;
+        Ok(clips)
+    }
+
+    /// `progress` is 0 to 1. Also marks the clip as rendering.
+    pub async fn set_progress(db: &DatabaseConnection, id: i32, progress: f32) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(CLIP_RENDERING))
+            .col_expr(Column::Progress, Expr::value(progress.clamp(0.0, 1.0)))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_done(db: &DatabaseConnection, id: i32, storage_key: &str, size: i64) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(CLIP_DONE))
+            .col_expr(Column::Progress, Expr::value(1.0f32))
+            .col_expr(Column::StorageKey, Expr::value(storage_key))
+            .col_expr(Column::Size, Expr::value(size))
+            .col_expr(Column::Error, Expr::value(Option::<String>::None))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_failed(db: &DatabaseConnection, id: i32, error: &str) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(CLIP_FAILED))
+            .col_expr(Column::Error, Expr::value(error))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::Expr, ActiveValue, QueryOrder};
+use serde::Deserialize;
+pub use super::_entities::cloudlog_alert_rules::{self, ActiveModel, Entity, Model as CARM, Column};
+
+pub const DEFAULT_COOLDOWN_SECS: i32 = 60 * 60;
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+/// Settings of a rule. The match fields left out match any line.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRuleParams {
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub level: Option<String>,
+    /// Lines with at least this levelnum
+    pub min_levelnum: Option<i32>,
+    pub module: Option<String>,
+    pub func_name: Option<String>,
+    /// Searched for in the message of the line
+    pub msg_regex: Option<String>,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    /// Fires on more matching lines than this within `window_secs`
+    pub threshold: i32,
+    pub window_secs: i32,
+    /// Least time between two firings
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: i32,
+    pub webhook_url: String,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_cooldown_secs() -> i32 {
+    DEFAULT_COOLDOWN_SECS
+}
+
+impl AlertRuleParams {
+    fn apply(self, rule: &mut ActiveModel) {
+        rule.name = ActiveValue::Set(self.name);
+        rule.enabled = ActiveValue::Set(self.enabled);
+        rule.level = ActiveValue::Set(self.level);
+        rule.min_levelnum = ActiveValue::Set(self.min_levelnum);
+        rule.module = ActiveValue::Set(self.module);
+        rule.func_name = ActiveValue::Set(self.func_name);
+        rule.msg_regex = ActiveValue::Set(self.msg_regex);
+        rule.branch = ActiveValue::Set(self.branch);
+        rule.commit = ActiveValue::Set(self.commit);
+        rule.threshold = ActiveValue::Set(self.threshold);
+        rule.window_secs = ActiveValue::Set(self.window_secs);
+        rule.cooldown_secs = ActiveValue::Set(self.cooldown_secs);
+        rule.webhook_url = ActiveValue::Set(self.webhook_url);
+    }
+}
+
+impl CARM {
+    pub async fn add_rule(db: &DatabaseConnection, params: AlertRuleParams) -> ModelResult<CARM> {
+        let mut rule = ActiveModel::default();
+        params.apply(&mut rule);
+        Ok(rule.insert(db).await?)
+    }
+
+    pub async fn update_rule(db: &DatabaseConnection, id: i32, params: AlertRuleParams) -> ModelResult<CARM> {
+        let mut rule = CARM::find_rule(db, id).await?.into_active_model();
+        params.apply(&mut rule);
+        Ok(rule.update(db).await?)
+    }
+
+    pub async fn delete_rule(db: &DatabaseConnection, id: i32) -> ModelResult<()> {
+        Entity::delete_by_id(id).exec(db).await?;
+        Ok(())
+    }
+
+    pub async fn find_rule(db: &DatabaseConnection, id: i32) -> ModelResult<CARM> {
+        Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or(ModelError::EntityNotFound)
+    }
+
+    pub async fn find_rules(db: &DatabaseConnection) -> ModelResult<Vec<CARM>> {
+        Ok(Entity::find().order_by_asc(Column::Id).all(db).await?)
+    }
+
+    pub async fn find_enabled_rules(db: &DatabaseConnection) -> ModelResult<Vec<CARM>> {
+        Ok(Entity::find()
+            .filter(Column::Enabled.eq(true))
+            .order_by_asc(Column::Id)
+            .all(db)
+            .await?)
+    }
+
+    /// Records the rule as fired at `now` unless it already did within its cooldown. Only one
+    /// instance gets `true` for the same firing.
+    pub async fn claim_firing(db: &DatabaseConnection, id: i32, now: i64) -> ModelResult<bool> {
+        let result = Entity::update_many()
+            .col_expr(Column::LastFiredAt, Expr::value(now))
+            .filter(Column::Id.eq(id))
+            .filter(Expr::col(Column::LastFiredAt).add(Expr::col(Column::CooldownSecs)).lte(now))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    /// What the last webhook call did, `None` if it went through.
+    pub async fn set_last_error(db: &DatabaseConnection, id: i32, error: Option<&str>) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::LastError, Expr::value(error.map(str::to_string)))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::{Expr, SimpleExpr}, ActiveValue, DbBackend, PaginatorTrait, QueryOrder, QuerySelect};
+pub use super::_entities::cloudlogs::{self, ActiveModel, Entity, Model as CLGM, Column};
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+/// Filters for stored cloudlog lines, all of them have to match.
+#[derive(Debug, Default, Clone)]
+pub struct CloudlogSearch {
+    pub dongle_id: Option<String>,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    pub module: Option<String>,
+    pub func_name: Option<String>,
+    pub level: Option<String>,
+    pub levelnum: Option<i32>,
+    /// `eq` (the default), `gt` or `lt`
+    pub levelnum_op: Option<String>,
+    /// Unix time in milliseconds
+    pub from_millis: Option<i64>,
+    pub to_millis: Option<i64>,
+    /// `(field, op, value)` on the UTC time of the line, e.g. `("minute", ">=", 30)`
+    pub time_parts: Vec<(&'static str, &'static str, i32)>,
+    /// Other fields of the line's `ctx`, compared as text
+    pub ctx: Vec<(&'static str, String)>,
+}
+
+impl CloudlogSearch {
+    /// The time of day and `ctx` filters only exist for Postgres and SQLite.
+    pub fn supported_on(&self, backend: DbBackend) -> bool {
+        (self.time_parts.is_empty() && self.ctx.is_empty())
+            || matches!(backend, DbBackend::Postgres | DbBackend::Sqlite)
+    }
+}
+
+/// `field` of the line's UTC time, e.g. `minute`, compared to `value`.
+fn time_part_filter(backend: DbBackend, field: &str, op: &str, value: i32) -> SimpleExpr {
+    match backend {
+        DbBackend::Sqlite => {
+            let format = match field {
+                "year" => "%Y",
+                "month" => "%m",
+                "day" => "%d",
+                "hour" => "%H",
+                "minute" => "%M",
+                _ => "%S",
+            };
+            Expr::cust_with_values(
+                format!("cast(strftime('{format}', log_time / 1000.0, 'unixepoch') as integer) {op} $1"),
+                [value],
+            )
+        }
+        _ => Expr::cust_with_values(
+            format!("floor(extract({field} from to_timestamp(log_time / 1000.0) at time zone 'UTC')) {op} $1"),
+            [value],
+        ),
+    }
+}
+
+/// `field` of the line's `ctx` as text, booleans as `true` and `false` like Postgres has them.
+fn ctx_filter(backend: DbBackend, field: &str, value: &str) -> SimpleExpr {
+    match backend {
+        DbBackend::Sqlite => Expr::cust_with_values(
+            "(case json_type(log, '$.ctx.' || $1) when 'true' then 'true' when 'false' then 'false' \
+             else json_extract(log, '$.ctx.' || $2) end) = $3",
+            [field.to_string(), field.to_string(), value.to_string()],
+        ),
+        _ => Expr::cust_with_values(
+            "log -> 'ctx' ->> $1 = $2",
+            [field.to_string(), value.to_string()],
+        ),
+    }
+}
+
+fn str_field(value: &serde_json::Value, key: &str) -> Option<String> {
+    value.get(key).and_then(|v| v.as_str()).map(str::to_string)
+}
+
+impl CLGM {
+    /// The row for a line from `forwardLogs`. Lines without a time get the current one.
+    pub fn new_line(dongle_id: &str, log: serde_json::Value) -> ActiveModel {
+        let ctx = log.get("ctx").cloned().unwrap_or_default();
+        let log_time = log
+            .get("created")
+            .and_then(|v| v.as_f64())
+            .map_or_else(|| Utc::now().timestamp_millis(), |created| (created * 1000.0) as i64);
+        ActiveModel {
+            created_at: ActiveValue::Set(Utc::now().naive_utc()),
+            updated_at: ActiveValue::Set(Utc::now().naive_utc()),
+            dongle_id: ActiveValue::Set(dongle_id.to_string()),
+            branch: ActiveValue::Set(str_field(&ctx, "branch").unwrap_or_else(|| "unknown".to_string())),
+            commit: ActiveValue::Set(str_field(&ctx, "commit").unwrap_or_default()),
+            module: ActiveValue::Set(str_field(&log, "filename").unwrap_or_else(|| "unknown".to_string())),
+            func_name: ActiveValue::Set(str_field(&log, "funcName").unwrap_or_default()),
+            level: ActiveValue::Set(str_field(&log, "level").unwrap_or_default()),
+            levelnum: ActiveValue::Set(log.get("levelnum").and_then(|v| v.as_i64()).unwrap_or_default() as i32),
+            log_time: ActiveValue::Set(log_time),
+            log: ActiveValue::Set(log),
+            ..Default::default()
+        }
+    }
+
+    pub async fn insert_lines(db: &DatabaseConnection, dongle_id: &str, logs: &[serde_json::Value]) -> ModelResult<()> {
+        if logs.is_empty() {
+            return Ok(());
+        }
+        Entity::insert_many(logs.iter().map(|log| CLGM::new_line(dongle_id, log.clone())))
+            .exec_without_returning(db)
+            .await?;
+        Ok(())
+    }
+
+    /// The id of the newest line, 0 without any.
+    pub async fn last_id(db: &DatabaseConnection) -> ModelResult<i32> {
+        let id: Option<i32> = Entity::find()
+            .select_only()
+            .column(Column::Id)
+            .order_by_desc(Column::Id)
+            .into_tuple()
+            .one(db)
+            .await?;
+        Ok(id.unwrap_or_default())
+    }
+
+    /// Lines stored after `after_id` and before `before`, oldest first.
+    pub async fn find_after(
+        db: &DatabaseConnection,
+        after_id: i32,
+        before: chrono::NaiveDateTime,
+        limit: u64,
+    ) -> ModelResult<Vec<CLGM>> {
+        Ok(Entity::find()
+            .filter(Column::Id.gt(after_id))
+            .filter(Column::CreatedAt.lt(before))
+            .order_by_asc(Column::Id)
+            .limit(limit)
+            .all(db)
+            .await?)
+    }
+
+    /// Newest first, with the total number of matching lines.
+    pub async fn search(
+        db: &DatabaseConnection,
+        search: &CloudlogSearch,
+        limit: u64,
+        offset: u64,
+    ) -> ModelResult<(Vec<CLGM>, u64)> {
+        let backend = db.get_database_backend();
+        if !search.supported_on(backend) {
+            return Err(ModelError::Any("time of day and ctx filters need Postgres or SQLite".into()));
+        }
+        let mut query = Entity::find();
+        if let Some(dongle_id) = &search.dongle_id {
+            query = query.filter(Column::DongleId.eq(dongle_id));
+        }
+        if let Some(branch) = &search.branch {
+            query = query.filter(Column::Branch.eq(branch));
+        }
+        if let Some(commit) = &search.commit {
+            query = query.filter(Column::Commit.eq(commit));
+        }
+        if let Some(module) = &search.module {
+            query = query.filter(Column::Module.eq(module));
+        }
+        if let Some(func_name) = &search.func_name {
+            query = query.filter(Column::FuncName.eq(func_name));
+        }
+        if let Some(level) = &search.level {
+            query = query.filter(Column::Level.eq(level));
+        }
+        if let Some(levelnum) = search.levelnum {
+            query = match search.levelnum_op.as_deref() {
+                None | Some("eq") => query.filter(Column::Levelnum.eq(levelnum)),
+                Some("gt") => query.filter(Column::Levelnum.gt(levelnum)),
+                Some("lt") => query.filter(Column::Levelnum.lt(levelnum)),
+                // an operator we don't know matches nothing
+                Some(_) => query.filter(Expr::value(false)),
+            };
+        }
+        if let Some(from_millis) = search.from_millis {
+            query = query.filter(Column::LogTime.gte(from_millis));
+        }
+        if let Some(to_millis) = search.to_millis {
+            query = query.filter(Column::LogTime.lte(to_millis));
+        }
+        for (field, op, value) in &search.time_parts {
+            query = query.filter(time_part_filter(backend, field, op, *value));
+        }
+        for (field, value) in &search.ctx {
+            query = query.filter(ctx_filter(backend, field, value));
+        }
+        let total = query.clone().count(db).await?;
+        let lines = query
+            .order_by_desc(Column::LogTime)
+            .order_by_desc(Column::Id)
+            .limit(limit)
+            .offset(offset)
+            .all(db)
+            .await?;
+        Ok((lines, total))
+    }
+
+    /// Drops lines logged before `cutoff_millis`, returns how many.
+    pub async fn delete_before(db: &DatabaseConnection, cutoff_millis: i64) -> ModelResult<u64> {
+        let result = Entity::delete_many()
+            .filter(Column::LogTime.lt(cutoff_millis))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_forwarded_line() {
+        let log = serde_json::json!({
+            "created": 1700000000.25,
+            "filename": "controlsd.py",
+            "funcName": "step",
+            "level": "ERROR",
+            "levelnum": 40,
+            "ctx": {"branch": "release3", "commit": "abc123", "dirty": false},
+        });
+        let line = CLGM::new_line("0123456789abcdef", log);
+        assert_eq!(line.branch.unwrap(), "release3");
+        assert_eq!(line.commit.unwrap(), "abc123");
+        assert_eq!(line.module.unwrap(), "controlsd.py");
+        assert_eq!(line.levelnum.unwrap(), 40);
+        assert_eq!(line.log_time.unwrap(), 1_700_000_000_250);
+
+        let line = CLGM::new_line("0123456789abcdef", serde_json::json!({"msg": "no ctx"}));
+        assert_eq!(line.branch.unwrap(), "unknown");
+        assert_eq!(line.module.unwrap(), "unknown");
+    }
+
+    #[tokio::test]
+    async fn searches_time_and_ctx_on_sqlite() {
+        let db = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+        let schema = sea_orm::Schema::new(DbBackend::Sqlite);
+        db.execute(db.get_database_backend().build(&schema.create_table_from_entity(Entity)))
+            .await
+            .unwrap();
+        // 2023-11-14 22:13:20 and 22:43:20 UTC
+        let logs = [
+            serde_json::json!({"created": 1700000000.0, "ctx": {"branch": "release3", "dirty": false}}),
+            serde_json::json!({"created": 1700001800.0, "ctx": {"branch": "devel", "dirty": true}}),
+        ];
+        CLGM::insert_lines(&db, "0123456789abcdef", &logs).await.unwrap();
+
+        let search = CloudlogSearch {
+            time_parts: vec![("hour", "=", 22), ("minute", ">=", 30)],
+            ..Default::default()
+        };
+        let (lines, total) = CLGM::search(&db, &search, 10, 0).await.unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(lines[0].branch, "devel");
+
+        let search = CloudlogSearch {
+            ctx: vec![("dirty", "false".to_string())],
+            ..Default::default()
+        };
+        let (lines, _) = CLGM::search(&db, &search, 10, 0).await.unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].branch, "release3");
+
+        let search = CloudlogSearch {
+            ctx: vec![("branch", "devel".to_string())],
+            ..Default::default()
+        };
+        let (lines, _) = CLGM::search(&db, &search, 10, 0).await.unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].branch, "devel");
+    }
+}
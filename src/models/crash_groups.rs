@@ -0,0 +1,76 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::OnConflict, ActiveValue};
+pub use super::_entities::crash_groups::{self, ActiveModel, Entity, Model as CGM, Column};
+
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+impl CGM {
+    /// Create the group for `signature` or bump `last_seen` on the existing one.
+    pub async fn record(
+        db: &DatabaseConnection,
+        signature: &str,
+        exception_type: &str,
+        file: Option<String>,
+        line: Option<i32>,
+        function: Option<String>,
+    ) -> ModelResult<CGM> {
+        let now = Utc::now().naive_utc();
+        let group = ActiveModel {
+            signature: ActiveValue::Set(signature.to_string()),
+            exception_type: ActiveValue::Set(exception_type.to_string()),
+            file: ActiveValue::Set(file),
+            line: ActiveValue::Set(line),
+            function: ActiveValue::Set(function),
+            first_seen: ActiveValue::Set(now),
+            last_seen: ActiveValue::Set(now),
+            created_at: ActiveValue::Set(now),
+            updated_at: ActiveValue::Set(now),
+            ..Default::default()
+        };
+        let group = Entity::insert(group)
+            .on_conflict(
+                OnConflict::column(Column::Signature)
+                    .update_columns([Column::LastSeen, Column::UpdatedAt])
+                    .to_owned(),
+            )
+            .exec_with_returning(db)
+            .await?;
+        Ok(group)
+    }
+
+    pub async fn find_by_signature(db: &DatabaseConnection, signature: &str) -> ModelResult<CGM> {
+        let group = Entity::find()
+            .filter(Column::Signature.eq(signature))
+            .one(db)
+            .await?;
+        group.ok_or_else(|| ModelError::EntityNotFound)
+    }
+
+    pub async fn find_by_ids(db: &DatabaseConnection, ids: Vec<i32>) -> ModelResult<Vec<CGM>> {
+        let groups = Entity::find()
+            .filter(Column::Id.is_in(ids))
+            .all(db)
+            .await?;
+        Ok(groups)
+    }
+}
@@ -0,0 +1,102 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::{Expr, OnConflict}, ActiveValue, QueryOrder, QuerySelect, Select};
+pub use super::_entities::crashes::{self, ActiveModel, Entity, Model as CM, Column};
+
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+/// Narrow a crash query down. `dongle_ids` of None means every device.
+#[derive(Debug, Default)]
+pub struct CrashFilter {
+    pub dongle_ids: Option<Vec<String>>,
+    pub commit: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl CrashFilter {
+    fn apply(&self, mut query: Select<Entity>) -> Select<Entity> {
+        if let Some(dongle_ids) = &self.dongle_ids {
+            query = query.filter(Column::DongleId.is_in(dongle_ids.clone()));
+        }
+        if let Some(commit) = &self.commit {
+            query = query.filter(Column::Commit.starts_with(commit));
+        }
+        if let Some(branch) = &self.branch {
+            query = query.filter(Column::Branch.eq(branch));
+        }
+        query
+    }
+}
+
+impl CM {
+    /// Store a parsed crash. Reprocessing the same file is a no-op.
+    pub async fn add_crash(db: &DatabaseConnection, crash: CM) -> ModelResult<()> {
+        let mut crash = crash.into_active_model();
+        crash.id = ActiveValue::NotSet;
+        let now = Utc::now().naive_utc();
+        crash.created_at = ActiveValue::Set(now);
+        crash.updated_at = ActiveValue::Set(now);
+        Entity::insert(crash)
+            .on_conflict(OnConflict::column(Column::FileKey).do_nothing().to_owned())
+            .exec_without_returning(db)
+            .await?;
+        Ok(())
+    }
+
+    /// (crash_group_id, commit, branch, crashes, devices) for every group matching `filter`.
+    pub async fn group_counts(
+        db: &DatabaseConnection,
+        filter: &CrashFilter,
+    ) -> ModelResult<Vec<(i32, String, Option<String>, i64, i64)>> {
+        let counts = filter
+            .apply(Entity::find())
+            .select_only()
+            .column(Column::CrashGroupId)
+            .column(Column::Commit)
+            .column(Column::Branch)
+            .column_as(Column::Id.count(), "crashes")
+            .column_as(Expr::cust("COUNT(DISTINCT dongle_id)"), "devices")
+            .group_by(Column::CrashGroupId)
+            .group_by(Column::Commit)
+            .group_by(Column::Branch)
+            .into_tuple()
+            .all(db)
+            .await?;
+        Ok(counts)
+    }
+
+    pub async fn find_group_crashes(
+        db: &DatabaseConnection,
+        crash_group_id: i32,
+        filter: &CrashFilter,
+        limit: u64,
+    ) -> ModelResult<Vec<CM>> {
+        let crashes = filter
+            .apply(Entity::find())
+            .filter(Column::CrashGroupId.eq(crash_group_id))
+            .order_by_desc(Column::CreatedAt)
+            .limit(limit)
+            .all(db)
+            .await?;
+        Ok(crashes)
+    }
+}
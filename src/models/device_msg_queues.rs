@@ -1,9 +1,23 @@
 use chrono::prelude::Utc;
 use loco_rs::prelude::*;
-use sea_orm::{ActiveValue, QueryOrder, QuerySelect};
+use sea_orm::{sea_query::Expr, ActiveValue, Condition, PaginatorTrait, QueryOrder, QuerySelect};
 pub use super::_entities::device_msg_queues::{self, ActiveModel, Entity, Model as DMQM, Column};
 use crate::controllers::ws::JsonRpcRequest;
 
+pub const MSG_QUEUED: &str = "queued";
+/// Sent and waiting for the device to answer
+pub const MSG_SENT: &str = "sent";
+pub const MSG_DONE: &str = "done";
+/// The device answered with an error, or it ran out of attempts
+pub const MSG_FAILED: &str = "failed";
+pub const MSG_EXPIRED: &str = "expired";
+pub const MSG_CANCELLED: &str = "cancelled";
+
+/// JSON-RPC ids of queued commands start with this
+pub const OFFLINE_RPC_PREFIX: &str = "offline_";
+pub const DEFAULT_EXPIRY_SECS: i64 = 60 * 60 * 24 * 7;
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 3;
+
 
 #[async_trait::async_trait]
 impl ActiveModelBehavior for ActiveModel {
@@ -27,60 +41,206 @@ impl ActiveModelBehavior for ActiveModel {
 
 
 impl DMQM {
+    /// The JSON-RPC id the next attempt goes out under. Unique per attempt, so a late answer
+    /// can still be told apart from a live request's.
+    pub fn next_rpc_id(&self) -> String {
+        format!("{OFFLINE_RPC_PREFIX}{}_{}", self.id, self.attempts + 1)
+    }
 
-    pub async fn get_latest_msg(
+    /// Queue a command with the default expiry and attempts.
+    pub async fn insert_msg(
+        db: &DatabaseConnection,
+        dongle_id: &String,
+        json_rpc_request: JsonRpcRequest,
+    ) -> Result<(), DbErr> {
+        let expires_at = Utc::now().timestamp() + DEFAULT_EXPIRY_SECS;
+        Self::queue_msg(db, dongle_id, json_rpc_request, expires_at, DEFAULT_MAX_ATTEMPTS).await?;
+        Ok(())
+    }
+
+    /// `expires_at` is unix seconds, 0 never expires.
+    pub async fn queue_msg(
         db: &DatabaseConnection,
         dongle_id: &str,
-    ) -> Result<Option<JsonRpcRequest>, DbErr> {
-        if let Some(json_rpc_value) = Entity::find()
-            .filter(Column::DongleId.eq(dongle_id))
-            .order_by_desc(Column::CreatedAt)
-            .select_only()
-            .column(Column::JsonRpcRequest)
-            .into_model::<serde_json::Value>()
-            .one(db)
-            .await?
-        {
-            match serde_json::from_value::<JsonRpcRequest>(json_rpc_value) {
-                Ok(json_rpc_request) => Ok(Some(json_rpc_request)),
-                Err(_) => Ok(None), // Return None if deserialization fails
-            }
-        } else {
-            Ok(None)
+        json_rpc_request: JsonRpcRequest,
+        expires_at: i64,
+        max_attempts: i32,
+    ) -> Result<DMQM, DbErr> {
+        ActiveModel {
+            dongle_id: ActiveValue::Set(dongle_id.to_string()),
+            method: ActiveValue::Set(json_rpc_request.method.clone()),
+            json_rpc_request: ActiveValue::Set(serde_json::to_value(json_rpc_request).unwrap_or_default()),
+            status: ActiveValue::Set(MSG_QUEUED.to_string()),
+            expires_at: ActiveValue::Set(expires_at),
+            max_attempts: ActiveValue::Set(max_attempts),
+            ..Default::default()
         }
+        .insert(db)
+        .await
     }
 
-    pub async fn find_latest_msg(
+    /// Queued commands of a device that are waiting to go out, oldest first.
+    pub async fn find_due_msgs(
         db: &DatabaseConnection,
         dongle_id: &str,
-    ) -> Result<Option<DMQM>, DbErr> {
-        let msg = Entity::find()
+        limit: u64,
+    ) -> Result<Vec<DMQM>, DbErr> {
+        let now = Utc::now().timestamp();
+        Entity::find()
             .filter(Column::DongleId.eq(dongle_id))
-            .order_by_desc(Column::CreatedAt)
-            .one(db)
+            .filter(Column::Status.eq(MSG_QUEUED))
+            .filter(Condition::any().add(Column::ExpiresAt.eq(0)).add(Column::ExpiresAt.gt(now)))
+            .order_by_asc(Column::Id)
+            .limit(limit)
+            .all(db)
+            .await
+    }
+
+    /// Whether a command went out to the device and is still waiting for its answer.
+    pub async fn has_unacked(db: &DatabaseConnection, dongle_id: &str) -> Result<bool, DbErr> {
+        let sent = Entity::find()
+            .filter(Column::DongleId.eq(dongle_id))
+            .filter(Column::Status.eq(MSG_SENT))
+            .count(db)
             .await?;
-    
-        Ok(msg)
+        Ok(sent > 0)
     }
 
-    pub async fn insert_msg(
+    pub async fn mark_sent(db: &DatabaseConnection, id: i32, rpc_id: &str) -> Result<(), DbErr> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(MSG_SENT))
+            .col_expr(Column::RpcId, Expr::value(rpc_id))
+            .col_expr(Column::Attempts, Expr::col(Column::Attempts).add(1))
+            .col_expr(Column::SentAt, Expr::value(Utc::now().timestamp()))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Store the device's answer to the attempt sent as `rpc_id`. A late answer to an attempt
+    /// that was already put back in the queue still counts. False if nothing was waiting on it.
+    pub async fn ack(
         db: &DatabaseConnection,
-        dongle_id: &String,
-        json_rpc_request: JsonRpcRequest,
-    ) -> Result<(), DbErr> {
-        // Create a new active model to insert into the database
-        let new_msg = ActiveModel {
-            dongle_id: ActiveValue::Set(dongle_id.clone()),
-            json_rpc_request: ActiveValue::Set(serde_json::to_value(json_rpc_request).unwrap_or_default()),
-            ..Default::default()
-        };
+        dongle_id: &str,
+        rpc_id: &str,
+        result: Option<serde_json::Value>,
+        error: Option<serde_json::Value>,
+    ) -> Result<bool, DbErr> {
+        let status = if error.is_some() { MSG_FAILED } else { MSG_DONE };
+        let updated = Entity::update_many()
+            .col_expr(Column::Status, Expr::value(status))
+            .col_expr(Column::Result, Expr::value(result))
+            .col_expr(Column::Error, Expr::value(error.map(|error| error.to_string())))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::DongleId.eq(dongle_id))
+            .filter(Column::RpcId.eq(rpc_id))
+            .filter(Column::Status.is_in([MSG_SENT, MSG_QUEUED]))
+            .exec(db)
+            .await?;
+        Ok(updated.rows_affected > 0)
+    }
+
+    /// The send itself failed, try again later.
+    pub async fn requeue(db: &DatabaseConnection, id: i32, error: &str) -> Result<(), DbErr> {
+        Self::requeue_where(db, Condition::all().add(Column::Id.eq(id)), error).await
+    }
 
-        // Insert the new message into the database
-        Entity::insert(new_msg).exec(db).await?;
+    /// Sent before `cutoff` (unix seconds) and never answered.
+    pub async fn requeue_unacked(db: &DatabaseConnection, cutoff: i64) -> Result<(), DbErr> {
+        Self::requeue_where(db, Condition::all().add(Column::SentAt.lt(cutoff)), "no response from the device").await
+    }
 
+    /// Sent messages matching `condition` go back in the queue, or fail once they're out of attempts.
+    async fn requeue_where(db: &DatabaseConnection, condition: Condition, error: &str) -> Result<(), DbErr> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(MSG_FAILED))
+            .col_expr(Column::Error, Expr::value(format!("{error}, out of attempts")))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(condition.clone())
+            .filter(Column::Status.eq(MSG_SENT))
+            .filter(Expr::col(Column::Attempts).gte(Expr::col(Column::MaxAttempts)))
+            .exec(db)
+            .await?;
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(MSG_QUEUED))
+            .col_expr(Column::Error, Expr::value(error))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(condition)
+            .filter(Column::Status.eq(MSG_SENT))
+            .exec(db)
+            .await?;
         Ok(())
     }
 
+    pub async fn fail(db: &DatabaseConnection, id: i32, error: &str) -> Result<(), DbErr> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(MSG_FAILED))
+            .col_expr(Column::Error, Expr::value(error))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Queued messages past their expiry. Returns how many expired.
+    pub async fn expire_msgs(db: &DatabaseConnection) -> Result<u64, DbErr> {
+        let expired = Entity::update_many()
+            .col_expr(Column::Status, Expr::value(MSG_EXPIRED))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Status.eq(MSG_QUEUED))
+            .filter(Column::ExpiresAt.gt(0))
+            .filter(Column::ExpiresAt.lte(Utc::now().timestamp()))
+            .exec(db)
+            .await?;
+        Ok(expired.rows_affected)
+    }
+
+    /// A device's messages, newest first, with the total number that match.
+    pub async fn find_device_msgs(
+        db: &DatabaseConnection,
+        dongle_id: &str,
+        status: Option<&str>,
+        limit: u64,
+        offset: u64,
+    ) -> ModelResult<(Vec<DMQM>, u64)> {
+        let mut query = Entity::find().filter(Column::DongleId.eq(dongle_id));
+        if let Some(status) = status {
+            query = query.filter(Column::Status.eq(status));
+        }
+        let total = query.clone().count(db).await?;
+        let msgs = query
+            .order_by_desc(Column::Id)
+            .limit(limit)
+            .offset(offset)
+            .all(db)
+            .await?;
+        Ok((msgs, total))
+    }
+
+    pub async fn find_device_msg(db: &DatabaseConnection, dongle_id: &str, id: i32) -> ModelResult<DMQM> {
+        Entity::find_by_id(id)
+            .filter(Column::DongleId.eq(dongle_id))
+            .one(db)
+            .await?
+            .ok_or(ModelError::EntityNotFound)
+    }
+
+    /// Only messages that haven't been answered can be cancelled. False if it was too late.
+    pub async fn cancel(db: &DatabaseConnection, dongle_id: &str, id: i32) -> ModelResult<bool> {
+        let cancelled = Entity::update_many()
+            .col_expr(Column::Status, Expr::value(MSG_CANCELLED))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .filter(Column::DongleId.eq(dongle_id))
+            .filter(Column::Status.is_in([MSG_QUEUED, MSG_SENT]))
+            .exec(db)
+            .await?;
+        Ok(cancelled.rows_affected > 0)
+    }
+
     pub async fn delete_one_msg(
         db: &DatabaseConnection,
         id: &str,
@@ -4,6 +4,7 @@ use sea_orm::{ActiveValue, TransactionTrait, QueryOrder};
 use loco_rs::prelude::*;
 pub use super::_entities::devices::{self, ActiveModel, Entity, Model as DM, Column};
 use crate::controllers::v2::DeviceRegistrationParams;
+use crate::common::quota::FileClass;
 
 
 #[async_trait::async_trait]
@@ -123,6 +124,26 @@ impl DM {
         device.ok_or_else(|| ModelError::EntityNotFound)
     }
 
+    /// Atomically add `bytes` (negative to subtract) to the device's storage counters.
+    pub async fn add_storage<C: ConnectionTrait>(
+        db: &C,
+        dongle_id: &str,
+        class: FileClass,
+        bytes: i64,
+    ) -> Result<(), DbErr> {
+        let class_column = match class {
+            FileClass::Logs => Column::LogStorage,
+            FileClass::Cameras => Column::CameraStorage,
+        };
+        Entity::update_many()
+            .col_expr(Column::ServerStorage, Expr::col(Column::ServerStorage).add(bytes))
+            .col_expr(class_column, Expr::col(class_column).add(bytes))
+            .filter(Column::DongleId.eq(dongle_id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
     pub async fn reset_online(
         db: &DatabaseConnection,
     ) -> Result<(), DbErr> {
@@ -7,3 +7,12 @@ pub mod authorized_users;
 pub mod bootlogs;
 pub mod anonlogs;
 pub mod device_msg_queues;
+pub mod upload_sessions;
+pub mod storage_objects;
+pub mod crash_groups;
+pub mod crashes;
+pub mod clips;
+pub mod reprocess_jobs;
+pub mod worker_jobs;
+pub mod cloudlogs;
+pub mod cloudlog_alert_rules;
\ No newline at end of file
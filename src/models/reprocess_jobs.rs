@@ -0,0 +1,262 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{
+    sea_query::{Expr, LockBehavior, LockType},
+    ActiveValue, Condition, PaginatorTrait, QueryOrder, QuerySelect, TransactionTrait,
+};
+pub use super::_entities::reprocess_jobs::{self, ActiveModel, Entity, Model as RJM, Column};
+use super::_entities::segments;
+
+pub const JOB_PENDING: &str = "pending";
+pub const JOB_RUNNING: &str = "running";
+/// Every segment has been claimed, some batches haven't reported back yet
+pub const JOB_FINISHING: &str = "finishing";
+pub const JOB_DONE: &str = "done";
+pub const JOB_CANCELLED: &str = "cancelled";
+
+/// A batch that hasn't reported back by now is taken to be lost along with its worker
+const STALE_BATCH_SECS: i64 = 60 * 60;
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+/// Which segments a job reprocesses. Every field that is set narrows it down further.
+#[derive(Debug, Default, Clone)]
+pub struct ReprocessScope {
+    pub dongle_id: Option<String>,
+    pub route_fullname: Option<String>,
+    pub segment_name: Option<String>,
+    pub below_version: Option<i32>,
+}
+
+impl ReprocessScope {
+    pub fn is_empty(&self) -> bool {
+        self.dongle_id.is_none()
+            && self.route_fullname.is_none()
+            && self.segment_name.is_none()
+            && self.below_version.is_none()
+    }
+
+    /// Only segments with a qlog can be parsed again
+    pub fn condition(&self) -> Condition {
+        let mut condition = Condition::all().add(segments::Column::QlogUrl.ne(""));
+        if let Some(dongle_id) = &self.dongle_id {
+            condition = condition.add(segments::Column::CanonicalRouteName.starts_with(format!("{dongle_id}|")));
+        }
+        if let Some(route_fullname) = &self.route_fullname {
+            condition = condition.add(segments::Column::CanonicalRouteName.eq(route_fullname));
+        }
+        if let Some(segment_name) = &self.segment_name {
+            condition = condition.add(segments::Column::CanonicalName.eq(segment_name));
+        }
+        if let Some(below_version) = self.below_version {
+            condition = condition.add(segments::Column::ParserVersion.lt(below_version));
+        }
+        condition
+    }
+}
+
+impl RJM {
+    pub fn scope(&self) -> ReprocessScope {
+        ReprocessScope {
+            dongle_id: self.dongle_id.clone(),
+            route_fullname: self.route_fullname.clone(),
+            segment_name: self.segment_name.clone(),
+            below_version: self.below_version,
+        }
+    }
+
+    /// A pending job for everything in `scope`, with the number of segments it covers right now.
+    pub async fn add_job(db: &DatabaseConnection, scope: ReprocessScope) -> ModelResult<RJM> {
+        let total = segments::Entity::find()
+            .filter(scope.condition())
+            .count(db)
+            .await?;
+        let job = ActiveModel {
+            dongle_id: ActiveValue::Set(scope.dongle_id),
+            route_fullname: ActiveValue::Set(scope.route_fullname),
+            segment_name: ActiveValue::Set(scope.segment_name),
+            below_version: ActiveValue::Set(scope.below_version),
+            status: ActiveValue::Set(JOB_PENDING.to_string()),
+            total: ActiveValue::Set(total as i32),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+        Ok(job)
+    }
+
+    pub async fn find_job(db: &DatabaseConnection, id: i32) -> ModelResult<RJM> {
+        Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or(ModelError::EntityNotFound)
+    }
+
+    /// Newest first
+    pub async fn find_recent_jobs(db: &DatabaseConnection, limit: u64) -> ModelResult<Vec<RJM>> {
+        let jobs = Entity::find()
+            .order_by_desc(Column::Id)
+            .limit(limit)
+            .all(db)
+            .await?;
+        Ok(jobs)
+    }
+
+    /// The job that was started for everything below `version`, whatever its status. The oldest
+    /// one if two instances started it at once.
+    pub async fn find_version_job(db: &DatabaseConnection, version: i32) -> ModelResult<Option<RJM>> {
+        let job = Entity::find()
+            .filter(Column::BelowVersion.eq(version))
+            .filter(Column::DongleId.is_null())
+            .filter(Column::RouteFullname.is_null())
+            .filter(Column::SegmentName.is_null())
+            .order_by_asc(Column::Id)
+            .one(db)
+            .await?;
+        Ok(job)
+    }
+
+    /// The next `limit` segments of the job after its cursor, by canonical name.
+    pub async fn next_segments<C: ConnectionTrait>(&self, db: &C, limit: u64) -> ModelResult<Vec<segments::Model>> {
+        let mut query = segments::Entity::find().filter(self.scope().condition());
+        if let Some(cursor) = &self.cursor {
+            query = query.filter(segments::Column::CanonicalName.gt(cursor));
+        }
+        let segments = query
+            .order_by_asc(segments::Column::CanonicalName)
+            .limit(limit)
+            .all(db)
+            .await?;
+        Ok(segments)
+    }
+
+    /// Takes the next `limit` segments of the oldest unfinished job and moves its cursor past
+    /// them in one transaction, so workers running side by side never get the same batch. A job
+    /// that has none left comes back with an empty batch, and is done once the batches still out
+    /// have been counted, see `add_progress`.
+    ///
+    /// The job row is locked only while claiming. A worker that finds it locked by another one
+    /// goes on to the next job, or gets `None` when there is no other.
+    pub async fn claim_batch(db: &DatabaseConnection, limit: u64) -> ModelResult<Option<(RJM, Vec<segments::Model>)>> {
+        Self::finish_stale_jobs(db).await?;
+        let txn = db.begin().await?;
+        let Some(job) = Entity::find()
+            .filter(Column::Status.is_in([JOB_PENDING, JOB_RUNNING]))
+            .order_by_asc(Column::Id)
+            .lock_with_behavior(LockType::Update, LockBehavior::SkipLocked)
+            .one(&txn)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let segments = job.next_segments(&txn, limit).await?;
+        let update = match segments.last() {
+            Some(last) => Entity::update_many()
+                .col_expr(Column::Status, Expr::value(JOB_RUNNING))
+                .col_expr(Column::Cursor, Expr::value(last.canonical_name.clone()))
+                .col_expr(Column::Claimed, Expr::col(Column::Claimed).add(segments.len() as i32)),
+            None => Entity::update_many().col_expr(Column::Status, Expr::value(JOB_FINISHING)),
+        };
+        update
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(job.id))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        if segments.is_empty() {
+            Self::finish_if_counted(db, job.id).await?;
+        }
+        Ok(Some((job, segments)))
+    }
+
+    /// Marks a finishing job done once every segment it handed out has been counted.
+    async fn finish_if_counted(db: &DatabaseConnection, id: i32) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(JOB_DONE))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .filter(Column::Status.eq(JOB_FINISHING))
+            .filter(Expr::col(Column::Processed).add(Expr::col(Column::Failed)).gte(Expr::col(Column::Claimed)))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Finishing jobs that haven't heard from a batch in `STALE_BATCH_SECS` are done, with the
+    /// segments that never got counted as failed.
+    async fn finish_stale_jobs(db: &DatabaseConnection) -> ModelResult<()> {
+        let cutoff = Utc::now().naive_utc() - chrono::Duration::seconds(STALE_BATCH_SECS);
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(JOB_DONE))
+            .col_expr(Column::Failed, Expr::col(Column::Claimed).sub(Expr::col(Column::Processed)))
+            .col_expr(Column::Error, Expr::value("a batch was never reported back"))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Status.eq(JOB_FINISHING))
+            .filter(Column::UpdatedAt.lt(cutoff))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Counts a claimed batch once it has been worked through, which finishes the job if it was
+    /// the last one out.
+    pub async fn add_progress(
+        db: &DatabaseConnection,
+        id: i32,
+        processed: i32,
+        failed: i32,
+        error: Option<&str>,
+    ) -> ModelResult<()> {
+        let mut update = Entity::update_many()
+            .col_expr(Column::Processed, Expr::col(Column::Processed).add(processed))
+            .col_expr(Column::Failed, Expr::col(Column::Failed).add(failed))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()));
+        if let Some(error) = error {
+            update = update.col_expr(Column::Error, Expr::value(error));
+        }
+        update
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Self::finish_if_counted(db, id).await
+    }
+
+    pub async fn set_status(db: &DatabaseConnection, id: i32, status: &str) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(status))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Share of the segments handled so far, 0 to 1.
+    pub fn progress(&self) -> f32 {
+        if self.status == JOB_DONE {
+            return 1.0;
+        }
+        if self.total <= 0 {
+            return 0.0;
+        }
+        ((self.processed + self.failed) as f32 / self.total as f32).min(1.0)
+    }
+}
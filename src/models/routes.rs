@@ -1,7 +1,7 @@
 use chrono::prelude::Utc;
 use loco_rs::model::{ModelError, ModelResult};
 use loco_rs::prelude::*;
-use sea_orm::{ActiveValue, DeleteResult, PaginatorTrait, QueryOrder, QuerySelect, SelectColumns, TransactionTrait};
+use sea_orm::{sea_query::OnConflict, ActiveValue, DeleteResult, PaginatorTrait, QueryOrder, QuerySelect, SelectColumns, TransactionTrait};
 pub use super::_entities::routes::{self, ActiveModel, Entity, Model as RM, Column};
 
 
@@ -58,6 +58,19 @@ impl RM {
         Ok(self)
         
     }
+    /// Inserts the route unless a row with its fullname exists, then returns the stored row.
+    /// Safe to race, the insert of whoever loses is dropped.
+    pub async fn find_or_add_route(self, db: &DatabaseConnection) -> ModelResult<RM> {
+        let now = Utc::now().naive_utc();
+        let mut active_model = self.clone().into_active_model();
+        active_model.created_at = ActiveValue::Set(now);
+        active_model.updated_at = ActiveValue::Set(now);
+        Entity::insert(active_model)
+            .on_conflict(OnConflict::column(Column::Fullname).do_nothing().to_owned())
+            .exec_without_returning(db)
+            .await?;
+        RM::find_route(db, &self.fullname).await
+    }
     /// Finds a route by its canonical route name.
     ///
     /// # Arguments
@@ -111,6 +124,7 @@ impl RM {
         from: Option<i64>,
         to: Option<i64>,
         limit: Option<u64>,
+        flagged: Option<bool>,
     ) -> ModelResult<Vec<RM>> {
         let mut query = Entity::find().filter(Column::DeviceDongleId.eq(dongle_id));
 
@@ -120,6 +134,9 @@ impl RM {
         if let Some(to_time) = to {
             query = query.filter(Column::StartTimeUtcMillis.lte(to_time));
         }
+        if let Some(flagged) = flagged {
+            query = query.filter(Column::HasUserFlag.eq(flagged));
+        }
         if let Some(limit_val) = limit {
             query = query.limit(limit_val);
         }
@@ -155,6 +172,32 @@ impl RM {
         Ok(route.is_public)
     }
 
+    /// Routes with gps after `after` (by fullname), for walking the whole table a page at a
+    /// time. Unless `all` is set only the ones without place names yet.
+    pub async fn find_gps_routes_page(
+        db: &DatabaseConnection,
+        dongle_id: Option<&str>,
+        all: bool,
+        after: &str,
+        limit: u64,
+    ) -> ModelResult<Vec<RM>> {
+        let mut query = Entity::find()
+            .filter(Column::Hpgps.eq(true))
+            .filter(Column::Fullname.gt(after));
+        if let Some(dongle_id) = dongle_id {
+            query = query.filter(Column::DeviceDongleId.eq(dongle_id));
+        }
+        if !all {
+            query = query.filter(Column::StartPlace.is_null());
+        }
+        let routes = query
+            .order_by_asc(Column::Fullname)
+            .limit(limit)
+            .all(db)
+            .await?;
+        Ok(routes)
+    }
+
     /// Finds all routes associated with a device.
     ///
     /// # Arguments
@@ -203,7 +246,7 @@ impl RM {
         dongle_id: &str,
         from: Option<i64>,
         to: Option<i64>,
-    ) -> ModelResult<(f32, u32, i64)> {
+    ) -> ModelResult<(f32, u32, i64, f32)> {
         use sea_orm::prelude::*;
         use sea_orm::{QuerySelect, Condition};
         
@@ -220,19 +263,20 @@ impl RM {
             condition = condition.add(Column::StartTimeUtcMillis.lte(to));
         }
         
-        let routes: Vec<(f32, i64, i64)> = Entity::find()
+        let routes: Vec<(f32, i64, i64, f32)> = Entity::find()
             .filter(condition)
             .select_only()
-            .columns([Column::Length, Column::StartTimeUtcMillis, Column::EndTimeUtcMillis])
-            .into_tuple::<(f32, i64, i64)>()  // Fetch length, start, and end times
+            .columns([Column::Length, Column::StartTimeUtcMillis, Column::EndTimeUtcMillis, Column::EngagedMiles])
+            .into_tuple::<(f32, i64, i64, f32)>()  // Fetch length, start, end times and engaged length
             .all(db)
             .await?;
         
-        let total_length: f32 = routes.iter().map(|(length, _, _)| length).sum(); // Sum all the lengths
-        let total_route_time: i64 = routes.iter().map(|(_, start, end)| end - start).sum(); // Sum total time (end - start) for each route
+        let total_length: f32 = routes.iter().map(|(length, _, _, _)| length).sum(); // Sum all the lengths
+        let total_route_time: i64 = routes.iter().map(|(_, start, end, _)| end - start).sum(); // Sum total time (end - start) for each route
+        let engaged_length: f32 = routes.iter().map(|(_, _, _, engaged)| engaged).sum();
         let route_count = routes.len() as u32; // Count the number of routes
         
-        Ok((total_length, route_count, total_route_time))
+        Ok((total_length, route_count, total_route_time, engaged_length))
     }
 
     pub async fn get_miles(db: &DatabaseConnection) -> ModelResult<f32> {
@@ -1,7 +1,7 @@
 use chrono::prelude::Utc;
 use loco_rs::prelude::*;
 use serde::Serialize;
-use sea_orm::{entity::prelude::*, DeleteResult, TransactionTrait, QueryOrder};
+use sea_orm::{entity::prelude::*, sea_query::OnConflict, DeleteResult, TransactionTrait, QueryOrder};
 pub use super::_entities::segments::{self, ActiveModel, Entity, Model as SM, Column};
 
 
@@ -73,6 +73,20 @@ impl SM {
         
     }
 
+    /// Inserts the segment unless a row with its canonical name exists, then returns the stored
+    /// row. Safe to race, the insert of whoever loses is dropped.
+    pub async fn find_or_add_segment(self, db: &DatabaseConnection) -> ModelResult<SM> {
+        let now = Utc::now().naive_utc();
+        let mut active_model = self.clone().into_active_model();
+        active_model.created_at = ActiveValue::Set(now);
+        active_model.updated_at = ActiveValue::Set(now);
+        Entity::insert(active_model)
+            .on_conflict(OnConflict::column(Column::CanonicalName).do_nothing().to_owned())
+            .exec_without_returning(db)
+            .await?;
+        SM::find_one(db, &self.canonical_name).await
+    }
+
     pub async fn find_one(
         db: &DatabaseConnection,
         canonical_name: &String,
@@ -84,8 +98,8 @@ impl SM {
         segment.ok_or_else(|| ModelError::EntityNotFound)
     }
     /// Sorted by segment number ascending
-    pub async fn find_segments_by_route(
-        db: &DatabaseConnection,
+    pub async fn find_segments_by_route<C: ConnectionTrait>(
+        db: &C,
         canonical_route_name: &str,
     ) -> ModelResult<Vec<SM>> {
         let segments = Entity::find()
@@ -0,0 +1,129 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::{Expr, OnConflict}, ActiveValue, TransactionTrait};
+pub use super::_entities::storage_objects::{self, ActiveModel, Entity, Model as SOM, Column};
+use super::_entities::devices;
+use super::devices::DM;
+use crate::common::quota::FileClass;
+
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+/// Every stored key starts with the dongle id of the device it belongs to.
+pub fn dongle_id_from_key(key: &str) -> Option<&str> {
+    key.split_once('_').map(|(dongle_id, _)| dongle_id)
+}
+
+impl SOM {
+    /// Record a newly stored object and add its size to the device's totals.
+    /// Returns false without counting anything if the key was already tracked.
+    pub async fn track(db: &DatabaseConnection, key: &str, size: i64) -> ModelResult<bool> {
+        let Some(dongle_id) = dongle_id_from_key(key) else {
+            return Ok(false);
+        };
+        let now = Utc::now().naive_utc();
+        let object = ActiveModel {
+            key: ActiveValue::Set(key.to_string()),
+            dongle_id: ActiveValue::Set(dongle_id.to_string()),
+            size: ActiveValue::Set(size),
+            created_at: ActiveValue::Set(now),
+            updated_at: ActiveValue::Set(now),
+            ..Default::default()
+        };
+
+        let txn = db.begin().await?;
+        let inserted = Entity::insert(object)
+            .on_conflict(OnConflict::column(Column::Key).do_nothing().to_owned())
+            .exec_without_returning(&txn)
+            .await?;
+        if inserted == 1 {
+            DM::add_storage(&txn, dongle_id, FileClass::from_file_name(key), size).await?;
+        }
+        txn.commit().await?;
+        Ok(inserted == 1)
+    }
+
+    /// Forget a deleted object and subtract its size from the device's totals.
+    /// Returns the size that was released, None if the key wasn't tracked.
+    pub async fn untrack(db: &DatabaseConnection, key: &str) -> ModelResult<Option<i64>> {
+        let txn = db.begin().await?;
+        let Some(object) = Entity::find()
+            .filter(Column::Key.eq(key))
+            .one(&txn)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let deleted = Entity::delete_by_id(object.id).exec(&txn).await?;
+        if deleted.rows_affected == 1 {
+            DM::add_storage(&txn, &object.dongle_id, FileClass::from_file_name(key), -object.size).await?;
+        }
+        txn.commit().await?;
+        Ok((deleted.rows_affected == 1).then_some(object.size))
+    }
+
+    /// Replace everything tracked for a device with `objects` (key, size) and set the
+    /// device's totals to match. Used when recomputing usage from the blob store.
+    pub async fn replace_device_objects(
+        db: &DatabaseConnection,
+        dongle_id: &str,
+        objects: &[(String, i64)],
+    ) -> ModelResult<()> {
+        let txn = db.begin().await?;
+        Entity::delete_many()
+            .filter(Column::DongleId.eq(dongle_id))
+            .exec(&txn)
+            .await?;
+
+        let (mut log_storage, mut camera_storage) = (0, 0);
+        for (key, size) in objects {
+            match FileClass::from_file_name(key) {
+                FileClass::Logs => log_storage += size,
+                FileClass::Cameras => camera_storage += size,
+            }
+        }
+
+        let now = Utc::now().naive_utc();
+        for chunk in objects.chunks(1000) {
+            let models = chunk.iter().map(|(key, size)| {
+                ActiveModel {
+                    key: ActiveValue::Set(key.clone()),
+                    dongle_id: ActiveValue::Set(dongle_id.to_string()),
+                    size: ActiveValue::Set(*size),
+                    created_at: ActiveValue::Set(now),
+                    updated_at: ActiveValue::Set(now),
+                    ..Default::default()
+                }
+            });
+            Entity::insert_many(models).exec_without_returning(&txn).await?;
+        }
+
+        devices::Entity::update_many()
+            .col_expr(devices::Column::ServerStorage, Expr::value(log_storage + camera_storage))
+            .col_expr(devices::Column::LogStorage, Expr::value(log_storage))
+            .col_expr(devices::Column::CameraStorage, Expr::value(camera_storage))
+            .filter(devices::Column::DongleId.eq(dongle_id))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::prelude::Utc;
+use chrono::Duration;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::{Expr, OnConflict}, ActiveValue};
+pub use super::_entities::upload_sessions::{self, ActiveModel, Entity, Model as USM, Column};
+
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+impl USM {
+    /// Storage key of the `index`th chunk of this session.
+    pub fn part_key(&self, index: i32) -> String {
+        format!("{}_upload_{}_{:05}", self.dongle_id, self.session_id, index)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        match self.total_size {
+            Some(total_size) => self.committed_offset == total_size,
+            None => self.part_count > 0,
+        }
+    }
+
+    pub fn file_key_for(dongle_id: &str, timestamp: &str, segment: &str, file: &str) -> String {
+        format!("{}_{}--{}--{}", dongle_id, timestamp, segment, file)
+    }
+
+    /// The session for `file_key`, expired or not. There is at most one.
+    pub async fn find_file_session(db: &DatabaseConnection, file_key: &str) -> ModelResult<Option<USM>> {
+        Ok(Entity::find().filter(Column::FileKey.eq(file_key)).one(db).await?)
+    }
+
+    /// The session for the file, created if there is none. Concurrent calls get the same one.
+    pub async fn find_or_create(
+        db: &DatabaseConnection,
+        dongle_id: &str,
+        timestamp: &str,
+        segment: &str,
+        file: &str,
+        total_size: Option<i64>,
+        ttl: Duration,
+    ) -> ModelResult<USM> {
+        let file_key = USM::file_key_for(dongle_id, timestamp, segment, file);
+        let now = Utc::now().naive_utc();
+        let session = ActiveModel {
+            created_at: ActiveValue::Set(now),
+            updated_at: ActiveValue::Set(now),
+            session_id: ActiveValue::Set(Uuid::new_v4()),
+            dongle_id: ActiveValue::Set(dongle_id.to_string()),
+            file_key: ActiveValue::Set(file_key.clone()),
+            timestamp: ActiveValue::Set(timestamp.to_string()),
+            segment: ActiveValue::Set(segment.to_string()),
+            file: ActiveValue::Set(file.to_string()),
+            total_size: ActiveValue::Set(total_size),
+            committed_offset: ActiveValue::Set(0),
+            part_count: ActiveValue::Set(0),
+            expires_at: ActiveValue::Set(now + ttl),
+            ..Default::default()
+        };
+        Entity::insert(session)
+            .on_conflict(OnConflict::column(Column::FileKey).do_nothing().to_owned())
+            .exec_without_returning(db)
+            .await?;
+        USM::find_file_session(db, &file_key)
+            .await?
+            .ok_or(ModelError::EntityNotFound)
+    }
+
+    pub async fn find_session(db: &DatabaseConnection, session_id: &Uuid) -> ModelResult<USM> {
+        let session = Entity::find()
+            .filter(Column::SessionId.eq(*session_id))
+            .filter(Column::ExpiresAt.gt(Utc::now().naive_utc()))
+            .one(db)
+            .await?;
+        session.ok_or_else(|| ModelError::EntityNotFound)
+    }
+
+    /// Record a chunk that was written at the current offset and push the expiry out. Only
+    /// the first of two requests committing the same part moves the offset.
+    pub async fn commit_part(
+        self,
+        db: &DatabaseConnection,
+        bytes: i64,
+        ttl: Duration,
+    ) -> ModelResult<USM> {
+        Entity::update_many()
+            .col_expr(Column::CommittedOffset, Expr::value(self.committed_offset + bytes))
+            .col_expr(Column::PartCount, Expr::value(self.part_count + 1))
+            .col_expr(Column::ExpiresAt, Expr::value(Utc::now().naive_utc() + ttl))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(self.id))
+            .filter(Column::CommittedOffset.eq(self.committed_offset))
+            .exec(db)
+            .await?;
+        Entity::find_by_id(self.id)
+            .one(db)
+            .await?
+            .ok_or(ModelError::EntityNotFound)
+    }
+
+    pub async fn find_expired(db: &DatabaseConnection) -> ModelResult<Vec<USM>> {
+        let sessions = Entity::find()
+            .filter(Column::ExpiresAt.lte(Utc::now().naive_utc()))
+            .all(db)
+            .await?;
+        Ok(sessions)
+    }
+
+    pub async fn delete_session(db: &DatabaseConnection, id: i32) -> ModelResult<()> {
+        Entity::delete_by_id(id).exec(db).await?;
+        Ok(())
+    }
+}
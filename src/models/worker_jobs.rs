@@ -0,0 +1,137 @@
+use chrono::prelude::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{sea_query::Expr, ActiveValue, PaginatorTrait, QueryOrder, QuerySelect};
+pub use super::_entities::worker_jobs::{self, ActiveModel, Entity, Model as WJM, Column};
+
+pub const STATUS_QUEUED: &str = "queued";
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_DONE: &str = "done";
+/// Nothing to do for the file, e.g. it came from an unregistered device
+pub const STATUS_SKIPPED: &str = "skipped";
+pub const STATUS_FAILED: &str = "failed";
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    // extend activemodel below (keep comment for generators)
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut this = self;
+        if insert {
+            this.created_at = ActiveValue::Set(Utc::now().naive_utc());
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        } else {
+            // update time
+            this.updated_at = ActiveValue::Set(Utc::now().naive_utc());
+            Ok(this)
+        }
+    }
+}
+
+impl WJM {
+    /// Mark the job for `file_key` as running, counting another attempt if it ran before.
+    pub async fn start(
+        db: &DatabaseConnection,
+        worker: &str,
+        file_key: &str,
+        dongle_id: &str,
+        args: &str,
+    ) -> ModelResult<WJM> {
+        let existing = Entity::find()
+            .filter(Column::Worker.eq(worker))
+            .filter(Column::FileKey.eq(file_key))
+            .one(db)
+            .await?;
+        let job = match existing {
+            Some(job) => {
+                let attempts = job.attempts + 1;
+                let mut job = job.into_active_model();
+                job.status = ActiveValue::Set(STATUS_RUNNING.to_string());
+                job.attempts = ActiveValue::Set(attempts);
+                job.args = ActiveValue::Set(args.to_string());
+                job.update(db).await?
+            }
+            None => {
+                ActiveModel {
+                    worker: ActiveValue::Set(worker.to_string()),
+                    file_key: ActiveValue::Set(file_key.to_string()),
+                    dongle_id: ActiveValue::Set(dongle_id.to_string()),
+                    args: ActiveValue::Set(args.to_string()),
+                    status: ActiveValue::Set(STATUS_RUNNING.to_string()),
+                    attempts: ActiveValue::Set(1),
+                    ..Default::default()
+                }
+                .insert(db)
+                .await?
+            }
+        };
+        Ok(job)
+    }
+
+    /// `error` is kept for skipped jobs too, as the reason.
+    pub async fn finish(
+        db: &DatabaseConnection,
+        id: i32,
+        status: &str,
+        duration_millis: i64,
+        error: Option<&str>,
+    ) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(status))
+            .col_expr(Column::DurationMillis, Expr::value(duration_millis))
+            .col_expr(Column::LastError, Expr::value(error.map(str::to_string)))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_queued(db: &DatabaseConnection, id: i32) -> ModelResult<()> {
+        Entity::update_many()
+            .col_expr(Column::Status, Expr::value(STATUS_QUEUED))
+            .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn find_job(db: &DatabaseConnection, id: i32) -> ModelResult<WJM> {
+        Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or(ModelError::EntityNotFound)
+    }
+
+    /// Most recently updated first, with the total number of matching jobs.
+    pub async fn find_jobs(
+        db: &DatabaseConnection,
+        status: Option<&str>,
+        worker: Option<&str>,
+        dongle_id: Option<&str>,
+        limit: u64,
+        offset: u64,
+    ) -> ModelResult<(Vec<WJM>, u64)> {
+        let mut query = Entity::find();
+        if let Some(status) = status {
+            query = query.filter(Column::Status.eq(status));
+        }
+        if let Some(worker) = worker {
+            query = query.filter(Column::Worker.eq(worker));
+        }
+        if let Some(dongle_id) = dongle_id {
+            query = query.filter(Column::DongleId.eq(dongle_id));
+        }
+        let total = query.clone().count(db).await?;
+        let jobs = query
+            .order_by_desc(Column::UpdatedAt)
+            .limit(limit)
+            .offset(offset)
+            .all(db)
+            .await?;
+        Ok((jobs, total))
+    }
+}
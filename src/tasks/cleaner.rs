@@ -1,8 +1,5 @@
 use std::collections::BTreeMap;
-use reqwest::Client;
 use regex::Regex;
-use serde_json::from_str;
-use serde_json::Value;
 use loco_rs::prelude::*;
 use chrono::{Utc, Duration, NaiveDateTime, ParseError};
 use sysinfo::Disks;
@@ -12,13 +9,14 @@ use std::path::Path;
 
 use crate::{
     common::{
-        mkv_helpers,
         re::*,
+        storage::{self, StorageError},
     },
     models::{
         segments::SM,
         devices::DM,
         routes::RM,
+        storage_objects::SOM,
     },
 };
 
@@ -38,7 +36,6 @@ impl Task for Cleaner {
     async fn run(&self, ctx: &AppContext, _vars: &task::Vars) -> Result<()> {
         println!("Task Cleaner generated");
     
-        let client = Client::new();
         let mut retention_minutes = 24 * 60 * 7; // keep for 7 days for debugging
 
         loop {
@@ -57,26 +54,23 @@ impl Task for Cleaner {
                     &device.dongle_id,
                     None,
                     Some(older_than.and_utc().timestamp_millis()),
-                    Some(10000)
+                    Some(10000),
+                    None,
                 ).await?;
 
                 // check the length of each route
                 for route in routes {
                     if (route.length < 0.1 && route.hpgps == true) || (route.can == false) {
-                        let query = mkv_helpers::list_keys_starting_with(&route.fullname);
-                        let response = client.get(&query).send().await.unwrap();
-                        if !response.status().is_success() {
-                            tracing::info!("Failed to get keys");
-                            return Ok(());
-                        }
-                        let body = response.text().await.unwrap();
-                        let json: Value = from_str(&body).unwrap(); // Convert response text into JSON
-                        let keys = json["keys"].as_array().unwrap(); // Safely extract as an array
-                        for key in keys {
-                            let mut file_name = key.as_str().unwrap().to_string(); // Convert to string for independent ownership
-                            file_name = file_name.strip_prefix("/").unwrap().to_string(); // Strip prefix and convert back to string
-                            tracing::info!("Deleting file: {file_name}");
-                            delete_file(&client, &file_name).await;
+                        // keys use `_` where the route fullname has `|`
+                        let keys = match storage::blob_store().list_prefix(&route.fullname.replace("|", "_")).await {
+                            Ok(keys) => keys,
+                            Err(e) => {
+                                tracing::info!("Failed to get keys: {e}");
+                                return Ok(());
+                            }
+                        };
+                        for file_name in &keys {
+                            delete_file(&ctx.db, file_name).await;
                         }
 
                         RM::delete_route(&ctx.db, &route.fullname).await?;
@@ -88,7 +82,14 @@ impl Task for Cleaner {
     }
 }
 
-async fn delete_file(client: &Client, file_name: &str) {
+async fn delete_file(db: &DatabaseConnection, file_name: &str) {
     tracing::info!("Deleting file: {file_name}");
-    client.delete(&mkv_helpers::get_mkv_file_url(file_name)).send().await.unwrap();
+    match storage::blob_store().delete(file_name).await {
+        Ok(()) | Err(StorageError::NotFound(_)) => {
+            if let Err(e) = SOM::untrack(db, file_name).await {
+                tracing::error!("Failed to release storage for {file_name}: {e}");
+            }
+        }
+        Err(e) => tracing::error!("Failed to delete {file_name}: {e}"),
+    }
 }
\ No newline at end of file
@@ -1,18 +1,16 @@
 use std::collections::BTreeMap;
-use reqwest::Client;
 use regex::Regex;
-use serde_json::from_str;
-use serde_json::Value;
 use loco_rs::prelude::*;
 use chrono::{Utc, Duration, NaiveDateTime, ParseError};
 use sysinfo::Disks;
 use std::env;
 use std::path::Path;
 
-use crate::{models::_entities::{
-    segments,
+use crate::{models::{
+    _entities::segments,
+    storage_objects::SOM,
     },
-    common::mkv_helpers,
+    common::storage::{self, StorageError},
     common::re::*,
 };
 
@@ -50,21 +48,14 @@ impl Task for Deleter {
         );
         let re = Regex::new(&segment_file_regex_string).unwrap();
 
-        let client = Client::new();
-        // Get all keys from the MKV server
-        let query = mkv_helpers::list_keys_starting_with("");
-        let response = client.get(&query).send().await.unwrap();
-
-        if !response.status().is_success() {
-            tracing::info!("Failed to get keys");
-            return Ok(());
-        }
-
-        let body = response.text().await.unwrap();
-        let json: Value = from_str(&body).unwrap(); // Convert response text into JSON
-
-        // Extract keys from the JSON object
-        let keys = json["keys"].as_array().unwrap(); // Safely extract as an array
+        // Get all keys from storage
+        let keys = match storage::blob_store().list_prefix("").await {
+            Ok(keys) => keys,
+            Err(e) => {
+                tracing::info!("Failed to get keys: {e}");
+                return Ok(());
+            }
+        };
         // TODO: Refactor to not load the whole response in ram at once as it could get large.
 
         let mut retention_minutes = 356 * 24 * 60; // Start with 356 days in minutes
@@ -84,10 +75,8 @@ impl Task for Deleter {
             let older_than = now - Duration::minutes(retention_minutes);
             tracing::info!("now: {now}, deleting files older than: {older_than}");
 
-            for key in keys {
-                let mut file_name = key.as_str().unwrap().to_string(); // Convert to string for independent ownership
-                file_name = file_name.strip_prefix("/").unwrap().to_string(); // Strip prefix and convert back to string
-                match re.captures(&file_name) {
+            for file_name in &keys {
+                match re.captures(file_name) {
                     Some(caps) => {
                         let dongle_id = &caps[1];
                         let timestamp = &caps[2];
@@ -97,14 +86,14 @@ impl Task for Deleter {
                             Ok(segment) => {
                                 let deleted = false;
                                 if segment.updated_at <= older_than && !deleted { // Fallback to updated_at
-                                    delete_file(&client, &file_name).await;
+                                    delete_file(&ctx.db, file_name).await;
                                 }
                             },
                             Err(_e) => {
                                 tracing::error!("No segment found for file: {file_name}. ");
                                 if let Ok(derived_dt) = parse_timestamp(timestamp) {
                                     if derived_dt <= older_than {
-                                        delete_file(&client, &file_name).await;
+                                        delete_file(&ctx.db, file_name).await;
                                     }
                                 };
                             }   
@@ -112,7 +101,7 @@ impl Task for Deleter {
                     }
                     None => {
                         tracing::error!("Unknown file or bootlog in kv store. Deleting it!");
-                        delete_file(&client, &file_name).await;
+                        delete_file(&ctx.db, file_name).await;
                     }
                 }
             }
@@ -128,7 +117,14 @@ impl Task for Deleter {
     }
 }
 
-async fn delete_file(client: &Client, file_name: &str) {
+async fn delete_file(db: &DatabaseConnection, file_name: &str) {
     tracing::info!("Deleting file: {file_name}");
-    client.delete(&mkv_helpers::get_mkv_file_url(file_name)).send().await.unwrap();
+    match storage::blob_store().delete(file_name).await {
+        Ok(()) | Err(StorageError::NotFound(_)) => {
+            if let Err(e) = SOM::untrack(db, file_name).await {
+                tracing::error!("Failed to release storage for {file_name}: {e}");
+            }
+        }
+        Err(e) => tracing::error!("Failed to delete {file_name}: {e}"),
+    }
 }
\ No newline at end of file
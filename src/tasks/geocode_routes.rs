@@ -0,0 +1,53 @@
+use loco_rs::prelude::*;
+
+use crate::{common::geocode, models::routes::RM};
+
+const PAGE_SIZE: u64 = 500;
+
+/// Fills in the start and end place names of routes parsed before the geocoder was set up.
+/// `dongle_id:<id>` limits it to one device, `all:true` redoes routes that already have names.
+pub struct GeocodeRoutes;
+#[async_trait]
+impl Task for GeocodeRoutes {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "geocode_routes".to_string(),
+            detail: "Backfill route start and end place names from the offline geocoder".to_string(),
+        }
+    }
+    async fn run(&self, ctx: &AppContext, vars: &task::Vars) -> Result<()> {
+        let dongle_id = vars.cli_arg("dongle_id").ok().cloned();
+        let all = vars.cli_arg("all").is_ok_and(|v| v == "true");
+
+        if !geocode::is_available() {
+            tracing::error!("No reverse geocoding dataset loaded, check GEOCODER_DATASET");
+            return Ok(());
+        }
+
+        let mut after = String::new();
+        let mut updated = 0;
+        loop {
+            let routes = RM::find_gps_routes_page(&ctx.db, dongle_id.as_deref(), all, &after, PAGE_SIZE).await?;
+            let Some(last) = routes.last() else {
+                break;
+            };
+            after = last.fullname.clone();
+
+            for route in routes {
+                let start_place = geocode::place_name(route.start_lat, route.start_lng);
+                let end_place = geocode::place_name(route.end_lat, route.end_lng);
+                if start_place.is_none() && end_place.is_none() {
+                    continue; // nowhere near anything in the dataset, leave it for a bigger one
+                }
+                let mut active_route = route.into_active_model();
+                active_route.start_place = ActiveValue::Set(start_place);
+                active_route.end_place = ActiveValue::Set(end_place);
+                active_route.update(&ctx.db).await?;
+                updated += 1;
+            }
+            tracing::info!("Geocoded {updated} routes so far");
+        }
+        tracing::info!("Done, geocoded {updated} routes");
+        Ok(())
+    }
+}
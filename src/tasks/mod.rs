@@ -4,4 +4,5 @@ pub mod collect_data;
 pub mod deleter;
 pub mod huggingface;
 pub mod storage_count;
-pub mod cleaner;
\ No newline at end of file
+pub mod cleaner;
+pub mod geocode_routes;
\ No newline at end of file
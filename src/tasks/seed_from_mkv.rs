@@ -5,11 +5,9 @@ use time::serde::timestamp; use std::env::vars;
 // For the `shuffle` method
 use std::thread;
 use std::time::Duration;
-use reqwest::Client;
 use std::time::{SystemTime, UNIX_EPOCH};
-use serde_json::{Value, from_str};
 use indicatif::{ProgressBar, ProgressStyle};
-use crate::{common, workers::{bootlog_parser::{BootlogParserWorker, BootlogParserWorkerArgs}, log_parser::{LogSegmentWorker, LogSegmentWorkerArgs}}};
+use crate::{common::storage, workers::{bootlog_parser::{BootlogParserWorker, BootlogParserWorkerArgs}, log_parser::{LogSegmentWorker, LogSegmentWorkerArgs}, reprocess::delete_derived}};
 use loco_rs::prelude::*;
 use crate::common::re::*;
 pub struct SeedFromMkv;
@@ -32,42 +30,30 @@ impl Task for SeedFromMkv {
             .cli_arg("timestamp")
             .ok();
         
-        let client = Client::new();
+        let store = storage::blob_store();
 
         // Hex characters for prefix chunking
         let hex_chars = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", 
             "a", "b", "c", "d", "e", "f"];
-        let mut keys = Vec::with_capacity(1000);
+        let mut keys: Vec<String> = Vec::with_capacity(1000);
         if let Some(dongle_id_filter) = dongle_id_filter {
-            let query = common::mkv_helpers::list_keys_starting_with(&format!("{}", dongle_id_filter));
-            let response = client.get(&query).send().await.unwrap();
-
-            if !response.status().is_success() {
-                tracing::info!("Failed to get keys");
-                return Ok(());
-            }
-
-            let body = response.text().await.unwrap();
-            let json: Value = from_str(&body).unwrap(); // Convert response text into JSON
-            keys = json["keys"].as_array().unwrap().clone();
+            keys = match store.list_prefix(dongle_id_filter).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    tracing::info!("Failed to get keys: {e}");
+                    return Ok(());
+                }
+            };
         } else {
             for prefix in hex_chars.iter() {
-                // Get all keys from the MKV server
-                let query = common::mkv_helpers::list_keys_starting_with(prefix);
-                //let query = common::mkv_helpers::list_keys_starting_with("");
-                let response = client.get(&query).send().await.unwrap();
-
-                if !response.status().is_success() {
-                    tracing::info!("Failed to get keys");
-                    return Ok(());
+                // Get all keys from storage
+                match store.list_prefix(prefix).await {
+                    Ok(prefix_keys) => keys.extend(prefix_keys),
+                    Err(e) => {
+                        tracing::info!("Failed to get keys: {e}");
+                        return Ok(());
+                    }
                 }
-
-                let body = response.text().await.unwrap();
-                let json: Value = from_str(&body).unwrap(); // Convert response text into JSON
-
-                // Extract keys from the JSON object
-                //keys = json["keys"].as_array().unwrap().clone(); // Safely extract as an array
-                keys.extend(json["keys"].as_array().unwrap().clone());
             }
         }
         let mut rng = StdRng::from_entropy();
@@ -87,9 +73,8 @@ impl Task for SeedFromMkv {
             r"^({DONGLE_ID})_({ROUTE_NAME})--({NUMBER})--({ALLOWED_FILENAME}$)"
         );
         let re = regex::Regex::new(&segment_file_regex_string).unwrap();
-        for key_value in keys {
+        for file_name in keys {
             progress_bar.inc(1);
-            let file_name = key_value.as_str().unwrap().trim_start_matches('/').to_string(); // Convert to string for independent ownership
     
             match re.captures(&file_name) {
                 Some(caps) => {
@@ -107,6 +92,7 @@ impl Task for SeedFromMkv {
                     }
 
                     if  file_type.to_string().ends_with(".unlog") || 
+                        file_type.to_string().ends_with(".jsonl") || 
                         file_type.to_string().ends_with("sprite.jpg") ||
                         file_type.to_string().ends_with("coords.json") ||
                         file_type.to_string().ends_with("events.json"){
@@ -114,32 +100,16 @@ impl Task for SeedFromMkv {
                         continue
                     
                     } else if (file_type.to_string().ends_with("qlog.bz2")) || (file_type.to_string().ends_with("qlog.zst")) {
-                        // delete the unlog file from mkv
-                        let unlog_file_name = file_name.replace(".bz2", ".unlog").replace(".zst", ".unlog");
-                        let internal_unlog_url = common::mkv_helpers::get_mkv_file_url(&unlog_file_name);
-                        tracing::trace!("Deleting: {internal_unlog_url}");
-                        let _response = client.delete(&internal_unlog_url).send().await.unwrap();
-                        // delete the sprite file from mkv
-                        let sprite_file_name = file_name.replace("qlog.bz2", "sprite.jpg").replace("qlog.zst", "sprite.jpg");
-                        let internal_sprite_url = common::mkv_helpers::get_mkv_file_url(&sprite_file_name);
-                        tracing::trace!("Deleting: {internal_sprite_url}");
-                        let _response = client.delete(&internal_sprite_url).send().await.unwrap();
-                        let coords_file_name = file_name.replace("qlog.bz2", "coords.json").replace("qlog.zst", "coords.json");
-                        let internal_coords_url = common::mkv_helpers::get_mkv_file_url(&coords_file_name);
-                        tracing::trace!("Deleting: {internal_coords_url}");
-                        let _response = client.delete(&internal_coords_url).send().await.unwrap();
-                        let events_file_name = file_name.replace("qlog.bz2", "events.json").replace("qlog.zst", "events.json");
-                        let internal_coords_url = common::mkv_helpers::get_mkv_file_url(&events_file_name);
-                        tracing::trace!("Deleting: {internal_coords_url}");
-                        let _response = client.delete(&internal_coords_url).send().await.unwrap();
+                        // delete the derived files so the worker can write them again
+                        if let Err(e) = delete_derived(&app_context.db, &file_name).await {
+                            tracing::error!("Failed to delete the files derived from {file_name}: {e}");
+                        }
                     }
-    
-                    let internal_url = common::mkv_helpers::get_mkv_file_url(&file_name);
-                    
+
                     let result = LogSegmentWorker::perform_later(
                         &app_context,
                         LogSegmentWorkerArgs {
-                            internal_file_url: internal_url,
+                            file_key: file_name.clone(),
                             dongle_id: dongle_id.to_string(),
                             timestamp: timestamp.to_string(),
                             segment: segment.to_string(),
@@ -164,12 +134,10 @@ impl Task for SeedFromMkv {
                         Some(caps) => {
                             let dongle_id = &caps[1];
                             let file = &caps[2];
-                            let internal_file_url = common::mkv_helpers::get_mkv_file_url(&file_name);
-                            let unlog_internal_file_url = internal_file_url.replace(".bz2", ".unlog");
-                            let _response = client.delete(&unlog_internal_file_url).send().await.unwrap();
+                            let _ = store.delete(&file_name.replace(".bz2", ".unlog")).await;
                             let _result = BootlogParserWorker::perform_later(&app_context, 
                                 BootlogParserWorkerArgs {
-                                    internal_file_url: internal_file_url.clone(),
+                                    file_key: file_name.clone(),
                                     dongle_id: dongle_id.into(),
                                     file_name: file.into(),
                                     create_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64,
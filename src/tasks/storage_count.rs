@@ -1,14 +1,12 @@
 use std::collections::BTreeMap;
-use reqwest::Client;
-use serde_json::{Value, from_str};
 use regex::Regex;
 use std::collections::HashMap;
 use loco_rs::prelude::*;
 use std::io::{self, Write};
 
-use crate::common::mkv_helpers;
+use crate::common::storage;
 use crate::common::re::*;
-use crate::models::devices;
+use crate::models::{devices, storage_objects::SOM};
 
 pub struct StorageCount;
 #[async_trait]
@@ -24,8 +22,12 @@ impl Task for StorageCount {
         let dongle_id_filter = vars
             .cli_arg("dongle_id")
             .ok();
+        // recompute:true rewrites the device totals and tracked objects from what is actually stored
+        let recompute = vars
+            .cli_arg("recompute")
+            .is_ok_and(|v| v == "true");
 
-        let client = Client::new();
+        let store = storage::blob_store();
         
         // Hex characters for prefix chunking
         let mut hex_chars: Vec<&str> = vec!["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", 
@@ -34,12 +36,12 @@ impl Task for StorageCount {
         let mut total_bytes: i128 = 0;
         let mut largest_files: Vec<(String, i128)> = Vec::new();
         let mut storage_by_dongle: HashMap<String, i128> = HashMap::new();
+        let mut objects_by_dongle: HashMap<String, Vec<(String, i64)>> = HashMap::new();
         let mut unmatched_files_total: i128 = 0;
+        // Files that couldn't be listed or sized, the totals leave them out
+        let mut incomplete = false;
 
-        let segment_file_regex_string = format!(
-            r"^({DONGLE_ID})_({ROUTE_NAME})--({NUMBER})--({ALLOWED_FILENAME}$)"
-        );
-        let segment_file_regex = Regex::new(&segment_file_regex_string).unwrap();
+        let device_file_regex = Regex::new(&format!(r"^({DONGLE_ID})_")).unwrap();
 
         if let Some(dongle_id_filter) = dongle_id_filter {
             // If a specific dongle ID is provided, filter the hex characters
@@ -48,69 +50,68 @@ impl Task for StorageCount {
 
         // Process each hex prefix sequentially
         for prefix in hex_chars.iter() {
-            let query = mkv_helpers::list_keys_starting_with(prefix);
-            let response = client.get(&query).send().await.unwrap();
-
-            if !response.status().is_success() {
-                tracing::info!("Failed to get keys for prefix {}", prefix);
-                continue;
-            }
-
-            let body = response.text().await.unwrap();
-            let json: Value = from_str(&body).unwrap();
-
-            if let Some(keys) = json["keys"].as_array() {
-                tracing::info!("Found {} keys for prefix {}", keys.len(), prefix);
-                let mut count = 0;
-                for key_value in keys {
-                    let file_name = key_value.as_str().unwrap().trim_start_matches('/').to_string();
-                    let file_url = mkv_helpers::get_mkv_file_url(&file_name);
-
-                    // Make HEAD request to get file size
-                    let head_response = client.head(&file_url).send().await.unwrap();
-
-                    if head_response.status().is_success() {
-                        if let Some(content_length) = head_response.headers().get(reqwest::header::CONTENT_LENGTH) {
-                            if let Ok(content_length_str) = content_length.to_str() {
-                                if let Ok(content_length_val) = content_length_str.parse::<i128>() {
-                                    total_bytes += content_length_val;
-
-                                    if let Some(captures) = segment_file_regex.captures(&file_name) {
-                                        let dongle_id = captures.get(1).map_or("", |m| m.as_str()).to_string();
-                                        storage_by_dongle.entry(dongle_id.clone())
-                                            .and_modify(|e| *e += content_length_val)
-                                            .or_insert(content_length_val);
-                                    } else {
-                                        unmatched_files_total += content_length_val;
-                                    }
-
-                                    // Maintain top 100 largest files
-                                    largest_files.push((file_name.clone(), content_length_val));
-                                    largest_files.sort_by(|a, b| b.1.cmp(&a.1));
-                                    if largest_files.len() > 100 {
-                                        largest_files.pop();
-                                    }
-                                }
-                            }
+            let keys = match store.list_prefix(prefix).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    tracing::error!("Failed to get keys for prefix {}: {}", prefix, e);
+                    incomplete = true;
+                    continue;
+                }
+            };
+
+            tracing::info!("Found {} keys for prefix {}", keys.len(), prefix);
+            let mut count = 0;
+            for file_name in keys {
+                // Parts of unfinished resumable uploads aren't counted until they are assembled
+                if file_name.contains("_upload_") {
+                    continue;
+                }
+                // Stat the object to get file size
+                match store.stat(&file_name).await {
+                    Ok(Some(size)) => {
+                        let content_length_val = size as i128;
+                        total_bytes += content_length_val;
+
+                        if let Some(captures) = device_file_regex.captures(&file_name) {
+                            let dongle_id = captures.get(1).map_or("", |m| m.as_str()).to_string();
+                            storage_by_dongle.entry(dongle_id.clone())
+                                .and_modify(|e| *e += content_length_val)
+                                .or_insert(content_length_val);
+                            objects_by_dongle.entry(dongle_id)
+                                .or_default()
+                                .push((file_name.clone(), size as i64));
+                        } else {
+                            unmatched_files_total += content_length_val;
                         }
-                    } else {
-                        tracing::info!("Failed to get file size for {}", file_name);
-                    }
 
-                    // Progress indicator
-                    count += 1;
-                    if count % 100 == 0 {
-                        print!(".");
-                        io::stdout().flush().unwrap();
+                        // Maintain top 100 largest files
+                        largest_files.push((file_name.clone(), content_length_val));
+                        largest_files.sort_by(|a, b| b.1.cmp(&a.1));
+                        if largest_files.len() > 100 {
+                            largest_files.pop();
+                        }
                     }
-                    if count % 1000 == 0 {
-                        println!(" {} files processed", count);
+                    // gone since it was listed
+                    Ok(None) => (),
+                    Err(e) => {
+                        tracing::error!("Failed to get file size for {}: {}", file_name, e);
+                        incomplete = true;
                     }
                 }
-                if count > 0 {
-                    println!(" Processed {} files for prefix {}", count, prefix);
+
+                // Progress indicator
+                count += 1;
+                if count % 100 == 0 {
+                    print!(".");
+                    io::stdout().flush().unwrap();
+                }
+                if count % 1000 == 0 {
+                    println!(" {} files processed", count);
                 }
             }
+            if count > 0 {
+                println!(" Processed {} files for prefix {}", count, prefix);
+            }
         }
 
         let mut report = String::new();
@@ -124,20 +125,31 @@ impl Task for StorageCount {
         report.push_str(&format!("Total storage used: {:.2} GB\n", total_gb));
         report.push_str("Storage used by each DONGLE_ID (in GB):\n");
 
-        // Update database with storage information
         for (dongle_id, storage) in &storage_by_dongle {
             let storage_gb = *storage as f64 / 1_000_000_000.0;
-            if let Ok(device_model) = devices::DM::find_device(&ctx.db, dongle_id).await {
-                let mut active_device_model = device_model.into_active_model();
-                active_device_model.server_storage = ActiveValue::Set(*storage as i64);
-                if let Err(e) = active_device_model.update(&ctx.db).await {
-                    tracing::error!("Failed to update {}: {}", dongle_id, e);
-                }
-            }
             println!("{}: {:.2} GB", dongle_id, storage_gb);
             report.push_str(&format!("{}: {:.2} GB\n", dongle_id, storage_gb));
         }
 
+        if recompute && incomplete {
+            // Zeroing what couldn't be seen would drop the storage of devices that still have files
+            return Err(Error::Message("Not all files could be listed, storage was not recomputed".to_string()));
+        }
+        if recompute {
+            // Devices with nothing left in storage still need their totals zeroed
+            let devices = match dongle_id_filter {
+                Some(dongle_id) => devices::DM::find_device(&ctx.db, dongle_id).await.into_iter().collect(),
+                None => devices::DM::find_all_devices(&ctx.db).await,
+            };
+            for device in devices {
+                let objects = objects_by_dongle.remove(&device.dongle_id).unwrap_or_default();
+                match SOM::replace_device_objects(&ctx.db, &device.dongle_id, &objects).await {
+                    Ok(()) => println!("Recomputed storage for {} from {} files", device.dongle_id, objects.len()),
+                    Err(e) => tracing::error!("Failed to recompute storage for {}: {}", device.dongle_id, e),
+                }
+            }
+        }
+
         println!("Unmatched files storage: {:.2} GB", unmatched_gb);
         report.push_str(&format!("Unmatched files storage: {:.2} GB\n", unmatched_gb));
 
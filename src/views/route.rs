@@ -1,7 +1,7 @@
 use loco_rs::prelude::*;
 
 use crate::controllers::{
-    useradmin::{CloudlogsTemplate, MasterTemplate, UlogText},
+    useradmin::{CloudlogsTemplate, JobsTemplate, MasterTemplate, UlogText},
     stats::ServerUsage};
 
 pub fn admin_route(v: impl ViewRenderer, template: MasterTemplate) -> Result<impl IntoResponse> {
@@ -14,6 +14,10 @@ pub fn admin_cloudlogs(v: impl ViewRenderer, template: CloudlogsTemplate) -> Res
     format::render().view(&v, "useradmin/cloudlog.html", template)
 }
 
+pub fn admin_jobs(v: impl ViewRenderer, template: JobsTemplate) -> Result<impl IntoResponse> {
+    format::render().view(&v, "useradmin/jobs.html", template)
+}
+
 pub fn admin_segment_ulog(v: impl ViewRenderer, data: UlogText) -> Result<impl IntoResponse> {
     // Render the view with the template
     format::render().view(&v, "useradmin/ulog.html", data)
@@ -1,15 +1,24 @@
-use capnp::message::ReaderOptions;
-use futures::TryStreamExt;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use loco_rs::prelude::*;
-use tokio::io::AsyncReadExt;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use std::time::Instant;
 use std::io::Write;
 use std::env;
 
 
-use crate::{cereal::log_capnp, common, models::_entities::{self}};
+use crate::{cereal::log_capnp, common::{log_json, log_reader::LogReader, storage}, models::{_entities::{self}, bootlogs::BootInfo}};
+use super::job_tracker::{track_job, JobOutcome, BOOTLOG_PARSER_JOB};
+use super::log_helpers::replace_object;
+
+/// Lines kept per field, the journal of a long uptime can be huge
+const MAX_BOOT_LINES: usize = 200;
+
+static MANAGER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\bmanager(?:_init|\.py|\s+start|\s+init)").unwrap());
+static PANDA_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\bpanda\b.*\b(?:version|fw|firmware|signature)\b").unwrap());
+static ERROR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\b(?:error|exception|traceback|fatal|failed|panic)\b").unwrap());
+static KERNEL_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"Linux version (\S+)").unwrap());
+static AGNOS_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\bAGNOS\s+v?(\d+(?:\.\d+)*)").unwrap());
 
 pub struct BootlogParserWorker {
     pub ctx: AppContext,
@@ -17,12 +26,15 @@ pub struct BootlogParserWorker {
 
 struct ParsedLog {
     data: Vec<u8>,
+    jsonl: Vec<u8>,
     date_time: String,
+    info: BootInfo,
 }
 
 #[derive(Deserialize, Debug, Serialize)]
 pub struct BootlogParserWorkerArgs {
-    pub internal_file_url: String,
+    #[serde(alias = "internal_file_url")]
+    pub file_key: String,
     pub dongle_id: String,
     pub file_name: String,
     pub create_time: i64, // time we got it
@@ -37,86 +49,110 @@ impl worker::AppWorker<BootlogParserWorkerArgs> for BootlogParserWorker {
 #[async_trait]
 impl worker::Worker<BootlogParserWorkerArgs> for BootlogParserWorker {
     async fn perform(&self, args: BootlogParserWorkerArgs) -> worker::Result<()> {
+        let file_key = storage::key_from_url(&args.file_key);
+        track_job(&self.ctx.db, BOOTLOG_PARSER_JOB, file_key, &args.dongle_id, &args, self.process(&args)).await?;
+        Ok(())
+    }
+}
+
+impl BootlogParserWorker {
+    async fn process(&self, args: &BootlogParserWorkerArgs) -> worker::Result<JobOutcome> {
         let start = Instant::now();
-        tracing::trace!("Starting BootlogParser for URL: {}", args.internal_file_url);
-        let client = Client::new();
+        tracing::trace!("Starting BootlogParser for key: {}", args.file_key);
+        let file_key = storage::key_from_url(&args.file_key);
         let api_endpoint = env::var("API_ENDPOINT").expect("API_ENDPOINT env variable not set");
         // Make sure we have the data in the key value store
-        let response = client.get(&args.internal_file_url)
-            .send().await
-            .map_err(Box::from)?;
-    
-        if !response.status().is_success() {
-            return Ok(())
-        }
+        let response = match storage::blob_store().get(file_key).await {
+            Ok(object) => object.stream,
+            Err(storage::StorageError::NotFound(key)) => return Ok(JobOutcome::Skipped(format!("{key} is not in storage"))),
+            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
+        };
         // check if the device is in the database
         let _device = match _entities::devices::Model::find_device(&self.ctx.db, &args.dongle_id).await {
             Ok(device) => device,
             Err(_e) => {
                 tracing::info!("Recieved file from an unregistered device. Do something: {}", &args.dongle_id);
-                return Ok(())
+                return Ok(JobOutcome::Skipped(format!("unregistered device {}", args.dongle_id)))
             }
         };
-        let bytes_stream = response.bytes_stream().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
-        let stream_reader = tokio_util::io::StreamReader::new(bytes_stream);
-        let mut bz2_decoder = async_compression::tokio::bufread::BzDecoder::new(stream_reader);
-        
-        let mut decompressed_data = Vec::new();
-        match bz2_decoder.read_to_end(&mut decompressed_data).await { 
-            Ok(_)=> (), 
-            Err(e) => return Err(sidekiq::Error::Message(e.to_string()))
+        // Newer openpilot writes zstd bootlogs, older ones bz2
+        let reader = match LogReader::sniff(response, file_key).await {
+            Ok(reader) => reader,
+            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
         };
-        let parsed_log = match parse_bootlog(decompressed_data).await {
+        let parsed_log = match parse_bootlog(reader).await {
             Ok(parsed_log) => parsed_log,
             Err(e) => return Err(sidekiq::Error::Message(e.to_string()))
         };
 
-        // add bootlog to db
-        match _entities::bootlogs::Model::add_bootlog(
+        // The bootlog row goes last, so a failed attempt never leaves one behind for a retry to add again
+        upload_data(&self.ctx.db, &file_key.replace(".bz2", ".jsonl").replace(".zst", ".jsonl"), parsed_log.jsonl).await?;
+        upload_data(&self.ctx.db, &file_key.replace(".bz2", ".unlog").replace(".zst", ".unlog"), parsed_log.data).await?;
+
+        _entities::bootlogs::Model::add_bootlog(
             &self.ctx.db,
             &args.dongle_id,
-            &format!("{api_endpoint}/connectdata/bootlog/{}", 
-                args.internal_file_url.split("/").last()
-                    .expect("Failed to access the last segment of the internal file URL")),
-            &format!("{api_endpoint}/connectdata/logs?url={}",
-                common::mkv_helpers::get_mkv_file_url(
-                    &format!("{}_{}",
-                        &args.dongle_id,
-                        &args.file_name.replace(".bz2", ".unlog").replace(".zst", ".unlog")
-                    )
-                )
+            &format!("{api_endpoint}/connectdata/bootlog/{}", file_key),
+            &format!("{api_endpoint}/connectdata/logs?url={}_{}",
+                &args.dongle_id,
+                &args.file_name.replace(".bz2", ".unlog").replace(".zst", ".unlog")
             ),
-            &parsed_log.date_time).await 
-        {
-            Ok(_) => {
-                tracing::info!("bootlog added to db");
-            }
-            Err(e) => {
-                tracing::error!("Failed to add bootlog to db {}", e);
-            }
-        }
-
-        match upload_data(&client, &args.internal_file_url.replace(".bz2", ".unlog").replace(".zst", ".unlog"), parsed_log.data).await {
-            Ok(()) => {tracing::info!("Completed unlogging: {} in {:?}", args.internal_file_url, start.elapsed()); return Ok(())},
-            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
-        };
-
+            &parsed_log.date_time,
+            parsed_log.info).await
+            .map_err(|e| sidekiq::Error::Message(format!("Failed to add bootlog to db: {e}")))?;
+        tracing::info!("Completed unlogging: {} in {:?}", file_key, start.elapsed());
+        Ok(JobOutcome::Done)
     }
 }
 
 
-async fn parse_bootlog(decompressed_data: Vec<u8>,) -> worker::Result<ParsedLog> {
+async fn parse_bootlog(mut reader: LogReader) -> worker::Result<ParsedLog> {
     let mut writer: Vec<u8> = Vec::new();
-    let mut cursor: std::io::Cursor<Vec<u8>> = std::io::Cursor::new(decompressed_data);
+    let mut jsonl: Vec<u8> = Vec::new();
     let mut date_string: String = "".to_string();
-    while let Ok(message_reader) = capnp::serialize::read_message(&mut cursor, ReaderOptions::default()) {
+    let mut info = BootInfo::default();
+    let mut messages = 0;
+    loop {
+        let message_reader = match reader.next_message().await {
+            Ok(Some(message_reader)) => message_reader,
+            Ok(None) => break,
+            // a bootlog cut short still has everything before the cut
+            Err(e) if messages > 0 => {
+                tracing::warn!("Bootlog ends early after {messages} messages: {e}");
+                break;
+            }
+            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
+        };
+        messages += 1;
         let event: log_capnp::event::Reader = message_reader.get_root::<log_capnp::event::Reader>().map_err(Box::from)?;
+        log_json::write_event(&mut jsonl, event).map_err(Box::from)?;
         //writeln!(writer, "{:#?}", event).map_err(Box::from)?;
         match event.which().map_err(Box::from)? {
+            log_capnp::event::InitData(init_data) => {
+                if let Ok(init_data) = init_data {
+                    info.kernel_version = init_data
+                        .get_kernel_version().ok()
+                        .map_or_else(String::new, |d| d.to_string().unwrap_or_default());
+                    info.agnos_version = init_data
+                        .get_os_version().ok()
+                        .map_or_else(String::new, |d| d.to_string().unwrap_or_default());
+                    info.openpilot_version = init_data
+                        .get_version().ok()
+                        .map_or_else(String::new, |d| d.to_string().unwrap_or_default());
+                    info.git_commit = init_data
+                        .get_git_commit().ok()
+                        .map_or_else(String::new, |d| d.to_string().unwrap_or_default());
+                }
+            }
             log_capnp::event::Boot(log) => {
                 let bootlog = log.unwrap();
+                info.boot_time = (bootlog.get_wall_time_nanos() / 1_000_000) as i64;
                 if bootlog.has_launch_log() {
                     writeln!(writer, "{:#?}", bootlog.get_launch_log().unwrap()).map_err(Box::from)?;
+                    let launch_log = bootlog.get_launch_log().ok()
+                        .map_or_else(String::new, |d| d.to_string().unwrap_or_default());
+                    scan_boot_text(&launch_log, &mut info);
+                    info.launch_log = launch_log;
                 }
                 if bootlog.has_pstore() {
                     writeln!(writer, "{:#?}", bootlog.get_pstore().unwrap()).map_err(Box::from)?;
@@ -138,23 +174,66 @@ async fn parse_bootlog(decompressed_data: Vec<u8>,) -> worker::Result<ParsedLog>
                     } else {
                         println!("No matches found.");
                     }
+                    // the output of each command, the journal among them
+                    if let Ok(entries) = data.get_entries() {
+                        for entry in entries.iter() {
+                            if let Ok(output) = entry.get_value() {
+                                scan_boot_text(&String::from_utf8_lossy(output), &mut info);
+                            }
+                        }
+                    }
                     writeln!(writer, "{:?}", data).map_err(Box::from)?;
                 }
             }
             _ => ()
         }
     }
-    Ok(ParsedLog { data: writer, date_time: date_string})
+    Ok(ParsedLog { data: writer, jsonl, date_time: date_string, info })
 }
 
-async fn upload_data(client: &Client, url: &String, body: Vec<u8>) -> worker::Result<()> {
-    let response = client.put(url)
-        .body(body)
-        .send().await
-        .map_err(Box::from)?;
+/// Pick the manager, panda and error lines out of boot output. The kernel and AGNOS versions
+/// come from initData, the output only fills them in when a log doesn't have one.
+fn scan_boot_text(text: &str, info: &mut BootInfo) {
+    if info.kernel_version.is_empty() {
+        if let Some(caps) = KERNEL_RE.captures(text) {
+            info.kernel_version = caps[1].to_string();
+        }
+    }
+    if info.agnos_version.is_empty() {
+        if let Some(caps) = AGNOS_RE.captures(text) {
+            info.agnos_version = caps[1].to_string();
+        }
+    }
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if MANAGER_RE.is_match(line) {
+            push_line(&mut info.manager_init, line);
+        }
+        if PANDA_RE.is_match(line) && !info.panda_versions.lines().any(|known| known == line) {
+            push_line(&mut info.panda_versions, line);
+        }
+        if ERROR_RE.is_match(line) {
+            info.error_count += 1;
+            push_line(&mut info.errors, line);
+        }
+    }
+}
+
+fn push_line(lines: &mut String, line: &str) {
+    if lines.lines().count() >= MAX_BOOT_LINES {
+        return;
+    }
+    if !lines.is_empty() {
+        lines.push('\n');
+    }
+    lines.push_str(line);
+}
 
-    if !response.status().is_success() {
-        tracing::info!("Response status: {}", response.status());
+/// Replaces what an earlier attempt of the job may have written.
+async fn upload_data(db: &DatabaseConnection, key: &str, body: Vec<u8>) -> worker::Result<()> {
+    let size = body.len() as u64;
+    let body: storage::ByteStream = Box::pin(futures::stream::once(async move { Ok(bytes::Bytes::from(body)) }));
+    if let Err(e) = replace_object(db, key, body, size).await {
+        tracing::info!("Failed to upload {}: {}", key, e);
         return Err(sidekiq::Error::Message("Failed to upload data".to_string()));
     }
 
@@ -0,0 +1,122 @@
+use std::{io::SeekFrom, time::Instant};
+
+use bytes::Bytes;
+use chrono::Utc;
+use futures::stream;
+use loco_rs::prelude::*;
+use sea_orm::sea_query::Expr;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use super::log_helpers::{download_to_file, replace_object};
+use super::log_parser::LogSegmentWorkerArgs;
+use crate::{
+    common::{
+        storage::{ByteStream, StorageError},
+        video::{first_fragment_offset, remux_to_mp4, Mp4Layout},
+    },
+    models::_entities::segments,
+};
+
+/// Remuxes the raw hevc of the full resolution cameras into fragmented mp4 for the HLS
+/// playlists. Each upload becomes two objects next to it: `{camera}_init.mp4` with the
+/// codec setup and `{camera}.m4s` with the fragments. Queued by the `LogSegmentWorker`
+/// once it has recorded the camera file.
+pub struct CameraRemuxWorker {
+    pub ctx: AppContext,
+}
+
+impl worker::AppWorker<LogSegmentWorkerArgs> for CameraRemuxWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+}
+
+#[async_trait]
+impl worker::Worker<LogSegmentWorkerArgs> for CameraRemuxWorker {
+    async fn perform(&self, args: LogSegmentWorkerArgs) -> worker::Result<()> {
+        let start_time = Instant::now();
+        let (camera, duration_column) = match args.file.as_str() {
+            "fcamera.hevc" => ("fcamera", segments::Column::FcamDuration),
+            "ecamera.hevc" => ("ecamera", segments::Column::EcamDuration),
+            "dcamera.hevc" => ("dcamera", segments::Column::DcamDuration),
+            f => {
+                tracing::error!("Can't remux {}", f);
+                return Ok(());
+            }
+        };
+        let key_prefix = format!("{}_{}--{}", args.dongle_id, args.timestamp, args.segment);
+        let source_key = format!("{key_prefix}--{}", args.file);
+
+        let temp_dir = tempfile::tempdir().map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        let input = temp_dir.path().join(&args.file);
+        match download_to_file(&source_key, &input).await {
+            Ok(()) => (),
+            Err(StorageError::NotFound(key)) => {
+                tracing::trace!("{key} is not in storage");
+                return Ok(());
+            }
+            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
+        }
+
+        let output = temp_dir.path().join(format!("{camera}.mp4"));
+        let remux_output = output.clone();
+        let duration_millis = tokio::task::spawn_blocking(move || {
+            remux_to_mp4(&[(input, 0)], 0, i64::MAX, &remux_output, Mp4Layout::Fragmented)
+        })
+        .await
+        .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        let duration_millis = match duration_millis {
+            Ok(duration_millis) => duration_millis,
+            Err(e) => {
+                // A truncated or corrupt upload won't remux on a retry either
+                tracing::error!("Failed to remux {}: {}", source_key, e);
+                return Ok(());
+            }
+        };
+
+        let split_output = output.clone();
+        let fragments_start = tokio::task::spawn_blocking(move || first_fragment_offset(&split_output))
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?
+            .ok_or_else(|| sidekiq::Error::Message(format!("No fragments in the remux of {source_key}")))?;
+        let size = tokio::fs::metadata(&output)
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?
+            .len();
+
+        let mut file = tokio::fs::File::open(&output)
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        let mut init = vec![0u8; fragments_start as usize];
+        file.read_exact(&mut init)
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        let init_key = format!("{key_prefix}--{camera}_init.mp4");
+        let init: ByteStream = Box::pin(stream::once(async move { Ok(Bytes::from(init)) }));
+        replace_object(&self.ctx.db, &init_key, init, fragments_start)
+            .await
+            .map_err(|e| sidekiq::Error::Message(format!("Failed to store {init_key}: {e}")))?;
+
+        file.seek(SeekFrom::Start(fragments_start))
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        let media_key = format!("{key_prefix}--{camera}.m4s");
+        replace_object(&self.ctx.db, &media_key, Box::pin(ReaderStream::new(file)), size - fragments_start)
+            .await
+            .map_err(|e| sidekiq::Error::Message(format!("Failed to store {media_key}: {e}")))?;
+
+        // The playlists leave a segment out until it has a duration, so this goes last
+        let canonical_name = format!("{}|{}--{}", args.dongle_id, args.timestamp, args.segment);
+        segments::Entity::update_many()
+            .col_expr(duration_column, Expr::value(duration_millis as f32 / 1000.0))
+            .col_expr(segments::Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(segments::Column::CanonicalName.eq(&canonical_name))
+            .exec(&self.ctx.db)
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        tracing::info!("Remuxed {} for HLS in {:?}", source_key, start_time.elapsed());
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::Path;
+
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    common::video::{remux_to_mp4, Mp4Layout},
+    models::{clips::CLM, segments::SM},
+};
+use super::log_helpers::{download_to_file, replace_object};
+
+/// Every camera file covers one minute of the route.
+const SEGMENT_MILLIS: i64 = 60_000;
+/// Share of the progress spent fetching the segments, the remux itself is quick.
+const DOWNLOAD_PROGRESS: f32 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipCamera {
+    Qcamera,
+    Fcamera,
+    Ecamera,
+    Dcamera,
+}
+
+impl ClipCamera {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "qcamera" => Some(ClipCamera::Qcamera),
+            "fcamera" => Some(ClipCamera::Fcamera),
+            "ecamera" => Some(ClipCamera::Ecamera),
+            "dcamera" => Some(ClipCamera::Dcamera),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClipCamera::Qcamera => "qcamera",
+            ClipCamera::Fcamera => "fcamera",
+            ClipCamera::Ecamera => "ecamera",
+            ClipCamera::Dcamera => "dcamera",
+        }
+    }
+
+    /// The file the device uploads for this camera
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ClipCamera::Qcamera => "qcamera.ts",
+            ClipCamera::Fcamera => "fcamera.hevc",
+            ClipCamera::Ecamera => "ecamera.hevc",
+            ClipCamera::Dcamera => "dcamera.hevc",
+        }
+    }
+
+    fn segment_url(self, segment: &SM) -> &str {
+        match self {
+            ClipCamera::Qcamera => &segment.qcam_url,
+            ClipCamera::Fcamera => &segment.fcam_url,
+            ClipCamera::Ecamera => &segment.ecam_url,
+            ClipCamera::Dcamera => &segment.dcam_url,
+        }
+    }
+}
+
+/// Renders a clip of one camera of a route into a single mp4 in storage. The camera files of
+/// the segments the clip touches are fetched, then their packets are copied into the mp4
+/// without re-encoding.
+pub struct ClipRendererWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct ClipRendererWorkerArgs {
+    pub clip_id: i32,
+}
+
+impl worker::AppWorker<ClipRendererWorkerArgs> for ClipRendererWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+}
+
+#[async_trait]
+impl worker::Worker<ClipRendererWorkerArgs> for ClipRendererWorker {
+    async fn perform(&self, args: ClipRendererWorkerArgs) -> worker::Result<()> {
+        let clip = CLM::find_clip(&self.ctx.db, args.clip_id)
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        tracing::info!("Rendering clip {} of {}", clip.id, clip.route_fullname);
+
+        // A clip that can't be rendered won't render on a retry either, so it is marked
+        // failed rather than handed back to the queue
+        if let Err(e) = render_clip(&self.ctx.db, &clip).await {
+            tracing::error!("Failed to render clip {}: {}", clip.id, e);
+            CLM::set_failed(&self.ctx.db, clip.id, &e)
+                .await
+                .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+async fn render_clip(db: &DatabaseConnection, clip: &CLM) -> Result<(), String> {
+    let camera = ClipCamera::from_name(&clip.camera).ok_or(format!("unknown camera {}", clip.camera))?;
+    if clip.end_millis <= clip.start_millis {
+        return Err("clip ends before it starts".to_string());
+    }
+    let first_number = clip.start_millis / SEGMENT_MILLIS;
+    let last_number = (clip.end_millis - 1) / SEGMENT_MILLIS;
+    let mut segments = SM::find_segments_by_route(db, &clip.route_fullname)
+        .await
+        .map_err(|e| e.to_string())?;
+    segments.retain(|segment| {
+        (first_number..=last_number).contains(&(segment.number as i64)) && !camera.segment_url(segment).is_empty()
+    });
+    if segments.is_empty() {
+        return Err(format!("no {} files in that part of the route", camera.name()));
+    }
+    CLM::set_progress(db, clip.id, 0.0).await.map_err(|e| e.to_string())?;
+
+    let temp_dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let mut inputs = Vec::new();
+    for (index, segment) in segments.iter().enumerate() {
+        let key = format!("{}--{}--{}", clip.route_fullname.replace('|', "_"), segment.number, camera.file_name());
+        let path = temp_dir.path().join(&key);
+        download_to_file(&key, &path).await.map_err(|e| format!("failed to fetch {key}: {e}"))?;
+        inputs.push((path, segment.number as i64 * SEGMENT_MILLIS));
+        let progress = DOWNLOAD_PROGRESS * (index + 1) as f32 / segments.len() as f32;
+        CLM::set_progress(db, clip.id, progress).await.map_err(|e| e.to_string())?;
+    }
+
+    let output = temp_dir.path().join(format!("clip-{}.mp4", clip.id));
+    let (start_millis, end_millis) = (clip.start_millis, clip.end_millis);
+    let remux_output = output.clone();
+    tokio::task::spawn_blocking(move || remux_to_mp4(&inputs, start_millis, end_millis, &remux_output, Mp4Layout::Progressive))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("ffmpeg: {e}"))?;
+    CLM::set_progress(db, clip.id, 0.9).await.map_err(|e| e.to_string())?;
+
+    // Under the route's prefix so deleting the route deletes its clips too
+    let key = format!("{}--clip-{}.mp4", clip.route_fullname.replace('|', "_"), clip.id);
+    let size = tokio::fs::metadata(&output).await.map_err(|e| e.to_string())?.len();
+    let file = tokio::fs::File::open(&output).await.map_err(|e| e.to_string())?;
+    replace_object(db, &key, Box::pin(ReaderStream::new(file)), size)
+        .await
+        .map_err(|e| format!("failed to store {key}: {e}"))?;
+    CLM::set_done(db, clip.id, &key, size as i64).await.map_err(|e| e.to_string())?;
+    tracing::info!("Rendered clip {} to {} ({} bytes)", clip.id, key, size);
+    Ok(())
+}
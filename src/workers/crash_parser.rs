@@ -0,0 +1,310 @@
+use async_compression::tokio::bufread;
+use loco_rs::prelude::*;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sea_orm::{QueryOrder, QuerySelect};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    common::storage,
+    models::{
+        _entities::routes,
+        crash_groups::CGM,
+        crashes::CM,
+    },
+};
+
+pub struct CrashParserWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct CrashParserWorkerArgs {
+    pub file_key: String,
+    pub dongle_id: String,
+    pub log_id: String,
+    pub commit: String,
+    pub name: String,
+    pub create_time: i64, // time we got it
+}
+
+impl worker::AppWorker<CrashParserWorkerArgs> for CrashParserWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub file: String,
+    pub line: Option<i32>,
+    pub function: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedCrash {
+    pub exception_type: String,
+    pub message: String,
+    pub stack_trace: String,
+    /// Outermost first, like a python traceback.
+    pub frames: Vec<Frame>,
+}
+
+impl ParsedCrash {
+    pub fn innermost_frame(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    /// Stable id for "the same crash": exception type plus the normalized frames, without
+    /// line numbers or the message since those change between commits and runs. Without
+    /// frames there's nothing else to go on, so the message (the first line) is used.
+    pub fn signature(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.exception_type.as_bytes());
+        if self.frames.is_empty() {
+            hasher.update(b"\n");
+            hasher.update(self.message.as_bytes());
+        }
+        for frame in &self.frames {
+            hasher.update(b"\n");
+            hasher.update(frame.file.as_bytes());
+            hasher.update(b":");
+            hasher.update(frame.function.as_bytes());
+        }
+        hex::encode(hasher.finalize())[..16].to_string()
+    }
+}
+
+// File "/data/openpilot/selfdrive/controls/controlsd.py", line 123, in step
+static PY_FRAME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^\s*File "([^"]+)", line (\d+), in (.+?)\s*$"#).unwrap()
+});
+// #0  0x0000007f8c1d2f34 in Foo::bar (this=0x0) at selfdrive/ui/ui.cc:42
+static NATIVE_FRAME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*#\d+\s+(?:0x[0-9a-fA-F]+ in )?([^\s(]+).*?(?: at ([^\s:]+):(\d+))?\s*$").unwrap()
+});
+static EXCEPTION_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^([A-Za-z_][\w.]*)(?::\s?(.*))?$").unwrap()
+});
+
+/// Strip the install location so the same file hashes the same on every device.
+pub fn normalize_path(path: &str) -> String {
+    for marker in ["/openpilot/", "/site-packages/", "/dist-packages/"] {
+        if let Some(index) = path.rfind(marker) {
+            return path[index + marker.len()..].to_string();
+        }
+    }
+    path.trim_start_matches("./").to_string()
+}
+
+/// Pull the exception and frames out of a crash file. Python tracebacks are the common case;
+/// gdb style native backtraces are handled as well. Anything else becomes an `UnknownCrash`
+/// keyed on its first line.
+pub fn parse_crash(text: &str) -> ParsedCrash {
+    let lines: Vec<&str> = text.lines().collect();
+
+    // Chained exceptions print several tracebacks, the last one is what actually killed the process
+    let traceback_start = lines
+        .iter()
+        .rposition(|line| line.starts_with("Traceback (most recent call last)"));
+    if let Some(start) = traceback_start {
+        let mut frames = vec![];
+        let mut exception = None;
+        for line in &lines[start + 1..] {
+            if let Some(caps) = PY_FRAME.captures(line) {
+                frames.push(Frame {
+                    file: normalize_path(&caps[1]),
+                    line: caps[2].parse().ok(),
+                    function: caps[3].to_string(),
+                });
+            } else if !line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+                exception = Some(*line);
+                break;
+            }
+        }
+        let (exception_type, message) = split_exception(exception.unwrap_or("UnknownException"));
+        return ParsedCrash {
+            exception_type,
+            message,
+            stack_trace: lines[start..].join("\n"),
+            frames,
+        };
+    }
+
+    let native_frames: Vec<Frame> = lines
+        .iter()
+        .filter_map(|line| NATIVE_FRAME.captures(line))
+        .map(|caps| Frame {
+            file: caps.get(2).map(|m| normalize_path(m.as_str())).unwrap_or_default(),
+            line: caps.get(3).and_then(|m| m.as_str().parse().ok()),
+            function: caps[1].to_string(),
+        })
+        .collect();
+    let first_line = lines
+        .iter()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or("");
+    if !native_frames.is_empty() {
+        // gdb lists the innermost frame first
+        let frames = native_frames.into_iter().rev().collect();
+        let exception_type = lines
+            .iter()
+            .find_map(|line| line.split("received signal ").nth(1))
+            .and_then(|signal| signal.split([',', ' ', '.']).next())
+            .unwrap_or("NativeCrash")
+            .to_string();
+        return ParsedCrash {
+            exception_type,
+            message: first_line.to_string(),
+            stack_trace: text.to_string(),
+            frames,
+        };
+    }
+
+    ParsedCrash {
+        exception_type: "UnknownCrash".to_string(),
+        message: first_line.to_string(),
+        stack_trace: text.to_string(),
+        frames: vec![],
+    }
+}
+
+fn split_exception(line: &str) -> (String, String) {
+    match EXCEPTION_LINE.captures(line.trim()) {
+        Some(caps) => (
+            caps[1].to_string(),
+            caps.get(2).map(|m| m.as_str().to_string()).unwrap_or_default(),
+        ),
+        None => ("UnknownException".to_string(), line.trim().to_string()),
+    }
+}
+
+/// Crash files may come in compressed like the logs do.
+async fn decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    if data.starts_with(b"BZh") {
+        bufread::BzDecoder::new(data).read_to_end(&mut decompressed).await?;
+    } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        bufread::ZstdDecoder::new(data).read_to_end(&mut decompressed).await?;
+    } else {
+        decompressed.extend_from_slice(data);
+    }
+    Ok(decompressed)
+}
+
+/// The crash path only carries the commit, so borrow the branch from a route the device
+/// drove on that commit.
+async fn find_branch(db: &DatabaseConnection, dongle_id: &str, commit: &str) -> Option<String> {
+    routes::Entity::find()
+        .filter(routes::Column::DeviceDongleId.eq(dongle_id))
+        .filter(routes::Column::GitCommit.starts_with(commit))
+        .filter(routes::Column::GitBranch.is_not_null())
+        .order_by_desc(routes::Column::CreateTime)
+        .limit(1)
+        .one(db)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|route| route.git_branch)
+}
+
+#[async_trait]
+impl worker::Worker<CrashParserWorkerArgs> for CrashParserWorker {
+    async fn perform(&self, args: CrashParserWorkerArgs) -> worker::Result<()> {
+        tracing::trace!("Starting CrashParserWorker for {}", args.file_key);
+        let data = match storage::blob_store().get_bytes(&args.file_key).await {
+            Ok(data) => data,
+            Err(e) => return Err(sidekiq::Error::Message(format!("Failed to get {}: {}", args.file_key, e))),
+        };
+        let data = match decompress(&data).await {
+            Ok(data) => data,
+            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
+        };
+        let crash = parse_crash(&String::from_utf8_lossy(&data));
+        let signature = crash.signature();
+        let frame = crash.innermost_frame().cloned();
+        tracing::info!("Crash {} from {} is {} ({signature})", args.log_id, args.dongle_id, crash.exception_type);
+
+        let group = match CGM::record(
+            &self.ctx.db,
+            &signature,
+            &crash.exception_type,
+            frame.as_ref().map(|f| f.file.clone()),
+            frame.as_ref().and_then(|f| f.line),
+            frame.as_ref().map(|f| f.function.clone()),
+        ).await {
+            Ok(group) => group,
+            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
+        };
+
+        let branch = find_branch(&self.ctx.db, &args.dongle_id, &args.commit).await;
+        let crash = CM {
+            dongle_id: args.dongle_id,
+            crash_group_id: group.id,
+            file_key: args.file_key,
+            log_id: args.log_id,
+            commit: args.commit,
+            branch,
+            name: args.name,
+            exception_type: crash.exception_type,
+            message: crash.message,
+            stack_trace: crash.stack_trace,
+            file: frame.as_ref().map(|f| f.file.clone()),
+            line: frame.as_ref().and_then(|f| f.line),
+            function: frame.map(|f| f.function),
+            ..Default::default()
+        };
+        if let Err(e) = CM::add_crash(&self.ctx.db, crash).await {
+            return Err(sidekiq::Error::Message(e.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEBACK: &str = r#"Traceback (most recent call last):
+  File "/data/openpilot/selfdrive/controls/controlsd.py", line 900, in main
+    controls.controlsd_thread()
+  File "/data/openpilot/selfdrive/controls/controlsd.py", line 412, in step
+    self.update_events(CS)
+KeyError: 'steeringPressed'
+"#;
+
+    #[test]
+    fn parses_python_traceback() {
+        let crash = parse_crash(TRACEBACK);
+        assert_eq!(crash.exception_type, "KeyError");
+        assert_eq!(crash.message, "'steeringPressed'");
+        let frame = crash.innermost_frame().unwrap();
+        assert_eq!(frame.file, "selfdrive/controls/controlsd.py");
+        assert_eq!(frame.line, Some(412));
+        assert_eq!(frame.function, "step");
+    }
+
+    #[test]
+    fn signature_ignores_line_numbers_and_install_path() {
+        let moved = TRACEBACK
+            .replace("/data/openpilot/", "/data/fork/openpilot/")
+            .replace("line 412", "line 420")
+            .replace("'steeringPressed'", "'gasPressed'");
+        assert_eq!(parse_crash(TRACEBACK).signature(), parse_crash(&moved).signature());
+    }
+
+    #[test]
+    fn unknown_crashes_group_by_first_line() {
+        let out_of_memory = parse_crash("Out of memory: killed process 1234 (modeld)\nmore");
+        let disk_full = parse_crash("No space left on device\nmore");
+        assert_eq!(out_of_memory.exception_type, "UnknownCrash");
+        assert_ne!(out_of_memory.signature(), disk_full.signature());
+        assert_eq!(
+            out_of_memory.signature(),
+            parse_crash("Out of memory: killed process 1234 (modeld)\nother").signature()
+        );
+    }
+}
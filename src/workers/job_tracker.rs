@@ -0,0 +1,109 @@
+use std::{future::Future, panic::AssertUnwindSafe, time::Instant};
+
+use futures::FutureExt;
+use loco_rs::prelude::*;
+use serde::Serialize;
+
+use super::bootlog_parser::{BootlogParserWorker, BootlogParserWorkerArgs};
+use super::log_parser::{LogSegmentWorker, LogSegmentWorkerArgs};
+use super::reprocess::delete_derived;
+use crate::{
+    common::storage::{self, StorageError},
+    models::{
+        storage_objects::SOM,
+        worker_jobs::{WJM, STATUS_DONE, STATUS_FAILED, STATUS_SKIPPED},
+    },
+};
+
+/// Names the tracked workers are recorded under
+pub const LOG_SEGMENT_JOB: &str = "log_segment";
+pub const BOOTLOG_PARSER_JOB: &str = "bootlog_parser";
+
+/// How a tracked job ended when it didn't fail.
+#[derive(Debug)]
+pub enum JobOutcome {
+    Done,
+    /// Nothing to do, with the reason
+    Skipped(String),
+}
+
+/// Runs `job` and keeps a record of it in `worker_jobs`, one per worker and file. A failure
+/// or panic is recorded with its error and still handed back to the queue. Problems with the
+/// record itself are only logged, they never fail the job.
+pub async fn track_job<A, F>(
+    db: &DatabaseConnection,
+    worker: &str,
+    file_key: &str,
+    dongle_id: &str,
+    args: &A,
+    job: F,
+) -> worker::Result<JobOutcome>
+where
+    A: Serialize,
+    F: Future<Output = worker::Result<JobOutcome>>,
+{
+    let start = Instant::now();
+    let args = serde_json::to_string(args).unwrap_or_default();
+    let record = match WJM::start(db, worker, file_key, dongle_id, &args).await {
+        Ok(record) => Some(record),
+        Err(e) => {
+            tracing::error!("Failed to record the {worker} job for {file_key}: {e}");
+            None
+        }
+    };
+
+    let result = match AssertUnwindSafe(job).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(sidekiq::Error::Message(format!("panicked: {message}")))
+        }
+    };
+
+    if let Some(record) = record {
+        let duration_millis = start.elapsed().as_millis() as i64;
+        let (status, error) = match &result {
+            Ok(JobOutcome::Done) => (STATUS_DONE, None),
+            Ok(JobOutcome::Skipped(reason)) => (STATUS_SKIPPED, Some(reason.clone())),
+            Err(e) => (STATUS_FAILED, Some(e.to_string())),
+        };
+        if let Err(e) = WJM::finish(db, record.id, status, duration_millis, error.as_deref()).await {
+            tracing::error!("Failed to record the end of the {worker} job for {file_key}: {e}");
+        }
+    }
+    result
+}
+
+/// Queue a recorded job again from its stored arguments. What the failed attempt managed to
+/// write is deleted first, since storage keys can't be overwritten.
+pub async fn retry_job(ctx: &AppContext, job: &WJM) -> Result<(), String> {
+    match job.worker.as_str() {
+        LOG_SEGMENT_JOB => {
+            let args: LogSegmentWorkerArgs = serde_json::from_str(&job.args).map_err(|e| e.to_string())?;
+            delete_derived(&ctx.db, &args.file_key).await.map_err(|e| e.to_string())?;
+            WJM::set_queued(&ctx.db, job.id).await.map_err(|e| e.to_string())?;
+            LogSegmentWorker::perform_later(ctx, args).await.map_err(|e| e.to_string())
+        }
+        BOOTLOG_PARSER_JOB => {
+            let args: BootlogParserWorkerArgs = serde_json::from_str(&job.args).map_err(|e| e.to_string())?;
+            let file_key = storage::key_from_url(&args.file_key);
+            for extension in [".unlog", ".jsonl"] {
+                let key = file_key.replace(".bz2", extension).replace(".zst", extension);
+                match storage::blob_store().delete(&key).await {
+                    Ok(()) => {
+                        SOM::untrack(&ctx.db, &key).await.ok();
+                    }
+                    Err(StorageError::NotFound(_)) => {}
+                    Err(e) => return Err(e.to_string()),
+                }
+            }
+            WJM::set_queued(&ctx.db, job.id).await.map_err(|e| e.to_string())?;
+            BootlogParserWorker::perform_later(ctx, args).await.map_err(|e| e.to_string())
+        }
+        worker => Err(format!("{worker} jobs can't be retried")),
+    }
+}
@@ -1,13 +1,18 @@
 use std::f64::consts::PI;
-use std::hash::Hasher;
-use std::hash::Hash;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use bytes::Bytes;
 use std::path::Path;
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
 use tracing;
+use futures::StreamExt;
+use sea_orm::DatabaseConnection;
+use tokio::io::AsyncWriteExt;
+
+use crate::common::storage::{self, ByteStream, StorageError};
+use crate::models::storage_objects::SOM;
 
 #[derive(Serialize, Deserialize)]
 pub struct ValueCounts(pub HashMap<String, u64>);
@@ -29,11 +34,11 @@ pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     r * c // Distance in meters
 }
 
+/// Every worker process has to agree on the key, so this can't use the std hasher which
+/// is free to change between builds.
 pub fn calculate_advisory_lock_key(canonical_name: &str) -> u32 {
-    let mut hasher = std::collections::hash_map::DefaultHasher::new();
-    canonical_name.hash(&mut hasher);
-    // Get the lower 32 bits of the hash
-    hasher.finish() as u32
+    let digest = Sha256::digest(canonical_name.as_bytes());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
 }
 
 pub fn increment_param_value(param_name: &str, value: &str) {
@@ -142,4 +147,38 @@ pub async fn persist_device_params(
     }
     try_join_all(tasks).await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Copy a stored object into a local file, for the tools that need a seekable input.
+pub async fn download_to_file(key: &str, path: &Path) -> Result<(), StorageError> {
+    let mut stream = storage::blob_store().get(key).await?.stream;
+    let mut file = tokio::fs::File::create(path).await?;
+    while let Some(chunk) = stream.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Store a derived object in place of any earlier version of it. Keys are write-once, so
+/// the old one is deleted first.
+pub async fn replace_object(
+    db: &DatabaseConnection,
+    key: &str,
+    body: ByteStream,
+    size: u64,
+) -> Result<(), StorageError> {
+    let store = storage::blob_store();
+    match store.delete(key).await {
+        Ok(()) => {
+            SOM::untrack(db, key).await.ok();
+        }
+        Err(StorageError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    store.put(key, body, Some(size)).await?;
+    if let Err(e) = SOM::track(db, key, size as i64).await {
+        tracing::error!("Failed to track storage for {}: {}", key, e);
+    }
+    Ok(())
+}
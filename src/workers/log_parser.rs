@@ -1,44 +1,45 @@
 use std::{
     env,
     time::Instant,
-    sync::Arc,
-    collections::HashMap,
-    io::{Write, Cursor}
+    sync::{Arc, Mutex},
+    collections::HashSet,
+    io::Write
 };
-use tokio_util::io::StreamReader;
 use tokio::{
-    io::{AsyncWriteExt, AsyncReadExt},
-    sync::{Mutex, Notify},
+    io::AsyncWriteExt,
+    sync::Notify,
 };
-use reqwest::{Client, Response};
 use rayon::prelude::*;
 use ffmpeg_next::{format as ffmpeg_format, Error as FfmpegError};
 use tempfile::NamedTempFile;
-use async_compression::tokio::{bufread, write::BzEncoder};
+use async_compression::tokio::write::BzEncoder;
 use futures_util::StreamExt;
 use futures::stream::TryStreamExt; // for stream::TryStreamExt to use try_next
 use once_cell::sync::Lazy;
 use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageBuffer, Rgba};
 use serde::{Deserialize, Serialize};
 use loco_rs::prelude::*;
-use capnp::{
-    message::ReaderOptions,
-    serialize::{read_message, write_message}
-};
+use capnp::serialize::{read_message, write_message};
 
 use crate::cereal::{legacy_capnp::nav_update::segment, log_capnp};
-use crate::common;
+use crate::common::{geocode, log_json, log_reader::LogReader, storage::{self, BlobWriter, ByteStream}};
 use crate::cereal::log_capnp::event as LogEvent;
 use crate::models::_entities::{devices, routes, segments};
+use crate::models::storage_objects::SOM;
+
+/// Stored on every segment whose qlog has been parsed. Bump it when a change to `parse_log`
+/// should be applied to segments that were already parsed, the `ReprocessWorker` then
+/// picks up everything below it.
+pub const PARSER_VERSION: i32 = 1;
 
 pub struct LogSegmentWorker {
     pub ctx: AppContext,
     pub lock_manager: Arc<LockManager>,
-    pub client: Arc<Client>,
 }
 #[derive(Deserialize, Debug, Serialize)]
 pub struct LogSegmentWorkerArgs {
-    pub internal_file_url: String,
+    #[serde(alias = "internal_file_url")]
+    pub file_key         : String,
     pub dongle_id        : String,
     pub timestamp        : String,
     pub segment          : String,
@@ -46,7 +47,7 @@ pub struct LogSegmentWorkerArgs {
     pub create_time      : i64, // This is the time the call was made to the worker.
 }
 
-struct QLogResult {
+pub(crate) struct QLogResult {
     car_fingerprint: String,
     git_branch: String,
     git_remote: String,
@@ -58,61 +59,123 @@ struct QLogResult {
     total_time: i64,
 }
 
-use sea_orm::{DatabaseConnection, DbErr};
+use sea_orm::{ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbBackend, DbErr, Statement, TransactionTrait};
 use async_trait::async_trait;
 
 use super::log_helpers::{increment_param_value, save_device_param};
+use super::reprocess::delete_derived;
+use super::rlog_parser::RlogParserWorker;
+use super::camera_remuxer::CameraRemuxWorker;
+use super::job_tracker::{track_job, JobOutcome, LOG_SEGMENT_JOB};
+
+/// Where the route locks live. Postgres advisory locks are seen by every worker process using
+/// the same database, the in-process set only by this process which is enough for SQLite or a
+/// single node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockBackend {
+    Postgres,
+    InProcess,
+}
+
+impl LockBackend {
+    /// `WORKER_LOCK_BACKEND` can be `postgres`, `local` or `auto` (the default), which uses
+    /// postgres whenever the database is postgres.
+    pub fn from_env(db: &DatabaseConnection) -> Self {
+        match env::var("WORKER_LOCK_BACKEND").unwrap_or_default().to_lowercase().as_str() {
+            "postgres" => LockBackend::Postgres,
+            "local" => LockBackend::InProcess,
+            _ if db.get_database_backend() == DbBackend::Postgres => LockBackend::Postgres,
+            _ => LockBackend::InProcess,
+        }
+    }
+}
 
 pub struct LockManager {
-    keys: Mutex<HashMap<u32, bool>>,
+    keys: Mutex<HashSet<u32>>,
     notify: Notify,
 }
 
 impl LockManager {
     fn new() -> Self {
         LockManager {
-            keys: Mutex::new(HashMap::new()),
+            keys: Mutex::new(HashSet::new()),
             notify: Notify::new(),
         }
     }
 
-    pub async fn acquire_advisory_lock(&self, _db: &DatabaseConnection, key: u32) -> Result<(), DbErr> {
-        //let start_time = Instant::now();
+    /// Blocks until `key` is free. The lock is held until the returned guard is released or dropped.
+    ///
+    /// The guard owns a transaction, and everything done under the lock has to go through
+    /// `AdvisoryLock::txn`. A second pooled connection taken while holding it could wait on the
+    /// pool forever when the pool is small. Jobs of this process wait for the key in memory
+    /// first, so at most one connection per key sits waiting on the postgres lock.
+    pub async fn acquire_advisory_lock(self: &Arc<Self>, db: &DatabaseConnection, key: u32) -> Result<AdvisoryLock, DbErr> {
+        loop {
+            // Register for the wakeup before checking so an unlock in between isn't missed
+            let notified = self.notify.notified();
+            if self.keys.lock().unwrap().insert(key) {
+                break;
+            }
+            notified.await;
+        }
+        // From here on dropping the guard frees the key, also when a statement below fails
+        let mut lock = AdvisoryLock { key, manager: Arc::clone(self), txn: None };
+        let txn = db.begin().await?;
+        if LockBackend::from_env(db) == LockBackend::Postgres {
+            // Session locks would have to be unlocked on the same pooled connection that took
+            // them, a transaction scoped lock is released by whichever way the transaction ends.
+            txn.execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT pg_advisory_xact_lock($1)",
+                [i64::from(key).into()],
+            )).await?;
+            tracing::trace!("Acquired postgres advisory lock with key: {}", key);
+        }
+        lock.txn = Some(txn);
+        Ok(lock)
+    }
 
-        let mut keys = self.keys.lock().await;
-        while keys.contains_key(&key) {
-            drop(keys);
-            //let wait_start = Instant::now();
-            self.notify.notified().await;
-            keys = self.keys.lock().await;
+    fn unlock(&self, key: u32) {
+        if self.keys.lock().unwrap().remove(&key) {
+            self.notify.notify_waiters();
         }
+    }
+}
 
-        // Insert the key to indicate it is locked
-        keys.insert(key, true);
-        // TODO: Fix the global locking
-        // This is the global lock (literally for servers around the globe accessing the same db)
-        // tracing::trace!("Attempting to acquire advisory lock with key: {}", key);
-        // let sql = format!("SELECT pg_advisory_lock({})", key);
-        // db.execute(Statement::from_string(db.get_database_backend(), sql)).await?;
-        // tracing::trace!("Successfully acquired advisory lock with key: {}", key);
+/// A held route lock. Dropping it releases the lock and rolls back its transaction so an early
+/// return can't leave the key locked, `release` commits instead and reports errors.
+pub struct AdvisoryLock {
+    key: u32,
+    manager: Arc<LockManager>,
+    txn: Option<DatabaseTransaction>,
+}
 
+impl AdvisoryLock {
+    /// The connection to do the locked reads and writes on.
+    pub fn txn(&self) -> &DatabaseTransaction {
+        self.txn.as_ref().expect("advisory lock without a transaction")
+    }
 
+    pub async fn release(mut self) -> Result<(), DbErr> {
+        if let Some(txn) = self.txn.take() {
+            txn.commit().await?;
+            tracing::trace!("Released advisory lock with key: {}", self.key);
+        }
         Ok(())
     }
+}
 
-    pub async fn release_advisory_lock(&self, _db: &DatabaseConnection, key: u32) -> Result<(), DbErr> {
-        let mut keys = self.keys.lock().await;
-        
-        if keys.remove(&key).is_some() {
-            self.notify.notify_waiters();
+impl Drop for AdvisoryLock {
+    fn drop(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            // Roll back explicitly rather than leaving it to the pool so the lock is freed right away
+            tokio::spawn(async move {
+                if let Err(e) = txn.rollback().await {
+                    tracing::error!("Failed to release advisory lock: {}", e);
+                }
+            });
         }
-
-        // tracing::trace!("Releasing advisory lock with key: {}", key);
-        // let sql = format!("SELECT pg_advisory_unlock({})", key);
-        // db.execute(Statement::from_string(db.get_database_backend(), sql)).await?;
-        // tracing::trace!("Successfully released advisory lock with key: {}", key);
-
-        Ok(())
+        self.manager.unlock(self.key);
     }
 }
 
@@ -120,115 +183,86 @@ impl LockManager {
 impl worker::AppWorker<LogSegmentWorkerArgs> for LogSegmentWorker {
     fn build(ctx: &AppContext) -> Self {
         static LOCK_MANAGER: Lazy<Arc<LockManager>> = Lazy::new(|| Arc::new(LockManager::new()));
-        //pub static CLIENT: Lazy<Arc<Client>> = Lazy::new(|| Arc::new(Client::new()));
-        pub static CLIENT: Lazy<Arc<Client>> = Lazy::new(|| {
-            Arc::new(
-                Client::builder()
-                    .tcp_nodelay(true)
-                    .connect_timeout(std::time::Duration::from_secs(1))
-                    .timeout(std::time::Duration::from_secs(1)) // Response timeout
-                    .pool_idle_timeout(Some(std::time::Duration::from_secs(10)))
-                    .build()
-                    .expect("Failed to create HTTP client")
-            )
-        });
-        Self { ctx: ctx.clone(), lock_manager: Arc::clone(&LOCK_MANAGER) , client: Arc::clone(&CLIENT)}
+        Self { ctx: ctx.clone(), lock_manager: Arc::clone(&LOCK_MANAGER) }
     }
 }
 
 #[async_trait]
 impl worker::Worker<LogSegmentWorkerArgs> for LogSegmentWorker {
     async fn perform(&self, args: LogSegmentWorkerArgs) -> worker::Result<()> {
-        let lock_manager = self.lock_manager.clone();
+        let outcome = track_job(&self.ctx.db, LOG_SEGMENT_JOB, &args.file_key, &args.dongle_id, &args, self.process(&args)).await?;
+        if !matches!(outcome, JobOutcome::Done) {
+            return Ok(());
+        }
+        if args.file.starts_with("rlog.") {
+            // Parsing a full rlog takes a while, so it gets its own job instead of holding up the qlogs
+            if let Err(e) = RlogParserWorker::perform_later(&self.ctx, args).await {
+                tracing::error!("Failed to queue rlog parser: {}", e);
+            }
+        } else if args.file.ends_with("camera.hevc") {
+            if let Err(e) = CameraRemuxWorker::perform_later(&self.ctx, args).await {
+                tracing::error!("Failed to queue camera remux: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LogSegmentWorker {
+    async fn process(&self, args: &LogSegmentWorkerArgs) -> worker::Result<JobOutcome> {
         let start_time = Instant::now();
-        tracing::trace!("Starting QlogParser for URL: {}", args.internal_file_url);
-        let client = self.client.clone();
+        tracing::trace!("Starting QlogParser for key: {}", args.file_key);
         let api_endpoint: String = env::var("API_ENDPOINT").expect("API_ENDPOINT env variable not set");
         // check if the device is in the database
         let _device_model = match devices::Model::find_device(&self.ctx.db, &args.dongle_id).await {
             Ok(device) => device,
             Err(e) => {
                 tracing::info!("Recieved file from an unregistered device. {} or DB Error: {}", &args.dongle_id, e.to_string());
-                return Ok(())
+                return Ok(JobOutcome::Skipped(format!("unregistered device {}: {}", args.dongle_id, e)));
             }
         };
 
         let canonical_route_name = format!("{}|{}", args.dongle_id, args.timestamp);
-        let key = super::log_helpers::calculate_advisory_lock_key(&canonical_route_name);
-        lock_manager.acquire_advisory_lock(&self.ctx.db, key).await
-            .map_err(|e| sidekiq::Error::Message(format!("Failed to aquire advisory lock: {}", e)))?; // blocks here until lock aquired
-
-        let route_model = match routes::Model::find_route(&self.ctx.db,  &canonical_route_name).await {
+        // Uploads of the same route race to create it, the insert ignores the conflict and
+        // everyone reads back the same row.
+        let default_route_model = routes::Model {
+            fullname: canonical_route_name.clone(),
+            device_dongle_id: args.dongle_id.clone(),
+            url: format!("{api_endpoint}/connectdata/{}/{}_{}",
+                args.dongle_id,
+                args.dongle_id,
+                args.timestamp),
+            ..Default::default()
+        };
+        let route_model = match default_route_model.find_or_add_route(&self.ctx.db).await {
             Ok(route) => route,
             Err(e) => {
-                tracing::trace!("Recieved file for a new route. Adding to DB: {} or Db Error: {}", &canonical_route_name, e);
-                let default_route_model = routes::Model {
-                    fullname: format!("{}|{}", args.dongle_id, args.timestamp),
-                    device_dongle_id: args.dongle_id.clone(),
-                    url: format!("{api_endpoint}/connectdata/{}/{}_{}",
-                        args.dongle_id,
-                        args.dongle_id,
-                        args.timestamp),
-                    ..Default::default()
-                };
-                match default_route_model.add_route_self(&self.ctx.db).await {
-                    Ok(route) => route,
-                    Err(e) => {
-                        tracing::error!("Failed to add the default route: {} with Error: {}", &canonical_route_name, e.to_string());
-                        match routes::Model::find_route(&self.ctx.db, &canonical_route_name).await {
-                            Ok(route) => {
-                                tracing::error!("But it was added in a separate thread!");
-                                route
-                            }
-                            Err(_) => return Err(sidekiq::Error::Message("Failed to add the default route: ".to_string() + &e.to_string())),
-                        }
-                    }
-                }
+                tracing::error!("Failed to add the default route: {} with Error: {}", &canonical_route_name, e.to_string());
+                return Err(sidekiq::Error::Message("Failed to add the default route: ".to_string() + &e.to_string()));
             }
         };
-        self.lock_manager.release_advisory_lock(&self.ctx.db, key).await
-            .map_err(|e| sidekiq::Error::Message(format!("Failed to release advisory lock: {}", e)))?;
-
 
         let canonical_name = format!("{}|{}--{}", args.dongle_id, args.timestamp, args.segment);
-        let key = super::log_helpers::calculate_advisory_lock_key(&canonical_name);
-        self.lock_manager.acquire_advisory_lock(&self.ctx.db, key).await
-            .map_err(|e| sidekiq::Error::Message(format!("Failed to aquire advisory lock: {}", e)))?; // blocks here until lock aquired
-        let segment = match segments::Model::find_one(&self.ctx.db, &canonical_name).await {
-            Ok(segment) => segment, // The segment was added previously so here is the row.
-            Err(e) => {  // Need to add the segment now.
-                tracing::trace!("Received file for a new segment. Adding to DB: {} or DB Error: {}", &canonical_name, e);
-                let default_segment_model = segments::Model {
-                    canonical_name: canonical_name.clone(),
-                    canonical_route_name: canonical_route_name.clone(),
-                    number: args.segment.parse::<i16>().unwrap_or(0),
-                    ..Default::default()
-                };
-                match default_segment_model.add_segment_self(&self.ctx.db).await {
-                    Ok(segment) => segment, // The segment was added and here is the row.
-                    Err(e) => {
-                        tracing::trace!("Failed to add the default segment {}: {}", &canonical_name, e);
-                        match segments::Model::find_one(&self.ctx.db, &canonical_name).await {
-                            Ok(segment) => {
-                                tracing::trace!("But it was added in a separate thread!");
-                                segment
-                            }
-                            Err(_) => return Err(sidekiq::Error::Message("Failed to add the default segment: ".to_string() + &e.to_string())),
-                        }
-                    }
-                }
+        let default_segment_model = segments::Model {
+            canonical_name: canonical_name.clone(),
+            canonical_route_name: canonical_route_name.clone(),
+            number: args.segment.parse::<i16>().unwrap_or(0),
+            ..Default::default()
+        };
+        let segment = match default_segment_model.find_or_add_segment(&self.ctx.db).await {
+            Ok(segment) => segment,
+            Err(e) => {
+                tracing::error!("Failed to add the default segment {}: {}", &canonical_name, e);
+                return Err(sidekiq::Error::Message("Failed to add the default segment: ".to_string() + &e.to_string()));
             }
         };
 
         // Make sure we have the data in the key value store. Maybe not needed later
-        let response = match client.get(&args.internal_file_url).send().await {
-            Ok(response) => {
-                let status = response.status();
-                tracing::trace!("Got Ok response with status {status}");
-                if !status.is_success() {
-                    return Ok(());
-                }
-                response
+        let response = match storage::blob_store().get(storage::key_from_url(&args.file_key)).await {
+            Ok(object) => object.stream,
+            Err(storage::StorageError::NotFound(key)) => {
+                tracing::trace!("{key} is not in storage");
+                return Ok(JobOutcome::Skipped(format!("{key} is not in storage")));
             }
             Err(e) => {
                 tracing::error!("GET request failed: {}", format!("{}", e));
@@ -254,8 +288,11 @@ impl worker::Worker<LogSegmentWorkerArgs> for LogSegmentWorker {
                 
             }
             "qlog.bz2" | "qlog.zst" =>  {
-                    qlog_result = match handle_qlog(&mut seg, response, &args, &self.ctx, &client).await {
-                        Ok(qlog_result) => Some(qlog_result),
+                    qlog_result = match handle_qlog(&mut seg, response, args, &self.ctx).await {
+                        Ok(qlog_result) => {
+                            seg.parser_version = ActiveValue::Set(PARSER_VERSION);
+                            Some(qlog_result)
+                        }
                         Err(e) => {
                             tracing::error!("Failed to handle qlog: {}", &e.to_string());
                             return Err(sidekiq::Error::Message("Failed to handle qlog: ".to_string() + &e.to_string()))
@@ -273,30 +310,37 @@ impl worker::Worker<LogSegmentWorkerArgs> for LogSegmentWorker {
                     args.segment,
                     args.file));
             }
+            // The durations are filled in by the CameraRemuxWorker once the HLS version exists
             "fcamera.hevc" =>   seg.fcam_url = ActiveValue::Set(
-                format!("{api_endpoint}/connectdata/fcam/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file)
+                format!("{api_endpoint}/connectdata/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file)
             ),
             "dcamera.hevc" =>   seg.dcam_url = ActiveValue::Set(
-                format!("{api_endpoint}/connectdata/dcam/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file)
+                format!("{api_endpoint}/connectdata/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file)
             ),
             "ecamera.hevc" =>   seg.ecam_url = ActiveValue::Set(
-                format!("{api_endpoint}/connectdata/ecam/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file)
+                format!("{api_endpoint}/connectdata/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file)
             ),
             f => {
                 tracing::error!("Got invalid file type: {}", f);
                 //ignore_uploads = Some(true);
-                return Ok(())
+                return Ok(JobOutcome::Skipped(format!("invalid file type {f}")))
             } // TODO: Mark for immediate deletion and block this user
         }
         //let seg_active_model = seg.into_active_model();
         match seg.update(&self.ctx.db).await {
             Ok(_) => (),
             Err(e) => {
-                tracing::error!("Failed to update segment: {}. DB Error: {}", &args.internal_file_url, e.to_string());
+                tracing::error!("Failed to update segment: {}. DB Error: {}", &args.file_key, e.to_string());
                 return Err(sidekiq::Error::Message(e.to_string()));
             }
         }
-        let segment_models = match segments::Model::find_segments_by_route(&self.ctx.db, &route_model.fullname).await {
+        // Segments of a route finish in any order. The route summary is read and written under
+        // the route lock, on the lock's own transaction, so the last one to write has seen every
+        // segment before it.
+        let key = super::log_helpers::calculate_advisory_lock_key(&canonical_route_name);
+        let route_lock = self.lock_manager.acquire_advisory_lock(&self.ctx.db, key).await
+            .map_err(|e| sidekiq::Error::Message(format!("Failed to aquire advisory lock: {}", e)))?; // blocks here until lock aquired
+        let segment_models = match segments::Model::find_segments_by_route(route_lock.txn(), &route_model.fullname).await {
             Ok(segments) => {
                 //segments.retain(|segment| segment.qlog_url != ""); // exclude ones wher the qlog is missing
                 segments
@@ -329,20 +373,20 @@ impl worker::Worker<LogSegmentWorkerArgs> for LogSegmentWorker {
         update_route_info(&self.ctx, &mut active_route_model, &segment_models).await?;
         //update_device_info(&self.ctx, &mut active_device_model, &active_route_model, &ignore_uploads).await?;
 
-        match active_route_model.update(&self.ctx.db).await {
+        match active_route_model.update(route_lock.txn()).await {
             Ok(_) => (),
             Err(e) => {
                 tracing::error!("Failed to update active route model. DB Error {}", e.to_string());
                 return Err(sidekiq::Error::Message(e.to_string()));
             }
         }
-        self.lock_manager.release_advisory_lock(&self.ctx.db, key).await
+        route_lock.release().await
             .map_err(|e| sidekiq::Error::Message(format!("Failed to release advisory lock: {}", e))
         )?;
 
         //active_device_model.update(&self.ctx.db).await.map_err(|e| sidekiq::Error::Message(e.to_string()))?;
-        tracing::info!("Completed unlogging: {} in {:?}", args.internal_file_url, start_time.elapsed());
-        return Ok(())
+        tracing::info!("Completed unlogging: {} in {:?}", args.file_key, start_time.elapsed());
+        Ok(JobOutcome::Done)
     }
 }
 
@@ -449,40 +493,54 @@ async fn update_route_info(
     }
 
     active_route_model.length = ActiveValue::Set(miles);
+    active_route_model.engaged_millis = ActiveValue::Set(segment_models.iter().map(|s| s.engaged_millis).sum());
+    active_route_model.engaged_miles = ActiveValue::Set(segment_models.iter().map(|s| s.engaged_miles).sum());
+    active_route_model.disengagements = ActiveValue::Set(segment_models.iter().map(|s| s.disengagements).sum());
+    active_route_model.overrides = ActiveValue::Set(segment_models.iter().map(|s| s.overrides).sum());
+    active_route_model.has_user_flag = ActiveValue::Set(segment_models.iter().any(|s| s.has_user_flag));
     active_route_model.segment_start_times = ActiveValue::Set(segment_start_times.into());
     active_route_model.segment_end_times = ActiveValue::Set(segment_end_times.into());
     active_route_model.segment_numbers = ActiveValue::Set(segment_numbers.into());
+
+    // Name the ends of the route. Without gps the coordinates are just zeros
+    if gps_seen {
+        if let (ActiveValue::Set(lat), ActiveValue::Set(lng)) = (&active_route_model.start_lat, &active_route_model.start_lng) {
+            active_route_model.start_place = ActiveValue::Set(geocode::place_name(*lat, *lng));
+        }
+        if let (ActiveValue::Set(lat), ActiveValue::Set(lng)) = (&active_route_model.end_lat, &active_route_model.end_lng) {
+            active_route_model.end_place = ActiveValue::Set(geocode::place_name(*lat, *lng));
+        }
+    }
     return Ok(());
 }
 
 async fn handle_qlog(
     seg: &mut segments::ActiveModel,
-    response: Response,
+    response: ByteStream,
     args: &LogSegmentWorkerArgs,
     ctx: &AppContext,
-    client: &Client
 ) -> worker::Result<QLogResult> {
-    let bytes_stream = response
-        .bytes_stream()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)
-    );
-    let stream_reader = StreamReader::new(bytes_stream);
-    let mut decoder: std::pin::Pin<Box<dyn tokio::io::AsyncRead + Send>> = if args.file.ends_with(".bz2") {
-        Box::pin(bufread::BzDecoder::new(stream_reader))
-    } else if args.file.ends_with(".zst") {
-        Box::pin(bufread::ZstdDecoder::new(stream_reader))
-    } else {
-        return Err(sidekiq::Error::Message("Invalid file type".to_string()));
-    };
-
-    let mut decompressed_data = Vec::new();
+    let reader = LogReader::new(response, &args.file)
+        .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+    Ok(parse_log(seg, reader, args, ctx, LogKind::Qlog).await?)
+}
 
-    match decoder.read_to_end(&mut decompressed_data).await {
-        Ok(_)=> (),
-        Err(e) => return Err(sidekiq::Error::Message(e.to_string()))
-    };
+/// Which log of a segment is being parsed. The qlog is always there and everything shown
+/// for a route comes from it. An rlog, when the user asked for one, gets its higher rate
+/// coords and events written next to the qlog ones as `rlog_coords.json`/`rlog_events.json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogKind {
+    Qlog,
+    Rlog,
+}
 
-    Ok(parse_qlog(&client, seg, decompressed_data, args, ctx).await?)
+impl LogKind {
+    fn derived_prefix(self) -> &'static str {
+        match self {
+            LogKind::Qlog => "",
+            LogKind::Rlog => "rlog_",
+        }
+    }
 }
 
 
@@ -517,43 +575,182 @@ struct JsonEvent {
     data: StateData,
 }
 
-async fn parse_qlog(
-    client: &Client,
+fn unlog(unlog_data: &mut Option<BlobWriter>, event: &LogEvent::Reader<'_>) {
+    if let Some(unlog_data) = unlog_data {
+        writeln!(unlog_data, "{:#?}", event).ok();
+    }
+}
+
+/// An `alert` entry of events.json, from controlsState or selfdriveState.
+fn alert_event(
+    log_mono_time: u64,
+    onroad_mono_time: u64,
+    alert_type: &str,
+    alert_text1: String,
+    alert_text2: String,
+    alert_status: u16,
+    alert_size: u16,
+) -> serde_json::Value {
+    serde_json::json!({
+        "type": "alert",
+        "time": log_mono_time,
+        "route_offset_millis": log_mono_time.saturating_sub(onroad_mono_time) / 1000000,
+        "data": {
+            "alertType": alert_type,
+            "alertText1": alert_text1,
+            "alertText2": alert_text2,
+            "alertStatus": alert_status,
+            "alertSize": alert_size,
+        }
+    })
+}
+
+/// How much of a segment openpilot drove. This goes by controlsState, which the qlog has
+/// at 10Hz. The onroad events only mark the moments the state changes, so on their own they
+/// can't tell whether a segment that starts in the middle of a drive is engaged.
+#[derive(Debug, Default)]
+struct EngagementStats {
+    engaged_nanos: u64,
+    engaged_meters: f64,
+    disengagements: i32,
+    overrides: i32,
+    engaged: bool,
+    overriding: bool,
+    last_mono_time: Option<u64>,
+}
+
+impl EngagementStats {
+    fn update(&mut self, log_mono_time: u64, engaged: bool, overriding: bool) {
+        if let Some(last_mono_time) = self.last_mono_time {
+            if self.engaged {
+                self.engaged_nanos += log_mono_time.saturating_sub(last_mono_time);
+            }
+            // Only edges inside the segment count, one that starts engaged or overriding
+            // is carrying on from the previous segment
+            if self.engaged && !engaged {
+                self.disengagements += 1;
+            }
+            if engaged && overriding && !self.overriding {
+                self.overrides += 1;
+            }
+        }
+        self.engaged = engaged;
+        self.overriding = overriding;
+        self.last_mono_time = Some(self.last_mono_time.map_or(log_mono_time, |last| last.max(log_mono_time)));
+    }
+
+    fn add_distance(&mut self, meters: f64) {
+        if self.engaged {
+            self.engaged_meters += meters;
+        }
+    }
+}
+
+/// coords.json and events.json are JSON arrays, written out an element at a time.
+struct JsonArrayWriter {
+    writer: BlobWriter,
+    empty: bool,
+}
+
+impl JsonArrayWriter {
+    fn create(key: &str) -> Self {
+        let mut writer = BlobWriter::create(key);
+        writer.write_all(b"[").ok();
+        Self { writer, empty: true }
+    }
+
+    fn push(&mut self, value: &serde_json::Value) {
+        if !self.empty {
+            self.writer.write_all(b",").ok();
+        }
+        serde_json::to_writer(&mut self.writer, value).ok();
+        self.empty = false;
+    }
+
+    fn finish(mut self) -> BlobWriter {
+        self.writer.write_all(b"]").ok();
+        self.writer
+    }
+}
+
+pub(crate) async fn parse_log(
     seg: &mut segments::ActiveModel,
-    decompressed_data: Vec<u8>,
+    mut reader: LogReader,
     args: &LogSegmentWorkerArgs,
-    _ctx: &AppContext
+    ctx: &AppContext,
+    kind: LogKind,
 ) -> worker::Result<QLogResult> {
-    let api_endpoint = env::var("API_ENDPOINT").expect("API_ENDPOINT env variable not set");
-    seg.ulog_url = ActiveValue::Set(
-                common::mkv_helpers::get_mkv_file_url(
-                    &format!("{}_{}--{}--{}",
+    if kind == LogKind::Qlog {
+        let api_endpoint = env::var("API_ENDPOINT").expect("API_ENDPOINT env variable not set");
+        seg.ulog_url = ActiveValue::Set(
+                    format!("{}_{}--{}--{}",
                         args.dongle_id,
                         args.timestamp,
                         args.segment,
                         args.file
                     )
-            )
-        );
-    seg.qlog_url = ActiveValue::Set(format!("{api_endpoint}/connectdata/qlog/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file));
+            );
+        seg.qlog_url = ActiveValue::Set(format!("{api_endpoint}/connectdata/qlog/{}/{}/{}/{}", args.dongle_id, args.timestamp, args.segment, args.file));
+    }
 
-    let mut unlog_data = Vec::new();
-    let mut cursor = Cursor::new(decompressed_data);
+    // A retry, or a reparse, finds what an earlier attempt wrote. Keys are write-once, so
+    // the old derived files go first.
+    if let Err(e) = delete_derived(&ctx.db, &storage::key_from_url(&args.file_key)).await {
+        return Err(sidekiq::Error::Message(format!("Failed to delete derived files of {}: {e}", args.file_key)));
+    }
+    let prefix = kind.derived_prefix();
+    let coords_key = format!("{}_{}--{}--{prefix}coords.json", args.dongle_id, args.timestamp, args.segment);
+    let events_key = format!("{}_{}--{}--{prefix}events.json", args.dongle_id, args.timestamp, args.segment);
+    let mut coordinates = JsonArrayWriter::create(&coords_key);
+    let mut events = JsonArrayWriter::create(&events_key);
+    // The rlog has every message at full rate, dumping all of it as text isn't worth the storage
+    let mut unlog_data = (kind == LogKind::Qlog).then(|| BlobWriter::create(
+        &storage::key_from_url(&args.file_key)
+            .replace(".bz2", ".unlog")
+            .replace(".zst", ".unlog"),
+    ));
+    let mut unlog_jsonl = (kind == LogKind::Qlog).then(|| BlobWriter::create(
+        &storage::key_from_url(&args.file_key)
+            .replace(".bz2", ".jsonl")
+            .replace(".zst", ".jsonl"),
+    ));
     let mut onroad_mono_time: Option<u64> = None;
     let mut gps_seen = false;
     let mut thumbnails: Vec<Vec<u8>> = Vec::new();
     let mut total_meters_traveled = 0.0; // gets converted to miles
     let mut last_lat = None;
     let mut last_lng = None;
-    let mut coordinates: Vec<serde_json::Value> = Vec::new();
-    let mut events: Vec<serde_json::Value> = Vec::new();
+    let mut last_route_time = 0;
+    let mut engagement = EngagementStats::default();
+    let mut last_alert_type = String::new();
+    let mut last_selfdrive_alert_type = String::new();
     let mut qlog_result = QLogResult{..Default::default()};
 
-    while let Ok(message_reader) = capnp::serialize::read_message(&mut cursor, ReaderOptions::default()) {
+    loop {
+        // Nothing decoded is alive here, the previous message went out of scope with the last iteration
+        coordinates.writer.flush_chunks().await;
+        events.writer.flush_chunks().await;
+        if let Some(unlog_data) = &mut unlog_data {
+            unlog_data.flush_chunks().await;
+        }
+        if let Some(unlog_jsonl) = &mut unlog_jsonl {
+            unlog_jsonl.flush_chunks().await;
+        }
+        let message_reader = match reader.next_message().await {
+            Ok(Some(message_reader)) => message_reader,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Stopped reading {} early: {}", args.file_key, e);
+                break;
+            }
+        };
         let event = match message_reader.get_root::<LogEvent::Reader>() {
             Ok(event) => event,
             Err(e) => {tracing::warn!("Failed to get root: {:?}", e); continue}, // Skip parsing if we can't get the root
         };
+        if let Some(unlog_jsonl) = &mut unlog_jsonl {
+            log_json::write_event(unlog_jsonl, event).ok();
+        }
 
         match event.which() {
             Err(_e) => {
@@ -582,16 +779,18 @@ async fn parse_qlog(
                                 if let (Some(last_lat), Some(last_lng)) = (last_lat, last_lng) {
                                     let meters = super::log_helpers::haversine_distance(last_lat, last_lng, lat, lng);
                                     total_meters_traveled += meters;
+                                    engagement.add_distance(meters);
         
                                     if let Some(onroad_mono_time) = onroad_mono_time{
                                         let route_time = (log_mono_time - onroad_mono_time) / 1000000000; // time since the start of route
-                                        coordinates.push(serde_json::json!({
+                                        coordinates.push(&serde_json::json!({
                                             "t": route_time,
                                             "lat": lat,
                                             "lng": lng,
                                             "speed": speed,
                                             "dist": meters,
                                         }));
+                                        last_route_time = route_time;
                                     }
                                 }
         
@@ -608,7 +807,7 @@ async fn parse_qlog(
                                 seg.end_time_utc_millis = ActiveValue::Set(gps_ts);
                             }
                         }
-                        writeln!(unlog_data, "{:#?}", event).ok();
+                        unlog(&mut unlog_data, &event);
                     }
                     LogEvent::DeviceState(device_state) => {
                         if let Ok(device_state) = device_state {
@@ -617,12 +816,13 @@ async fn parse_qlog(
                                 onroad_mono_time = Some(device_state.get_started_mono_time());
                             }
                         }
-                        writeln!(unlog_data, "{:#?}", event).ok();
+                        unlog(&mut unlog_data, &event);
                     }
                     LogEvent::Thumbnail(thumbnail) => {
                         // take the jpg and add it to the array of the other jpgs.
                         // after we get all the jpgs, put them together into a 1x12 jpg and downscale to 1280x96
-                        if let Ok(thumbnail) = thumbnail {
+                        // the qlog carries the same thumbnails, so the rlog doesn't redo the sprite
+                        if let (Ok(thumbnail), LogKind::Qlog) = (thumbnail, kind) {
                             // Assuming the thumbnail data is a JPEG image
                             let image_data = thumbnail.get_thumbnail().map_err(Box::from)?; // len is 9682
                             //let img = image::load_from_memory(image_data).map_err(Box::from)?; // len is 436692
@@ -634,7 +834,7 @@ async fn parse_qlog(
                             .ok()
                             .and_then(|params| params.get_car_fingerprint().ok())
                             .map_or_else(String::new, |fp| fp.to_string().unwrap_or_default());
-                        writeln!(unlog_data, "{:#?}", event).ok();
+                        unlog(&mut unlog_data, &event);
                     }
                     LogEvent::InitData(init_data) => {
                         if let Ok(init_data) = init_data {
@@ -658,14 +858,15 @@ async fn parse_qlog(
                             let params = init_data
                                 .get_params().ok();
 
-                            if let Some(params) = params {
+                            // counted once per segment, from the qlog
+                            if let (Some(params), LogKind::Qlog) = (params, kind) {
                                 if let Some(entries) = params.get_entries().ok() {
                                     handle_device_params(&args.dongle_id, entries);
                                 }
                             }
                         }
         
-                        writeln!(unlog_data, "{:#?}", event).ok();
+                        unlog(&mut unlog_data, &event);
                     }
                     LogEvent::OnroadEvents(onroad_event) => {
                         if let Ok(onroad_event) = onroad_event {
@@ -705,7 +906,7 @@ async fn parse_qlog(
 
                                 if let Some(onroad_mono_time) = onroad_mono_time{
                                     events.push(
-                                        serde_json::json!({
+                                        &serde_json::json!({
                                             "type": "state",
                                             "time": log_mono_time,
                                             "route_offset_millis": (log_mono_time - onroad_mono_time) / 1000000,
@@ -719,22 +920,87 @@ async fn parse_qlog(
                                 }
                             }
                         }
-                        writeln!(unlog_data, "{:#?}", event).ok();
+                        unlog(&mut unlog_data, &event);
+                    }
+                    LogEvent::ControlsState(controls_state) => {
+                        if let Ok(controls_state) = controls_state {
+                            let overriding = matches!(
+                                controls_state.get_state(),
+                                Ok(log_capnp::controls_state::OpenpilotState::Overriding)
+                            );
+                            engagement.update(log_mono_time, controls_state.get_enabled(), overriding);
+
+                            // controlsState repeats the current alert every frame, only note when it changes
+                            let alert_type = controls_state
+                                .get_alert_type().ok()
+                                .map_or_else(String::new, |t| t.to_string().unwrap_or_default());
+                            if alert_type != last_alert_type {
+                                if let (false, Some(onroad_mono_time)) = (alert_type.is_empty(), onroad_mono_time) {
+                                    events.push(&alert_event(
+                                        log_mono_time,
+                                        onroad_mono_time,
+                                        &alert_type,
+                                        controls_state.get_alert_text1().ok().map_or_else(String::new, |t| t.to_string().unwrap_or_default()),
+                                        controls_state.get_alert_text2().ok().map_or_else(String::new, |t| t.to_string().unwrap_or_default()),
+                                        controls_state.get_alert_status().map_or(0, |status| status as u16),
+                                        controls_state.get_alert_size().map_or(0, |size| size as u16),
+                                    ));
+                                }
+                                last_alert_type = alert_type;
+                            }
+                        }
+                    }
+                    // Newer openpilot moved the alerts out of controlsState
+                    LogEvent::SelfdriveState(selfdrive_state) => {
+                        if let Ok(selfdrive_state) = selfdrive_state {
+                            let alert_type = selfdrive_state
+                                .get_alert_type().ok()
+                                .map_or_else(String::new, |t| t.to_string().unwrap_or_default());
+                            if alert_type != last_selfdrive_alert_type {
+                                if let (false, Some(onroad_mono_time)) = (alert_type.is_empty(), onroad_mono_time) {
+                                    events.push(&alert_event(
+                                        log_mono_time,
+                                        onroad_mono_time,
+                                        &alert_type,
+                                        selfdrive_state.get_alert_text1().ok().map_or_else(String::new, |t| t.to_string().unwrap_or_default()),
+                                        selfdrive_state.get_alert_text2().ok().map_or_else(String::new, |t| t.to_string().unwrap_or_default()),
+                                        selfdrive_state.get_alert_status().map_or(0, |status| status as u16),
+                                        selfdrive_state.get_alert_size().map_or(0, |size| size as u16),
+                                    ));
+                                }
+                                last_selfdrive_alert_type = alert_type;
+                            }
+                        }
+                    }
+                    // The bookmark button logs `bookmark` on newer openpilot
+                    LogEvent::UserFlag(_) | LogEvent::Bookmark(_) => {
+                        seg.has_user_flag = ActiveValue::Set(true);
+                        if let Some(onroad_mono_time) = onroad_mono_time {
+                            events.push(
+                                &serde_json::json!({
+                                    "type": "user_flag",
+                                    "time": log_mono_time,
+                                    "route_offset_millis": log_mono_time.saturating_sub(onroad_mono_time) / 1000000,
+                                    "data": {}
+                                })
+                            )
+                        }
+                        unlog(&mut unlog_data, &event);
                     }
                     LogEvent::Can(_) => {
                         seg.can = ActiveValue::Set(true);
-                        writeln!(unlog_data, "{:#?}", event).ok();
+                        unlog(&mut unlog_data, &event);
                     },
-                    LogEvent::PandaStates(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::Sendcan(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::ErrorLogMessage(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::LogMessage(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::LiveParameters(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::LiveTorqueParameters(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::ManagerState(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::NavInstruction(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::UploaderState(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
-                    LogEvent::QcomGnss(_) => {writeln!(unlog_data, "{:#?}", event).ok();},
+                    LogEvent::PandaStates(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::Sendcan(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::ErrorLogMessage(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::LogMessage(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::LiveParameters(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::LiveTorqueParameters(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::ManagerState(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::NavInstruction(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::UploaderState(_) => unlog(&mut unlog_data, &event),
+                    LogEvent::QcomGnss(_) => unlog(&mut unlog_data, &event),
                     _ => continue, //writeln!(writer, "{:#?}", event).map_err(Box::from)?, // unlog everything?
                 }
             }
@@ -746,31 +1012,35 @@ async fn parse_qlog(
         seg.end_lng = ActiveValue::Set(last_lng);
         seg.miles = ActiveValue::Set((total_meters_traveled*0.000621371) as f32);
     }
-
-    let coords_url = common::mkv_helpers::get_mkv_file_url(
-        &format!("{}_{}--{}--coords.json", args.dongle_id, args.timestamp, args.segment)
-    );
-
-    let events_url = common::mkv_helpers::get_mkv_file_url(
-        &format!("{}_{}--{}--events.json", args.dongle_id, args.timestamp, args.segment)
-    );
-    
-    upload_data(client, &coords_url, serde_json::to_vec(&coordinates).unwrap_or_default()).await;
-    upload_data(client, &events_url, serde_json::to_vec(&events).unwrap_or_default()).await;
-    upload_data(&client, 
-        &args.internal_file_url
-            .replace(".bz2", ".unlog")
-            .replace(".zst", ".unlog"),
-        unlog_data
-    ).await;
+    seg.engaged_millis = ActiveValue::Set((engagement.engaged_nanos / 1_000_000) as i64);
+    seg.engaged_miles = ActiveValue::Set((engagement.engaged_meters*0.000621371) as f32);
+    seg.disengagements = ActiveValue::Set(engagement.disengagements);
+    seg.overrides = ActiveValue::Set(engagement.overrides);
+
+    // All of them get a chance to finish before the first failure is handed back
+    let mut uploads = vec![
+        finish_upload(&ctx.db, coordinates.finish()).await,
+        finish_upload(&ctx.db, events.finish()).await,
+    ];
+    if let Some(unlog_data) = unlog_data {
+        uploads.push(finish_upload(&ctx.db, unlog_data).await);
+    }
+    if let Some(unlog_jsonl) = unlog_jsonl {
+        uploads.push(finish_upload(&ctx.db, unlog_jsonl).await);
+    }
+    uploads.into_iter().collect::<worker::Result<Vec<()>>>()?;
 
     let img_proc_start = Instant::now();
     if !thumbnails.is_empty() {
         // Downscale each thumbnail in parallel
+        // A thumbnail that doesn't decode is left out of the sprite rather than failing the segment
         let downscaled_thumbnails: Vec<ImageBuffer<Rgba<u8>, Vec<u8>>> = thumbnails.par_iter()
-            .map(|image_data| {
-                let img = image::load_from_memory(image_data).expect("Failed to load image");
-                img.resize_exact(1536 / 12, 80, image::imageops::FilterType::Lanczos3).to_rgba8()
+            .filter_map(|image_data| match image::load_from_memory(image_data) {
+                Ok(img) => Some(img.resize_exact(1536 / 12, 80, image::imageops::FilterType::Lanczos3).to_rgba8()),
+                Err(e) => {
+                    tracing::warn!("Failed to decode a thumbnail of {}: {}", args.file_key, e);
+                    None
+                }
             })
             .collect();
 
@@ -800,43 +1070,56 @@ async fn parse_qlog(
             img_bytes
         };
 
-        let sprite_url = common::mkv_helpers::get_mkv_file_url(
-            &format!("{}_{}--{}--sprite.jpg", args.dongle_id, args.timestamp, args.segment)
-        );
+        let sprite_key = format!("{}_{}--{}--sprite.jpg", args.dongle_id, args.timestamp, args.segment);
         tracing::trace!("Image proc took: {:?}", img_proc_start.elapsed());
-        upload_data(client, &sprite_url, img_bytes).await;
+        if !downscaled_thumbnails.is_empty() {
+            upload_data(&ctx.db, &sprite_key, img_bytes).await?;
+        }
     }
 
-    qlog_result.total_time = coordinates
-        .last()
-        .and_then(|last_coordinate| last_coordinate.get("t"))
-        .and_then(|t| t.as_i64())  // Convert to i64 if it's a number
-        .unwrap_or(0);  // Default to 0 in case of any error
+    qlog_result.total_time = last_route_time as i64;
 
     Ok(qlog_result)
 }
 
-async fn upload_data(client: &Client, url: &str, body: Vec<u8>) {
-    match client.put(url).body(body).send().await {
-        Ok(response) => {
-            let status = response.status();
-            if status.is_success() {
-                tracing::trace!("Uploaded data to {} with status {}", url, status);
-            } else {
-                tracing::error!("Failed to upload data to {} with status {}", url, status);
+async fn finish_upload(db: &DatabaseConnection, writer: BlobWriter) -> worker::Result<()> {
+    let key = writer.key().to_string();
+    match writer.finish().await {
+        Ok(size) => {
+            tracing::trace!("Uploaded data to {}", key);
+            if let Err(e) = SOM::track(db, &key, size as i64).await {
+                tracing::error!("Failed to track storage for {}: {}", key, e);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!("Failed to upload data to {}: {}", key, e);
+            Err(sidekiq::Error::Message(format!("Failed to upload data to {key}: {e}")))
+        }
+    }
+}
+
+async fn upload_data(db: &DatabaseConnection, key: &str, body: Vec<u8>) -> worker::Result<()> {
+    let size = body.len() as i64;
+    match storage::blob_store().put_bytes(key, body).await {
+        Ok(()) => {
+            tracing::trace!("Uploaded data to {}", key);
+            if let Err(e) = SOM::track(db, key, size).await {
+                tracing::error!("Failed to track storage for {}: {}", key, e);
             }
+            Ok(())
         }
         Err(e) => {
-            tracing::error!("Request to {} failed: {}", url, e);
+            tracing::error!("Failed to upload data to {}: {}", key, e);
+            Err(sidekiq::Error::Message(format!("Failed to upload data to {key}: {e}")))
         }
     }
 }
 
-async fn get_qcam_duration(response: Response) -> Result<f32, FfmpegError> {
+async fn get_qcam_duration(mut stream: ByteStream) -> Result<f32, FfmpegError> {
     // Create a temporary file to store the video data
     let temp_file: NamedTempFile = NamedTempFile::new().unwrap();
     let mut temp_file_async = tokio::fs::File::from_std(temp_file.reopen().unwrap());
-    let mut stream = response.bytes_stream();
 
     while let Some(chunk) = stream.next().await {
         match chunk {
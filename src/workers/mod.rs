@@ -1,4 +1,9 @@
 pub mod log_parser;
-pub mod jpg_extractor;
+pub mod rlog_parser;
+pub mod reprocess;
+pub mod clip_renderer;
+pub mod camera_remuxer;
 pub mod bootlog_parser;
-pub mod log_helpers;
\ No newline at end of file
+pub mod crash_parser;
+pub mod log_helpers;
+pub mod job_tracker;
\ No newline at end of file
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use loco_rs::{prelude::*, worker::{AppWorker, Worker}};
+use serde::{Deserialize, Serialize};
+
+use super::log_parser::{LogSegmentWorker, LogSegmentWorkerArgs, PARSER_VERSION};
+use super::rlog_parser::RlogParserWorker;
+use crate::{
+    common::storage::{self, StorageError},
+    models::{
+        reprocess_jobs::{ReprocessScope, JOB_CANCELLED, RJM},
+        segments::SM,
+        storage_objects::SOM,
+    },
+};
+
+/// Segments claimed at a time. A worker parses them one after the other, so this and the
+/// pause between batches are what keep reprocessing from crowding out new uploads.
+const BATCH_SIZE: u64 = 10;
+const BATCH_PAUSE: Duration = Duration::from_secs(30);
+
+/// The files the workers derive from an uploaded log, which have to go before it is parsed
+/// again since storage keys can't be overwritten.
+pub fn derived_keys(log_key: &str) -> Vec<String> {
+    let Some((prefix, file)) = log_key.rsplit_once("--") else {
+        return vec![];
+    };
+    let derived: &[&str] = if file.starts_with("qlog.") {
+        &["qlog.unlog", "qlog.jsonl", "sprite.jpg", "coords.json", "events.json"]
+    } else if file.starts_with("rlog.") {
+        &["rlog_coords.json", "rlog_events.json"]
+    } else {
+        &[]
+    };
+    derived.iter().map(|file| format!("{prefix}--{file}")).collect()
+}
+
+pub async fn delete_derived(db: &DatabaseConnection, log_key: &str) -> Result<(), StorageError> {
+    let store = storage::blob_store();
+    for key in derived_keys(log_key) {
+        tracing::trace!("Deleting: {key}");
+        match store.delete(&key).await {
+            Ok(()) => {
+                SOM::untrack(db, &key).await.ok();
+            }
+            Err(StorageError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Parse a segment's logs again in place, the qlog through the `LogSegmentWorker` like a new
+/// upload and the rlog, if there is one, through the `RlogParserWorker`.
+pub async fn reprocess_segment(ctx: &AppContext, segment: &SM) -> Result<(), String> {
+    let Some((dongle_id, timestamp)) = segment.canonical_route_name.split_once('|') else {
+        return Err(format!("bad route name {}", segment.canonical_route_name));
+    };
+    // ulog_url is the storage key of the qlog
+    let Some((_, qlog_file)) = segment.ulog_url.rsplit_once("--") else {
+        return Err("no qlog".to_string());
+    };
+    let rlog_file = segment.rlog_url.rsplit('/').next().filter(|file| !file.is_empty());
+    let args = |file: &str| LogSegmentWorkerArgs {
+        file_key: format!("{dongle_id}_{timestamp}--{}--{file}", segment.number),
+        dongle_id: dongle_id.to_string(),
+        timestamp: timestamp.to_string(),
+        segment: segment.number.to_string(),
+        file: file.to_string(),
+        create_time: chrono::Utc::now().timestamp(),
+    };
+
+    let qlog_args = args(qlog_file);
+    delete_derived(&ctx.db, &qlog_args.file_key).await.map_err(|e| e.to_string())?;
+    LogSegmentWorker::build(ctx)
+        .perform(qlog_args)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Some(rlog_file) = rlog_file {
+        let rlog_args = args(rlog_file);
+        delete_derived(&ctx.db, &rlog_args.file_key).await.map_err(|e| e.to_string())?;
+        RlogParserWorker::build(ctx)
+            .perform(rlog_args)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Works through the unfinished reprocess jobs a batch at a time until there are none left.
+/// It's queued when a job is added and when the server starts, which also starts a job for
+/// everything parsed by an older `PARSER_VERSION` once a new one is deployed.
+///
+/// Batches are claimed in the database, so any number of these can run at once on any
+/// instance without parsing a segment twice. A batch whose worker dies on the way isn't
+/// handed out again, its job counts those segments as failed once it has waited long enough.
+pub struct ReprocessWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Deserialize, Debug, Serialize, Default)]
+pub struct ReprocessWorkerArgs {}
+
+impl AppWorker<ReprocessWorkerArgs> for ReprocessWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+}
+
+#[async_trait]
+impl Worker<ReprocessWorkerArgs> for ReprocessWorker {
+    async fn perform(&self, _args: ReprocessWorkerArgs) -> worker::Result<()> {
+        if let Err(e) = start_version_job(&self.ctx.db).await {
+            tracing::error!("Failed to start reprocessing for parser version {PARSER_VERSION}: {e}");
+        }
+        loop {
+            let (job, segments) = match RJM::claim_batch(&self.ctx.db, BATCH_SIZE).await {
+                Ok(Some(batch)) => batch,
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(sidekiq::Error::Message(format!("Failed to claim a reprocess batch: {e}"))),
+            };
+            if segments.is_empty() {
+                tracing::info!("Reprocess job {} has no segments left to hand out", job.id);
+                continue;
+            }
+
+            let (mut processed, mut failed, mut last_error) = (0, 0, None);
+            for segment in &segments {
+                match reprocess_segment(&self.ctx, segment).await {
+                    Ok(()) => processed += 1,
+                    Err(e) => {
+                        tracing::error!("Failed to reprocess {}: {e}", segment.canonical_name);
+                        failed += 1;
+                        last_error = Some(format!("{}: {e}", segment.canonical_name));
+                    }
+                }
+            }
+            if let Err(e) = RJM::add_progress(&self.ctx.db, job.id, processed, failed, last_error.as_deref()).await {
+                tracing::error!("Failed to record progress of reprocess job {}: {e}", job.id);
+            }
+            tokio::time::sleep(BATCH_PAUSE).await;
+        }
+    }
+}
+
+/// Only once per version, so segments that fail to parse aren't retried forever. Instances
+/// starting together can both add the job, the newer one is cancelled again.
+async fn start_version_job(db: &DatabaseConnection) -> ModelResult<()> {
+    if RJM::find_version_job(db, PARSER_VERSION).await?.is_some() {
+        return Ok(());
+    }
+    let job = RJM::add_job(db, ReprocessScope { below_version: Some(PARSER_VERSION), ..Default::default() }).await?;
+    if RJM::find_version_job(db, PARSER_VERSION).await?.map_or(false, |first| first.id != job.id) {
+        RJM::set_status(db, job.id, JOB_CANCELLED).await?;
+        return Ok(());
+    }
+    tracing::info!("Reprocessing {} segments parsed before version {PARSER_VERSION}", job.total);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::sea_query::Expr;
+
+use super::log_parser::{parse_log, LogKind, LogSegmentWorkerArgs};
+use crate::{
+    common::{log_reader::LogReader, storage},
+    models::_entities::{routes, segments},
+};
+
+/// `proclog` of a segment whose rlog has been parsed, same as comma's "processed" file status
+const LOG_PROCESSED: i32 = 40;
+
+/// Derives the full rate coords and events of a segment from its rlog. Queued by the
+/// `LogSegmentWorker` once it has recorded the rlog, so it never holds up the qlogs.
+pub struct RlogParserWorker {
+    pub ctx: AppContext,
+}
+
+impl worker::AppWorker<LogSegmentWorkerArgs> for RlogParserWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+}
+
+#[async_trait]
+impl worker::Worker<LogSegmentWorkerArgs> for RlogParserWorker {
+    async fn perform(&self, args: LogSegmentWorkerArgs) -> worker::Result<()> {
+        let start_time = Instant::now();
+        tracing::trace!("Starting RlogParser for key: {}", args.file_key);
+        let response = match storage::blob_store().get(storage::key_from_url(&args.file_key)).await {
+            Ok(object) => object.stream,
+            Err(storage::StorageError::NotFound(key)) => {
+                tracing::trace!("{key} is not in storage");
+                return Ok(());
+            }
+            Err(e) => return Err(sidekiq::Error::Message(e.to_string())),
+        };
+        let reader = LogReader::new(response, &args.file)
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+
+        // Only the uploaded files come out of this, the segment row itself keeps what the qlog says
+        let mut parsed = segments::ActiveModel::default();
+        parse_log(&mut parsed, reader, &args, &self.ctx, LogKind::Rlog).await?;
+        let can = matches!(parsed.can, ActiveValue::Set(true));
+
+        let canonical_route_name = format!("{}|{}", args.dongle_id, args.timestamp);
+        let canonical_name = format!("{canonical_route_name}--{}", args.segment);
+        let number = args.segment.parse::<i32>().unwrap_or(0);
+
+        let mut segment_update = segments::Entity::update_many()
+            .col_expr(segments::Column::Proclog, Expr::value(LOG_PROCESSED))
+            .col_expr(segments::Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(segments::Column::CanonicalName.eq(&canonical_name));
+        if can {
+            segment_update = segment_update.col_expr(segments::Column::Can, Expr::value(true));
+        }
+        segment_update
+            .exec(&self.ctx.db)
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+
+        // Single statements so this doesn't race the LogSegmentWorker updating the same route
+        routes::Entity::update_many()
+            .col_expr(routes::Column::Proclog, Expr::value(number))
+            .filter(routes::Column::Fullname.eq(&canonical_route_name))
+            .filter(routes::Column::Proclog.lt(number))
+            .exec(&self.ctx.db)
+            .await
+            .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        if can {
+            routes::Entity::update_many()
+                .col_expr(routes::Column::Can, Expr::value(true))
+                .filter(routes::Column::Fullname.eq(&canonical_route_name))
+                .exec(&self.ctx.db)
+                .await
+                .map_err(|e| sidekiq::Error::Message(e.to_string()))?;
+        }
+
+        tracing::info!("Completed rlog parsing: {} in {:?}", args.file_key, start_time.elapsed());
+        Ok(())
+    }
+}